@@ -0,0 +1,207 @@
+use std::{io, time::Duration};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, sync::{broadcast, mpsc::Receiver, oneshot}, time::Instant};
+
+use crate::{broadcast::BroadcastAssembler, constants::{ModemError, ModemErrorType, UnsolicitedResultCode, DEFAULT_COMMAND_TIMEOUT}, delivery::DeliveryTracker, error::{Error, Result}, events::{BackgroundTask, ModemEvent}, framer::{FinalResult, FrameKind, Framer}, transport::BoxedTransport};
+
+/// Sent in place of a prompt payload to back out of the `> ` prompt without sending anything
+const ESCAPE: &str = "\x1b";
+
+/// A single AT command queued for the actor
+pub(crate) struct Command {
+    /// The command line, including the trailing `\r`
+    pub data: String,
+    /// Sent once the modem prompts with `> ` (ie. the body of `AT+CMGS`)
+    pub payload: Option<String>,
+    /// How long to wait for the final result code once the command has been written
+    pub timeout: Duration,
+    /// Where the final response (or error) for this command is delivered
+    pub responder: oneshot::Sender<Result<String>>
+}
+
+/// The command currently waiting on its final result code
+struct InFlight {
+    command: Command,
+    response: String,
+    deadline: Instant
+}
+
+/// Set once a command is given up on before its final result code, until the modem is known to have caught up
+///
+/// Until then its late response could be taken for the response to the next command, so nothing new is sent.
+struct Recovery {
+    deadline: Instant,
+    /// How many times the modem has been probed with `AT`, 0 while still waiting on the late final result code
+    probes: u32
+}
+
+/// Owns the transport and runs every AT command one at a time
+///
+/// Commands are taken off the queue only once the previous one has received its final result code,
+/// so each response is matched to the command that produced it regardless of how many tasks are sending.
+///
+/// Once written, a command is always seen through by the actor even if the caller stops waiting on it,
+/// so dropping a caller's future can never leave the modem stuck at a `> ` prompt.
+///
+/// A command that times out (or whose caller went away at the prompt) still owes a final result code, so the
+/// actor waits for it before sending anything else. If it doesn't come, the modem is brought back with `ESC`
+/// (leaving any prompt) and `AT`, and the next command is sent once that's answered. Commands queued while
+/// the modem doesn't even answer `AT` fail with `Error::Timeout` without being sent.
+///
+/// Unsolicited result codes are parsed into `ModemEvent`s and broadcast to subscribers.
+///
+/// Status reports are matched up with the sent message they're for before being broadcast.
+pub(crate) async fn run(mut transport: BoxedTransport, mut commands: Receiver<Command>, events: broadcast::Sender<ModemEvent>, deliveries: DeliveryTracker) {
+    let urc_regex = UnsolicitedResultCode::get_regex_array();
+
+    let mut framer = Framer::new();
+    let mut broadcasts = BroadcastAssembler::new();
+    let mut serial_buf: Vec<u8> = vec![0; 1000];
+    let mut in_flight: Option<InFlight> = None;
+    let mut recovery: Option<Recovery> = None;
+    // Set when a prompting command timed out before its prompt arrived, the late prompt still needs backing out of
+    let mut abandoned_prompt = false;
+
+    loop {
+        let deadline = in_flight.as_ref().map(|current| current.deadline).or(recovery.as_ref().map(|recovery| recovery.deadline));
+        // Once the modem has ignored a probe there's no telling when it'll answer, so queued commands are failed rather than held
+        let unresponsive = recovery.as_ref().is_some_and(|recovery| recovery.probes > 1);
+
+        tokio::select! {
+            read = transport.read(serial_buf.as_mut_slice()) => {
+                let t = match read {
+                    Ok(0) => return,
+                    Ok(t) => t,
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(e) => {
+                        let _ = events.send(ModemEvent::BackgroundError { task: BackgroundTask::Connection, error: e.to_string() });
+                        return
+                    }
+                };
+
+                for frame in framer.push(&serial_buf[..t]) {
+                    if let FrameKind::Urc(urc) = frame.kind {
+                        let captures = urc_regex.iter().find(|(u, _)| *u == urc).and_then(|(_, regex)| regex.captures(&frame.line));
+                        match captures.and_then(|captures| ModemEvent::from_urc(urc, &captures, frame.body.as_deref())) {
+                            Some(mut event) => {
+                                if let ModemEvent::DeliveryReport(report) = &mut event {
+                                    deliveries.correlate(report);
+                                }
+                                let broadcast = match &event {
+                                    ModemEvent::CellBroadcastPage(page) => broadcasts.push(page.clone()),
+                                    _ => None
+                                };
+
+                                // Sending only fails when nobody is subscribed, which is fine
+                                let _ = events.send(event);
+                                if let Some(broadcast) = broadcast {
+                                    let _ = events.send(ModemEvent::CellBroadcast(broadcast));
+                                }
+                            }
+                            None => {
+                                let error = format!("Failed to parse URC: {:?}", frame.line);
+                                let _ = events.send(ModemEvent::BackgroundError { task: BackgroundTask::Connection, error: error });
+                            }
+                        }
+                        continue
+                    }
+
+                    // A prompt for a command that was given up on comes before any prompt for the command in flight
+                    let expecting_prompt = in_flight.as_ref().is_some_and(|current| current.command.payload.is_some());
+                    if frame.kind == FrameKind::Prompt && (abandoned_prompt || !expecting_prompt) {
+                        abandoned_prompt = false;
+                        let _ = write(&mut transport, ESCAPE).await;
+                        continue
+                    }
+
+                    let Some(current) = in_flight.as_mut() else {
+                        // The late final result code of a command that was given up on, the modem has caught up
+                        if let FrameKind::Final(_) = frame.kind && let Some(finished) = recovery.take() {
+                            // Unless it came from a probe, the command is over and won't prompt anymore
+                            if finished.probes == 0 {
+                                abandoned_prompt = false;
+                            }
+                        }
+                        continue
+                    };
+
+                    match frame.kind {
+                        FrameKind::Prompt => {
+                            // If the caller has gone away, back out of the prompt rather than sending something nobody wants anymore
+                            if current.command.responder.is_closed() {
+                                recovery = Some(Recovery { deadline: current.deadline, probes: 0 });
+                                in_flight = None;
+                                let _ = write(&mut transport, ESCAPE).await;
+                                continue
+                            }
+
+                            let payload = current.command.payload.take().unwrap();
+                            current.response.clear();
+
+                            if let Err(e) = write(&mut transport, &payload).await {
+                                let current = in_flight.take().unwrap();
+                                let _ = current.command.responder.send(Err(e.into()));
+                            }
+                        }
+                        FrameKind::Final(result) => {
+                            let mut current = in_flight.take().unwrap();
+                            current.response.push_str(&frame.raw);
+
+                            let response = match result {
+                                FinalResult::Ok => Ok(current.response),
+                                FinalResult::Error => Err(Error::CommandFailed),
+                                FinalResult::CmeError(code) => Err(Error::Modem(ModemError::new(ModemErrorType::CmeError, code))),
+                                FinalResult::CmsError(code) => Err(Error::Modem(ModemError::new(ModemErrorType::CmsError, code)))
+                            };
+                            let _ = current.command.responder.send(response);
+                        }
+                        _ => current.response.push_str(&frame.raw)
+                    }
+                }
+            }
+            command = commands.recv(), if in_flight.is_none() && (recovery.is_none() || unresponsive) => {
+                // Every sender has been dropped, the modem is closing
+                let Some(command) = command else { return };
+
+                // The caller gave up before the command was sent, so don't bother the modem with it
+                if command.responder.is_closed() {
+                    continue
+                }
+
+                if unresponsive {
+                    let error = Error::Timeout { command: String::from(command.data.trim_end()), timeout: command.timeout };
+                    let _ = command.responder.send(Err(error));
+                    continue
+                }
+
+                match write(&mut transport, &command.data).await {
+                    Ok(_) => {
+                        let deadline = Instant::now() + command.timeout;
+                        in_flight = Some(InFlight { command: command, response: String::new(), deadline: deadline });
+                    }
+                    Err(e) => { let _ = command.responder.send(Err(e.into())); }
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                if let Some(current) = in_flight.take() {
+                    // Still waiting on the prompt, so it may yet show up and will need backing out of
+                    abandoned_prompt |= current.command.payload.is_some();
+                    // Give the late final result code as long again to show up, within reason
+                    recovery = Some(Recovery { deadline: Instant::now() + current.command.timeout.min(DEFAULT_COMMAND_TIMEOUT), probes: 0 });
+
+                    let error = Error::Timeout { command: String::from(current.command.data.trim_end()), timeout: current.command.timeout };
+                    let _ = current.command.responder.send(Err(error));
+                } else if let Some(recovery) = recovery.as_mut() {
+                    recovery.probes += 1;
+                    recovery.deadline = Instant::now() + DEFAULT_COMMAND_TIMEOUT;
+                    let _ = write(&mut transport, &format!("{}AT\r", ESCAPE)).await;
+                }
+            }
+        }
+    }
+}
+
+async fn write(transport: &mut BoxedTransport, data: &str) -> io::Result<()> {
+    transport.write_all(data.as_bytes()).await?;
+    transport.flush().await
+}
@@ -0,0 +1,338 @@
+use std::{collections::HashSet, fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::Path, sync::{Arc, Mutex}};
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+use crate::{constants::{SmsMessage, SmsStatus}, delivery::SentMessage, error::{Error, Result}, phone_number::PhoneNumber, utils::{escape_field, unescape_field}};
+
+/// First line of every archive file, so a file that isn't an archive is never appended to
+const HEADER: &str = "# async-modem message archive v1";
+
+/// Whether an archived message was received or sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Received,
+    Sent
+}
+
+/// A message kept in the archive
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchivedMessage {
+    /// Unique within the archive, increasing in the order messages were archived
+    pub id: u64,
+    pub direction: Direction,
+    /// The sender of received messages, or the recipient of sent ones, as the modem gave it
+    pub address: String,
+    /// The normalized address shared by every message in the conversation, international once that form of the
+    /// number has been seen
+    pub contact: String,
+    pub content: String,
+    /// When the service centre received the message, or when it was sent (or archived, if it has no timestamp)
+    pub timestamp: DateTime<FixedOffset>,
+    /// Sent messages are always read
+    pub read: bool
+}
+
+/// Every message exchanged with one contact
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversation {
+    pub contact: String,
+    pub message_count: usize,
+    pub unread_count: usize,
+    pub last_message: ArchivedMessage
+}
+
+/// Filters for `MessageArchive::query`, every filter that's set has to match
+#[derive(Clone, Debug, Default)]
+pub struct MessageQuery {
+    /// Messages to or from this number, in any format (ie. with or without the country code's `+`)
+    pub contact: Option<String>,
+    /// Messages at or after this time
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Messages before this time
+    pub until: Option<DateTime<FixedOffset>>,
+    pub direction: Option<Direction>,
+    pub unread_only: bool
+}
+
+/// A local history of every message sent and received, kept beyond what fits in the modem's storage
+///
+/// Backed by an append-only file with one record per line, which is replayed into memory on open.
+/// Messages are deduplicated on direction, contact, content and timestamp, so reading the same message
+/// from storage twice only archives it once. Outgoing messages from storage have no timestamp, so they're
+/// deduplicated on direction, contact and content alone.
+///
+/// Cloning is cheap and every clone shares the same archive. Writes are small and done synchronously.
+#[derive(Clone)]
+pub struct MessageArchive {
+    inner: Arc<Mutex<ArchiveInner>>
+}
+
+struct ArchiveInner {
+    file: File,
+    messages: Vec<ArchivedMessage>,
+    seen: HashSet<(Direction, String, String, i64)>,
+    /// Same as `seen` without the timestamp, for messages that don't have one
+    seen_untimed: HashSet<(Direction, String, String)>,
+    /// Every contact that's a phone number, to find the conversation a differently written number belongs to
+    numbers: Vec<PhoneNumber>,
+    next_id: u64
+}
+
+impl MessageArchive {
+    /// Opens the archive at `path`, creating it if it doesn't exist
+    ///
+    /// A partly written last record (ie. from a crash mid-write) is dropped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MessageArchive> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        if contents.is_empty() {
+            writeln!(file, "{}", HEADER)?;
+            contents = format!("{}\n", HEADER);
+        } else if !contents.starts_with(HEADER) {
+            return Err(Error::parse("File is not a message archive!", contents.lines().next().unwrap_or_default()))
+        }
+
+        if !contents.ends_with('\n') {
+            let complete = contents.rfind('\n').map(|end| end + 1).unwrap_or_default();
+            file.set_len(complete as u64)?;
+            file.seek(SeekFrom::End(0))?;
+            contents.truncate(complete);
+        }
+
+        let mut inner = ArchiveInner {
+            file: file,
+            messages: Vec::new(),
+            seen: HashSet::new(),
+            seen_untimed: HashSet::new(),
+            numbers: Vec::new(),
+            next_id: 1
+        };
+        for line in contents.lines().filter(|line| !line.starts_with('#')) {
+            inner.replay(line)?;
+        }
+
+        Ok(MessageArchive { inner: Arc::new(Mutex::new(inner)) })
+    }
+
+    /// Archives a message read from the modem, returning its id or `None` if it was already archived
+    ///
+    /// Outgoing messages from storage have no timestamp, so they're archived with the current time unless the
+    /// same content was already archived as sent to the same contact.
+    pub fn record(&self, message: &SmsMessage) -> Result<Option<u64>> {
+        let direction = if message.is_outgoing() { Direction::Sent } else { Direction::Received };
+        let read = direction == Direction::Sent || message.status() == SmsStatus::ReceivedRead;
+
+        self.inner.lock().unwrap().append(direction, &message.address(), &message.content(), message.timestamp(), read)
+    }
+
+    /// Archives a message we sent, returning its id or `None` if it was already archived
+    pub fn record_sent(&self, message: &SentMessage) -> Result<Option<u64>> {
+        self.inner.lock().unwrap().append(Direction::Sent, &message.destination, &message.content, Some(message.sent_at.fixed_offset()), true)
+    }
+
+    /// Marks a message as read, returning whether it was unread
+    pub fn mark_read(&self, id: u64) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let Some(message) = inner.messages.iter_mut().find(|message| message.id == id && !message.read) else { return Ok(false) };
+        message.read = true;
+        writeln!(inner.file, "R\t{}", id)?;
+
+        Ok(true)
+    }
+
+    /// Marks every message from a contact as read, returning how many were unread
+    pub fn mark_conversation_read(&self, contact: &str) -> Result<usize> {
+        let query = MessageQuery { contact: Some(String::from(contact)), unread_only: true, ..Default::default() };
+
+        let mut marked = 0;
+        for message in self.query(&query) {
+            if self.mark_read(message.id)? {
+                marked += 1;
+            }
+        }
+
+        Ok(marked)
+    }
+
+    /// Messages matching every filter set in the query, oldest first
+    pub fn query(&self, query: &MessageQuery) -> Vec<ArchivedMessage> {
+        let inner = self.inner.lock().unwrap();
+        let contact = query.contact.as_deref().map(|contact| inner.find_contact(contact));
+
+        let mut messages: Vec<ArchivedMessage> = inner.messages.iter()
+            .filter(|message| contact.as_ref().is_none_or(|contact| message.contact == *contact))
+            .filter(|message| query.since.is_none_or(|since| message.timestamp >= since))
+            .filter(|message| query.until.is_none_or(|until| message.timestamp < until))
+            .filter(|message| query.direction.is_none_or(|direction| message.direction == direction))
+            .filter(|message| !query.unread_only || !message.read)
+            .cloned()
+            .collect();
+        messages.sort_by_key(|message| (message.timestamp, message.id));
+
+        messages
+    }
+
+    /// Every message to or from a contact, oldest first
+    pub fn conversation(&self, contact: &str) -> Vec<ArchivedMessage> {
+        self.query(&MessageQuery { contact: Some(String::from(contact)), ..Default::default() })
+    }
+
+    /// Every received message that hasn't been marked read, oldest first
+    pub fn unread(&self) -> Vec<ArchivedMessage> {
+        self.query(&MessageQuery { unread_only: true, ..Default::default() })
+    }
+
+    /// Every conversation, the one with the most recent message first
+    pub fn conversations(&self) -> Vec<Conversation> {
+        let mut conversations: Vec<Conversation> = Vec::new();
+
+        for message in self.query(&MessageQuery::default()) {
+            match conversations.iter_mut().find(|conversation| conversation.contact == message.contact) {
+                Some(conversation) => {
+                    conversation.message_count += 1;
+                    conversation.unread_count += !message.read as usize;
+                    conversation.last_message = message;
+                }
+                None => conversations.push(Conversation {
+                    contact: message.contact.clone(),
+                    message_count: 1,
+                    unread_count: !message.read as usize,
+                    last_message: message
+                })
+            }
+        }
+        conversations.sort_by_key(|conversation| std::cmp::Reverse(conversation.last_message.timestamp));
+
+        conversations
+    }
+
+    /// How many messages are archived
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ArchiveInner {
+    fn append(&mut self, direction: Direction, address: &str, content: &str, timestamp: Option<DateTime<FixedOffset>>, read: bool) -> Result<Option<u64>> {
+        let contact = self.find_contact(address);
+        let seen = match timestamp {
+            Some(timestamp) => self.seen.contains(&(direction, contact.clone(), String::from(content), timestamp.timestamp())),
+            None => self.seen_untimed.contains(&(direction, contact.clone(), String::from(content)))
+        };
+        if seen {
+            return Ok(None)
+        }
+
+        let timestamp = timestamp.unwrap_or_else(|| Utc::now().fixed_offset());
+        let message = ArchivedMessage {
+            id: self.next_id,
+            direction: direction,
+            address: String::from(address),
+            contact: contact,
+            content: String::from(content),
+            timestamp: timestamp,
+            read: read
+        };
+
+        let direction = match direction {
+            Direction::Received => "R",
+            Direction::Sent => "S"
+        };
+        writeln!(
+            self.file, "M\t{}\t{}\t{}\t{}\t{}\t{}",
+            message.id, direction, read as u8, timestamp.to_rfc3339(), escape_field(address), escape_field(content)
+        )?;
+
+        let id = message.id;
+        self.insert(message);
+
+        Ok(Some(id))
+    }
+
+    /// Applies a single record read back from the file
+    fn replay(&mut self, line: &str) -> Result<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let invalid = || Error::parse("Invalid message archive record!", line);
+
+        match fields.as_slice() {
+            ["M", id, direction, read, timestamp, address, content] => {
+                let address = unescape_field(address);
+                let message = ArchivedMessage {
+                    id: id.parse().map_err(|_| invalid())?,
+                    direction: match *direction {
+                        "R" => Direction::Received,
+                        "S" => Direction::Sent,
+                        _ => return Err(invalid())
+                    },
+                    contact: String::new(),
+                    address: address,
+                    content: unescape_field(content),
+                    timestamp: DateTime::parse_from_rfc3339(timestamp).map_err(|_| invalid())?,
+                    read: *read == "1"
+                };
+                self.insert(message);
+            }
+            ["R", id] => {
+                let id: u64 = id.parse().map_err(|_| invalid())?;
+                if let Some(message) = self.messages.iter_mut().find(|message| message.id == id) {
+                    message.read = true;
+                }
+            }
+            _ => return Err(invalid())
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, mut message: ArchivedMessage) {
+        message.contact = self.add_contact(&message.address);
+        self.next_id = self.next_id.max(message.id + 1);
+        self.seen.insert((message.direction, message.contact.clone(), message.content.clone(), message.timestamp.timestamp()));
+        self.seen_untimed.insert((message.direction, message.contact.clone(), message.content.clone()));
+        self.messages.push(message);
+    }
+
+    /// The contact of the conversation an address belongs to, without adding it
+    ///
+    /// Numbers are matched with `PhoneNumber::matches`, so `+447700900123` and `07700900123` share a conversation.
+    /// Alphanumeric senders (ie. `ACME`) are kept as they are.
+    fn find_contact(&self, address: &str) -> String {
+        match PhoneNumber::parse(address) {
+            Ok(number) => self.numbers.iter().find(|known| known.matches(&number)).unwrap_or(&number).to_string(),
+            Err(_) => String::from(address.trim())
+        }
+    }
+
+    /// Same as `find_contact`, remembering the number as a contact
+    ///
+    /// A national number's conversation moves to the international number once one is seen, since that's the
+    /// more complete of the two.
+    fn add_contact(&mut self, address: &str) -> String {
+        let Ok(number) = PhoneNumber::parse(address) else { return String::from(address.trim()) };
+
+        let Some(position) = self.numbers.iter().position(|known| known.matches(&number)) else {
+            self.numbers.push(number.clone());
+            return number.to_string()
+        };
+        if number.is_international() && !self.numbers[position].is_international() {
+            let national = std::mem::replace(&mut self.numbers[position], number).to_string();
+            let international = self.numbers[position].to_string();
+
+            for message in self.messages.iter_mut().filter(|message| message.contact == national) {
+                message.contact = international.clone();
+            }
+            self.seen = self.messages.iter().map(|message| (message.direction, message.contact.clone(), message.content.clone(), message.timestamp.timestamp())).collect();
+            self.seen_untimed = self.messages.iter().map(|message| (message.direction, message.contact.clone(), message.content.clone())).collect();
+        }
+
+        self.numbers[position].to_string()
+    }
+}
@@ -0,0 +1,411 @@
+use std::{collections::VecDeque, ops::RangeInclusive};
+
+use regex::Regex;
+
+use crate::{error::{Error, Result}, gsm7, utils::{bytes_to_hex, hex_to_bytes, hex_to_utf16, hex_to_utf16_or_raw}};
+
+/// Octets in a cell broadcast page, header included
+const PAGE_LENGTH: usize = 88;
+
+/// Octets of content in a cell broadcast page
+const PAGE_CONTENT_LENGTH: usize = PAGE_LENGTH - 6;
+
+/// How many partly received broadcasts are kept waiting on their other pages, the oldest is dropped past this
+const MAX_INCOMPLETE_BROADCASTS: usize = 16;
+
+/// Languages of the `0000` coding group, by the low nibble of the data coding scheme
+const LANGUAGES: [&str; 15] = ["de", "en", "it", "fr", "es", "nl", "sv", "da", "pt", "fi", "no", "el", "tr", "hu", "pl"];
+
+/// Languages of the `0010` coding group
+const MORE_LANGUAGES: [&str; 5] = ["cs", "he", "ar", "ru", "is"];
+
+/// Message identifiers reserved for public warning systems (ETWS, CMAS, EU-Alert and so on)
+pub const PUBLIC_WARNING_IDENTIFIERS: RangeInclusive<u16> = 0x1100..=0x18FF;
+
+/// Where a broadcast applies, and so when a repeat of it counts as new
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeographicalScope {
+    /// The cell, to be displayed straight away
+    CellImmediate,
+    Plmn,
+    LocationArea,
+    Cell
+}
+
+/// Identifies a broadcast along with its message identifier, changing when the broadcast is updated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SerialNumber(pub u16);
+
+impl SerialNumber {
+    pub fn geographical_scope(&self) -> GeographicalScope {
+        match self.0 >> 14 {
+            0 => GeographicalScope::CellImmediate,
+            1 => GeographicalScope::Plmn,
+            2 => GeographicalScope::LocationArea,
+            _ => GeographicalScope::Cell
+        }
+    }
+
+    pub fn message_code(&self) -> u16 {
+        (self.0 >> 4) & 0x3FF
+    }
+
+    pub fn update_number(&self) -> u8 {
+        (self.0 & 0x0F) as u8
+    }
+}
+
+/// The kind of public warning a broadcast carries, by its message identifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublicWarning {
+    /// Earthquake and tsunami warnings (ETWS)
+    Etws,
+    /// CMAS presidential level alert
+    Presidential,
+    /// CMAS extreme threat
+    Extreme,
+    /// CMAS severe threat
+    Severe,
+    /// CMAS child abduction emergency
+    Amber,
+    /// CMAS required monthly test, exercise or operator defined test
+    Test,
+    /// Any other public warning identifier (ie. EU-Alert)
+    Other
+}
+
+impl PublicWarning {
+    pub fn from_message_identifier(identifier: u16) -> Option<PublicWarning> {
+        if !PUBLIC_WARNING_IDENTIFIERS.contains(&identifier) {
+            return None
+        }
+
+        // CMAS identifiers repeat 13 higher for the additional language
+        let cmas = match identifier {
+            4383..=4395 => identifier - 13,
+            _ => identifier
+        };
+
+        Some(match cmas {
+            4352..=4359 => PublicWarning::Etws,
+            4370 => PublicWarning::Presidential,
+            4371 | 4372 => PublicWarning::Extreme,
+            4373..=4378 => PublicWarning::Severe,
+            4379 => PublicWarning::Amber,
+            4380..=4382 => PublicWarning::Test,
+            _ => PublicWarning::Other
+        })
+    }
+}
+
+/// How a broadcast's content is encoded, and its language if the coding scheme says
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BroadcastCoding {
+    alphabet: BroadcastAlphabet,
+    language: Option<String>,
+    /// The language is at the start of the content instead of in the coding scheme
+    language_in_content: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BroadcastAlphabet {
+    Gsm7,
+    EightBit,
+    Ucs2
+}
+
+impl BroadcastCoding {
+    /// Decodes the cell broadcast data coding scheme, which differs from the SMS one
+    fn from_dcs(dcs: u8) -> BroadcastCoding {
+        let coding = |alphabet, language: Option<&str>| BroadcastCoding { alphabet: alphabet, language: language.map(String::from), language_in_content: false };
+        let alphabet = |bits: u8| match bits & 0x03 {
+            1 => BroadcastAlphabet::EightBit,
+            2 => BroadcastAlphabet::Ucs2,
+            _ => BroadcastAlphabet::Gsm7
+        };
+
+        match dcs >> 4 {
+            0x0 => coding(BroadcastAlphabet::Gsm7, LANGUAGES.get((dcs & 0x0F) as usize).copied()),
+            0x1 => BroadcastCoding {
+                alphabet: if dcs & 0x0F == 1 { BroadcastAlphabet::Ucs2 } else { BroadcastAlphabet::Gsm7 },
+                language: None,
+                language_in_content: true
+            },
+            0x2 => coding(BroadcastAlphabet::Gsm7, MORE_LANGUAGES.get((dcs & 0x0F) as usize).copied()),
+            0x4..=0x7 | 0x9 => coding(alphabet(dcs >> 2), None),
+            0xF => coding(if dcs & 0x04 != 0 { BroadcastAlphabet::EightBit } else { BroadcastAlphabet::Gsm7 }, None),
+            _ => coding(BroadcastAlphabet::Gsm7, None)
+        }
+    }
+
+    /// Decodes a page's content, taking the language off the front if it's there
+    fn decode(&self, content: &[u8]) -> (String, Option<String>) {
+        let (text, language) = match self.alphabet {
+            BroadcastAlphabet::Gsm7 => {
+                let text = gsm7::decode(&gsm7::unpack_septets(content, content.len() * 8 / 7, 0));
+                match self.language_in_content {
+                    true => (text.get(3..).map(String::from).unwrap_or_default(), text.get(..2).map(String::from)),
+                    false => (text, None)
+                }
+            }
+            BroadcastAlphabet::Ucs2 => {
+                let (language, content) = match self.language_in_content {
+                    true if content.len() >= 2 => (Some(gsm7::decode(&gsm7::unpack_septets(&content[..2], 2, 0))), &content[2..]),
+                    _ => (None, content)
+                };
+                let units: Vec<u16> = content.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+                (String::from_utf16_lossy(&units), language)
+            }
+            BroadcastAlphabet::EightBit => (bytes_to_hex(content), None)
+        };
+
+        // Pages are padded out with carriage returns (or zeros, by some networks)
+        (String::from(text.trim_end_matches(['\r', '\n', '\0'])), language.or_else(|| self.language.clone()))
+    }
+}
+
+/// One page of a cell broadcast, as it arrives in `+CBM`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellBroadcastPage {
+    pub serial_number: SerialNumber,
+    pub message_identifier: u16,
+    pub data_coding_scheme: u8,
+    /// Which page this is, from 1
+    pub page: u8,
+    pub pages: u8,
+    pub language: Option<String>,
+    pub content: String
+}
+
+impl CellBroadcastPage {
+    /// Decodes a page from the hex PDU of a PDU mode `+CBM`
+    pub fn decode(pdu: &str) -> Result<CellBroadcastPage> {
+        let octets = hex_to_bytes(pdu)?;
+        if octets.len() < 6 {
+            return Err(Error::parse("Cell broadcast page is too short!", pdu))
+        }
+
+        let data_coding_scheme = octets[4];
+        let (content, language) = BroadcastCoding::from_dcs(data_coding_scheme).decode(&octets[6..]);
+
+        // A page parameter of zero means a single page
+        let (page, pages) = match (octets[5] >> 4, octets[5] & 0x0F) {
+            (0, _) | (_, 0) => (1, 1),
+            (page, pages) => (page, pages)
+        };
+
+        Ok(CellBroadcastPage {
+            serial_number: SerialNumber(u16::from_be_bytes([octets[0], octets[1]])),
+            message_identifier: u16::from_be_bytes([octets[2], octets[3]]),
+            data_coding_scheme: data_coding_scheme,
+            page: page,
+            pages: pages,
+            language: language,
+            content: content
+        })
+    }
+
+    /// Builds a page from a text mode `+CBM`, given its serial number, message identifier, coding scheme, page
+    /// and page count, and the content on the next line
+    ///
+    /// The content is UCS2 hex with `AT+CSCS="UCS2"`, anything that isn't is taken as-is.
+    pub fn from_text(serial_number: u16, message_identifier: u16, data_coding_scheme: u8, page: u8, pages: u8, content: &str) -> CellBroadcastPage {
+        let coding = BroadcastCoding::from_dcs(data_coding_scheme);
+        let content = hex_to_utf16(content).unwrap_or_else(|_| String::from(content));
+
+        // The modem converts the content to text, language indication and all
+        let (content, language) = match coding.language_in_content {
+            true => (content.get(3..).map(String::from).unwrap_or_default(), content.get(..2).map(String::from)),
+            false => (content, coding.language)
+        };
+
+        CellBroadcastPage {
+            serial_number: SerialNumber(serial_number),
+            message_identifier: message_identifier,
+            data_coding_scheme: data_coding_scheme,
+            page: page.max(1),
+            pages: pages.max(1),
+            language: language,
+            content: String::from(content.trim_end_matches(['\r', '\n', '\0']))
+        }
+    }
+
+    /// Encodes the page as a hex PDU, padding the content out to a full page
+    ///
+    /// Only text in the GSM 7-bit default alphabet (`0x0F` or any `0000` language) and UCS2 (`0x48`) can be encoded.
+    pub fn encode(&self) -> Result<String> {
+        let mut content = match BroadcastCoding::from_dcs(self.data_coding_scheme) {
+            BroadcastCoding { alphabet: BroadcastAlphabet::Gsm7, language_in_content: false, .. } => {
+                let mut septets = gsm7::encode(&self.content).ok_or_else(|| Error::invalid_argument("Cell broadcast must use the GSM 7-bit alphabet!"))?;
+                if septets.len() > PAGE_CONTENT_LENGTH * 8 / 7 {
+                    return Err(Error::invalid_argument("Cell broadcast content doesn't fit in a page!"))
+                }
+                septets.resize(PAGE_CONTENT_LENGTH * 8 / 7, b'\r');
+                gsm7::pack_septets(&septets, 0)
+            }
+            BroadcastCoding { alphabet: BroadcastAlphabet::Ucs2, language_in_content: false, .. } => {
+                let mut octets: Vec<u8> = self.content.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect();
+                while octets.len() < PAGE_CONTENT_LENGTH {
+                    octets.extend([0x00, 0x0D]);
+                }
+                octets
+            }
+            _ => return Err(Error::invalid_argument("Unsupported cell broadcast coding scheme!"))
+        };
+        if content.len() > PAGE_CONTENT_LENGTH {
+            return Err(Error::invalid_argument("Cell broadcast content doesn't fit in a page!"))
+        }
+        content.resize(PAGE_CONTENT_LENGTH, 0);
+
+        let mut octets = Vec::with_capacity(PAGE_LENGTH);
+        octets.extend(self.serial_number.0.to_be_bytes());
+        octets.extend(self.message_identifier.to_be_bytes());
+        octets.push(self.data_coding_scheme);
+        octets.push((self.page << 4) | (self.pages & 0x0F));
+        octets.extend(content);
+
+        Ok(bytes_to_hex(&octets))
+    }
+}
+
+/// A complete cell broadcast, with every page joined
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellBroadcast {
+    pub serial_number: SerialNumber,
+    /// The channel the broadcast came in on (ie. `4370` for a CMAS presidential alert)
+    pub message_identifier: u16,
+    pub data_coding_scheme: u8,
+    /// ISO 639 language code (ie. `en`), if the coding scheme gives one
+    pub language: Option<String>,
+    pub content: String,
+    pub pages: u8
+}
+
+impl CellBroadcast {
+    /// The kind of public warning (ie. a CMAS extreme alert), `None` for broadcasts that aren't warnings
+    pub fn public_warning(&self) -> Option<PublicWarning> {
+        PublicWarning::from_message_identifier(self.message_identifier)
+    }
+
+    /// A broadcast that only has one page
+    pub fn from_page(page: CellBroadcastPage) -> CellBroadcast {
+        CellBroadcast {
+            serial_number: page.serial_number,
+            message_identifier: page.message_identifier,
+            data_coding_scheme: page.data_coding_scheme,
+            language: page.language,
+            content: page.content,
+            pages: page.pages
+        }
+    }
+}
+
+/// Joins the pages of multipage broadcasts, keyed on serial number and message identifier
+///
+/// The modem does this for `ModemEvent::CellBroadcast`, this is for pages from elsewhere.
+#[derive(Default)]
+pub struct BroadcastAssembler {
+    incomplete: VecDeque<Vec<CellBroadcastPage>>
+}
+
+impl BroadcastAssembler {
+    pub fn new() -> Self {
+        BroadcastAssembler::default()
+    }
+
+    /// Adds a page, returning the broadcast once every page has arrived
+    ///
+    /// Repeats of a page that's already here are ignored, networks repeat broadcasts on a schedule.
+    pub fn push(&mut self, page: CellBroadcastPage) -> Option<CellBroadcast> {
+        if page.pages <= 1 {
+            return Some(CellBroadcast::from_page(page))
+        }
+
+        let same = |other: &CellBroadcastPage| other.serial_number == page.serial_number && other.message_identifier == page.message_identifier;
+        let position = match self.incomplete.iter().position(|pages| same(&pages[0])) {
+            Some(position) => position,
+            None => {
+                if self.incomplete.len() >= MAX_INCOMPLETE_BROADCASTS {
+                    self.incomplete.pop_front();
+                }
+                self.incomplete.push_back(Vec::new());
+                self.incomplete.len() - 1
+            }
+        };
+
+        let pages = &mut self.incomplete[position];
+        if !pages.iter().any(|existing| existing.page == page.page) {
+            pages.push(page);
+        }
+        if pages.len() < pages[0].pages as usize {
+            return None
+        }
+
+        let mut pages = self.incomplete.remove(position)?;
+        pages.sort_by_key(|page| page.page);
+        let content = pages.iter().map(|page| page.content.as_str()).collect();
+
+        Some(CellBroadcast { content: content, ..CellBroadcast::from_page(pages.remove(0)) })
+    }
+}
+
+/// Which broadcasts the modem passes on, as set with `AT+CSCB`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastChannels {
+    /// Reject the listed message identifiers and languages instead of only accepting them
+    pub reject: bool,
+    pub message_identifiers: Vec<RangeInclusive<u16>>,
+    /// Coding schemes to accept (ie. `0..=5` for the first languages), all of them if empty
+    pub languages: Vec<RangeInclusive<u8>>
+}
+
+impl BroadcastChannels {
+    /// Accepts the given message identifiers in any language
+    pub fn accept(message_identifiers: Vec<RangeInclusive<u16>>) -> Self {
+        BroadcastChannels { reject: false, message_identifiers: message_identifiers, languages: Vec::new() }
+    }
+
+    /// Accepts every public warning identifier
+    pub fn public_warnings() -> Self {
+        BroadcastChannels::accept(vec![PUBLIC_WARNING_IDENTIFIERS])
+    }
+
+    /// Takes the modem output of `AT+CSCB?`, where the lists may be UCS2 hex
+    pub fn from_cscb(raw_string: String) -> Result<BroadcastChannels> {
+        let captures = Regex::new(r#"\+CSCB: (\d),"([^"]*)","([^"]*)""#).unwrap()
+            .captures(&raw_string)
+            .ok_or_else(|| Error::parse("Failed to parse broadcast channels!", &raw_string))?;
+
+        let list = |raw: &str| hex_to_utf16_or_raw(raw, |c| c.is_ascii_digit() || c == ',' || c == '-');
+
+        Ok(BroadcastChannels {
+            reject: &captures[1] == "1",
+            message_identifiers: parse_ranges(&list(&captures[2])).ok_or_else(|| Error::parse("Failed to parse broadcast message identifiers!", &raw_string))?,
+            languages: parse_ranges(&list(&captures[3])).ok_or_else(|| Error::parse("Failed to parse broadcast languages!", &raw_string))?
+        })
+    }
+
+    /// The lists as sent in `AT+CSCB`, before any UCS2 encoding
+    pub fn to_lists(&self) -> (String, String) {
+        (format_ranges(&self.message_identifiers), format_ranges(&self.languages))
+    }
+}
+
+/// Parses a list like `4370-4383,4352`, an empty list is an empty vector
+fn parse_ranges<T: std::str::FromStr + Copy + PartialOrd>(list: &str) -> Option<Vec<RangeInclusive<T>>> {
+    list.split(',').filter(|item| !item.trim().is_empty()).map(|item| {
+        let (start, end) = item.split_once('-').unwrap_or((item, item));
+        let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+
+        if start > end { None } else { Some(start..=end) }
+    }).collect()
+}
+
+fn format_ranges<T: std::fmt::Display + PartialEq>(ranges: &[RangeInclusive<T>]) -> String {
+    let items: Vec<String> = ranges.iter().map(|range| match range.start() == range.end() {
+        true => range.start().to_string(),
+        false => format!("{}-{}", range.start(), range.end())
+    }).collect();
+
+    items.join(",")
+}
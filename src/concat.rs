@@ -0,0 +1,146 @@
+use std::{collections::{BTreeMap, HashMap}, time::{Duration, Instant}};
+
+use crate::{constants::SmsMessage, pdu::{ConcatReference, SmsDeliver, StoredPdu, Tpdu}};
+
+/// How long to wait for the rest of a concatenated message before giving up on it
+///
+/// Parts are sent separately and the network doesn't guarantee they arrive together (or in order).
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A concatenated message that is still missing parts
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncompleteMessage {
+    address: String,
+    reference: ConcatReference,
+    total: u8,
+    parts: BTreeMap<u8, SmsMessage>,
+    first_received: Instant
+}
+
+impl IncompleteMessage {
+    /// The sender of the message
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The concatenation reference shared by every part
+    pub fn reference(&self) -> ConcatReference {
+        self.reference
+    }
+
+    /// How many parts the full message has
+    pub fn total_parts(&self) -> u8 {
+        self.total
+    }
+
+    /// Sequence numbers (starting from 1) of the parts received so far
+    pub fn received_parts(&self) -> Vec<u8> {
+        self.parts.keys().copied().collect()
+    }
+
+    /// Sequence numbers (starting from 1) of the parts that haven't arrived
+    pub fn missing_parts(&self) -> Vec<u8> {
+        (1..=self.total).filter(|sequence| !self.parts.contains_key(sequence)).collect()
+    }
+
+    /// Memory index of every part received so far, ie. to delete them once given up on
+    pub fn memory_indices(&self) -> Vec<u32> {
+        self.parts.values().flat_map(|part| part.memory_indices()).collect()
+    }
+
+    /// The content of the parts received so far, in order, with nothing marking the gaps
+    pub fn partial_content(&self) -> String {
+        self.parts.values().map(|part| part.content()).collect()
+    }
+
+    /// When the first part of this message arrived
+    pub fn first_received(&self) -> Instant {
+        self.first_received
+    }
+}
+
+/// Joins the parts of concatenated messages back into one message each
+///
+/// Parts are matched on sender, reference and part count, and can arrive in any order.
+pub struct Reassembler {
+    pending: HashMap<(String, ConcatReference, u8), IncompleteMessage>,
+    timeout: Duration
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Reassembler { pending: HashMap::new(), timeout: timeout }
+    }
+
+    /// Adds a received message, returning the full message once every part has arrived
+    ///
+    /// Messages that aren't part of a concatenated message are returned straight away.
+    /// A part that was already received is ignored.
+    pub fn push(&mut self, deliver: &SmsDeliver, mem_index: u32) -> Option<SmsMessage> {
+        self.insert(deliver, SmsMessage::from_deliver(deliver, mem_index))
+    }
+
+    /// Same as `push`, keeping the status and service centre from storage
+    ///
+    /// Anything but an SMS-DELIVER is ignored and returns `None`.
+    pub fn push_stored(&mut self, stored: &StoredPdu) -> Option<SmsMessage> {
+        let Tpdu::Deliver(deliver) = &stored.pdu.tpdu else { return None };
+        self.insert(deliver, SmsMessage::from_stored_pdu(stored)?)
+    }
+
+    /// Same as `push`, for a message routed straight to us with `+CMT` which has no memory index
+    pub fn push_direct(&mut self, deliver: &SmsDeliver) -> Option<SmsMessage> {
+        self.insert(deliver, SmsMessage::from_direct_deliver(deliver))
+    }
+
+    fn insert(&mut self, deliver: &SmsDeliver, message: SmsMessage) -> Option<SmsMessage> {
+        let Some(concatenation) = deliver.user_data.header.as_ref().and_then(|header| header.concatenation()) else { return Some(message) };
+        if concatenation.total <= 1 {
+            return Some(message)
+        }
+
+        let key = (message.address(), concatenation.reference, concatenation.total);
+        let incomplete = self.pending.entry(key.clone()).or_insert_with(|| IncompleteMessage {
+            address: key.0.clone(),
+            reference: concatenation.reference,
+            total: concatenation.total,
+            parts: BTreeMap::new(),
+            first_received: Instant::now()
+        });
+        incomplete.parts.entry(concatenation.sequence).or_insert(message);
+
+        if incomplete.parts.len() < concatenation.total as usize {
+            return None
+        }
+
+        let complete = self.pending.remove(&key)?;
+        SmsMessage::from_parts(complete.parts.into_values().collect())
+    }
+
+    /// Messages still waiting on parts
+    pub fn pending(&self) -> Vec<&IncompleteMessage> {
+        self.pending.values().collect()
+    }
+
+    /// Gives up on messages whose first part arrived longer than the timeout ago, returning them
+    pub fn expire(&mut self) -> Vec<IncompleteMessage> {
+        let timeout = self.timeout;
+        let expired: Vec<_> = self.pending.iter()
+            .filter(|(_, incomplete)| incomplete.first_received.elapsed() >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired.iter().filter_map(|key| self.pending.remove(key)).collect()
+    }
+
+    /// Gives up on every message still waiting on parts, returning them
+    pub fn take_incomplete(&mut self) -> Vec<IncompleteMessage> {
+        self.pending.drain().map(|(_, incomplete)| incomplete).collect()
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
@@ -1,9 +1,9 @@
-use std::{collections::HashMap, error::Error, fmt};
+use std::{fmt, time::Duration};
 
 use chrono::{format::Fixed, DateTime, FixedOffset, Utc};
 use regex::Regex;
 
-use crate::utils::{hex_to_utf16, timestamp_to_iso_8601};
+use crate::{concat::DEFAULT_REASSEMBLY_TIMEOUT, error::{Error, Result}, error_codes::{CmeErrorCode, CmsErrorCode, ErrorCategory}, pdu::{Alphabet, ConcatReference, DataCodingScheme, MessageWaiting, SmsDeliver, StoredPdu, Tpdu, UserData, UserDataBody, UserDataHeader, ValidityPeriod}, phone_number::PhoneNumber, utils::{bytes_to_hex, datetime_to_timestamp, hex_to_bytes, hex_to_utf16, timestamp_to_iso_8601}};
 
 
 impl Into<u8> for SmsStatus {
@@ -37,19 +37,19 @@ impl Into<u8> for SmsFormat {
 }
 
 impl TryFrom<String> for SmsFormat {
-    type Error = Box<dyn Error>;
+    type Error = Error;
 
     /// Take a string that is a "1" or "0" and convert it into the SmsFormat enum
-    fn try_from(mode: String) -> Result<SmsFormat, Box<dyn Error>> {
-        let mode_int: isize = mode.trim().parse()?;
-        match mode_int {
-            0 => Ok(SmsFormat::ProtocolDataUnit),
-            1 => Ok(SmsFormat::Text),
-            _ => Err("Failed to parse given SMS Format!".into())
+    fn try_from(mode: String) -> Result<SmsFormat> {
+        match mode.trim() {
+            "0" => Ok(SmsFormat::ProtocolDataUnit),
+            "1" => Ok(SmsFormat::Text),
+            _ => Err(Error::parse("Failed to parse given SMS Format!", &mode))
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SmsStatus {
     ReceivedUnread,
     ReceivedRead,
@@ -70,25 +70,248 @@ impl SmsStatus {
         }
     }
 
-    pub fn try_from_text_status(&self, status: String) -> Result<SmsStatus, Box<dyn Error>> {
+    pub fn try_from_text_status(status: String) -> Result<SmsStatus> {
         match status.as_str() {
             "REC UNREAD" => Ok(SmsStatus::ReceivedUnread),
             "REC READ" => Ok(SmsStatus::ReceivedRead),
             "STO UNSENT" => Ok(SmsStatus::StoredUnsent),
             "STO SENT" => Ok(SmsStatus::StoredSent),
             "ALL" => Ok(SmsStatus::All),
-            _ => Err("Failed to parse text SMS status!".into())
+            _ => Err(Error::parse("Failed to parse text SMS status!", &status))
         }
     }
 
-    pub fn try_from_pdu_status(&self, status: u8) -> Result<SmsStatus, Box<dyn Error>> {
+    pub fn try_from_pdu_status(status: u8) -> Result<SmsStatus> {
         match status {
             0 => Ok(SmsStatus::ReceivedUnread),
             1 => Ok(SmsStatus::ReceivedRead),
             2 => Ok(SmsStatus::StoredUnsent),
             3 => Ok(SmsStatus::StoredSent),
             4 => Ok(SmsStatus::All),
-            _ => Err("Failed to parse PDU SMS status!".into())
+            _ => Err(Error::parse("Failed to parse PDU SMS status!", &status.to_string()))
+        }
+    }
+}
+
+/// Options for sending a message
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions {
+    /// Ask the service centre for a status report (TP-SRR) once the message is delivered or given up on
+    pub status_report: bool,
+    /// Reference to use if the content needs several parts, a new 8-bit reference is picked when `None`
+    pub concat_reference: Option<ConcatReference>,
+    /// How long the service centre keeps trying to deliver the message
+    ///
+    /// When `None`, text mode sends with the modem's default of 24 hours and PDU mode leaves it to the service centre.
+    pub validity_period: Option<ValidityPeriod>,
+    /// Message class from 0 to 3, ie. 0 for a flash message shown straight away without being stored
+    pub class: Option<u8>
+}
+
+impl SendOptions {
+    /// The data coding scheme for content in the given alphabet, with the message class if one is set
+    pub(crate) fn data_coding_scheme(&self, alphabet: Alphabet) -> Result<DataCodingScheme> {
+        if self.class.is_some_and(|class| class > 3) {
+            return Err(Error::invalid_argument("Message class must be from 0 to 3!"))
+        }
+
+        Ok(DataCodingScheme::new(alphabet, self.class))
+    }
+}
+
+/// Buffering of indications while the link to the modem is busy (`<mode>` of `AT+CNMI`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndicationMode {
+    /// Keep every indication in the modem, dropping the oldest once its buffer is full
+    Buffer = 0,
+    /// Drop indications that arrive while the link is busy, otherwise send them straight away
+    DiscardWhenBusy = 1,
+    /// Buffer indications while the link is busy and send them once it's free
+    BufferAndFlush = 2
+}
+
+/// How new messages (`<mt>`) and cell broadcasts (`<bm>`) are indicated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageIndication {
+    /// Not indicated at all
+    Disabled = 0,
+    /// Stored, with the memory index sent as `+CMTI` (or `+CBMI`)
+    StoredIndex = 1,
+    /// Sent straight to us as `+CMT` (or `+CBM`) without being stored, except class 2 messages which go to the SIM
+    Direct = 2,
+    /// Only class 3 messages are sent straight to us, everything else is stored and indexed
+    ClassThreeDirect = 3
+}
+
+/// How status reports are indicated (`<ds>`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusReportIndication {
+    Disabled = 0,
+    /// Sent straight to us as `+CDS`
+    Direct = 1,
+    /// Stored, with the memory index sent as `+CDSI`
+    StoredIndex = 2
+}
+
+/// How the modem tells us about new messages, set with `AT+CNMI`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NewMessageIndications {
+    pub mode: IndicationMode,
+    pub messages: MessageIndication,
+    pub broadcasts: MessageIndication,
+    pub status_reports: StatusReportIndication,
+    /// Whether indications buffered by the modem are sent (rather than dropped) when `mode` is set to something other than `Buffer`
+    pub flush_buffer: bool
+}
+
+impl NewMessageIndications {
+    /// Parses the response of `AT+CNMI?`
+    pub fn from_cnmi(raw_string: String) -> Result<NewMessageIndications> {
+        let captures = Regex::new(r"\+CNMI: (\d),(\d),(\d),(\d),(\d)").unwrap().captures(&raw_string).ok_or_else(|| Error::parse("Failed to parse new message indications!", &raw_string))?;
+        let value = |i: usize| captures[i].parse::<u8>().unwrap();
+
+        let mode = match value(1) {
+            0 => IndicationMode::Buffer,
+            1 => IndicationMode::DiscardWhenBusy,
+            2 => IndicationMode::BufferAndFlush,
+            _ => return Err(Error::parse("Failed to parse indication mode!", &raw_string))
+        };
+        let message_indication = |value: u8| match value {
+            0 => Ok(MessageIndication::Disabled),
+            1 => Ok(MessageIndication::StoredIndex),
+            2 => Ok(MessageIndication::Direct),
+            3 => Ok(MessageIndication::ClassThreeDirect),
+            _ => Err(Error::parse("Failed to parse message indication!", &raw_string))
+        };
+        let status_reports = match value(4) {
+            0 => StatusReportIndication::Disabled,
+            1 => StatusReportIndication::Direct,
+            2 => StatusReportIndication::StoredIndex,
+            _ => return Err(Error::parse("Failed to parse status report indication!", &raw_string))
+        };
+
+        Ok(NewMessageIndications {
+            mode: mode,
+            messages: message_indication(value(2))?,
+            broadcasts: message_indication(value(3))?,
+            status_reports: status_reports,
+            flush_buffer: value(5) == 0
+        })
+    }
+
+    /// The `AT+CNMI` command applying these settings
+    pub fn to_command(&self) -> String {
+        format!(
+            "AT+CNMI={},{},{},{},{}\r",
+            self.mode as u8, self.messages as u8, self.broadcasts as u8, self.status_reports as u8, if self.flush_buffer { 0 } else { 1 }
+        )
+    }
+}
+
+impl Default for NewMessageIndications {
+    /// Index notifications for new messages only, buffered while the link is busy
+    fn default() -> Self {
+        NewMessageIndications {
+            mode: IndicationMode::BufferAndFlush,
+            messages: MessageIndication::StoredIndex,
+            broadcasts: MessageIndication::Disabled,
+            status_reports: StatusReportIndication::Disabled,
+            flush_buffer: true
+        }
+    }
+}
+
+/// Options for `GsmModem::spawn_message_pipeline`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessagePipelineOptions {
+    /// Acknowledge every `+CMT` with `AT+CNMA`, which the modem expects after `AT+CSMS=1`
+    pub acknowledge: bool,
+    /// How long to wait for the rest of a concatenated message before giving up on it
+    pub reassembly_timeout: Duration
+}
+
+impl Default for MessagePipelineOptions {
+    fn default() -> Self {
+        MessagePipelineOptions { acknowledge: true, reassembly_timeout: DEFAULT_REASSEMBLY_TIMEOUT }
+    }
+}
+
+/// Parameters for messages sent in text mode, set with `AT+CSMP`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmsParameters {
+    /// The TP first octet of an SMS-SUBMIT (ie. `17`), with TP-SRR (`0x20`) to request status reports
+    ///
+    /// Its validity period format bits are ignored, they always follow `validity_period`.
+    pub first_octet: u8,
+    /// `None` sends messages without a validity period, leaving it to the service centre
+    pub validity_period: Option<ValidityPeriod>,
+    pub protocol_identifier: u8,
+    pub data_coding_scheme: DataCodingScheme
+}
+
+impl SmsParameters {
+    /// TP-VPF bits of the first octet
+    const VALIDITY_FORMAT_MASK: u8 = 0x18;
+
+    /// Parses the response of `AT+CSMP?`
+    pub fn from_csmp(raw_string: String) -> Result<SmsParameters> {
+        let captures = Regex::new(r#"\+CSMP: (\d+),(?:(\d+)|"([^"]*)")?,(\d+),(\d+)"#).unwrap().captures(&raw_string).ok_or_else(|| Error::parse("Failed to parse SMS parameters!", &raw_string))?;
+        let value = |i: usize| captures[i].parse::<u8>().map_err(|_| Error::parse("Failed to parse SMS parameters!", &raw_string));
+
+        let first_octet = value(1)?;
+        let relative = captures.get(2).and_then(|relative| relative.as_str().parse::<u8>().ok());
+        // Some modems give the string parameters as UCS2 hex with AT+CSCS="UCS2"
+        let string = captures.get(3).map(|string| hex_to_utf16(string.as_str()).ok().filter(|decoded| decoded.contains('/')).unwrap_or_else(|| String::from(string.as_str())));
+
+        let validity_period = match (first_octet & SmsParameters::VALIDITY_FORMAT_MASK) >> 3 {
+            0b00 => None,
+            0b10 => Some(ValidityPeriod::from_relative_value(relative.ok_or_else(|| Error::parse("Failed to parse relative validity period!", &raw_string))?)),
+            0b11 => {
+                let timestamp = timestamp_to_iso_8601(string.as_deref().unwrap_or_default())?;
+                let timestamp = DateTime::parse_from_rfc3339(&timestamp).map_err(|_| Error::parse("Failed to parse absolute validity period!", &raw_string))?;
+
+                Some(ValidityPeriod::Absolute(timestamp))
+            }
+            _ => {
+                let octets = hex_to_bytes(string.as_deref().unwrap_or_default())?;
+                let octets = octets.try_into().map_err(|_| Error::parse("Failed to parse enhanced validity period!", &raw_string))?;
+
+                Some(ValidityPeriod::Enhanced(octets))
+            }
+        };
+
+        Ok(SmsParameters {
+            first_octet: first_octet,
+            validity_period: validity_period,
+            protocol_identifier: value(4)?,
+            data_coding_scheme: DataCodingScheme(value(5)?)
+        })
+    }
+
+    /// The `AT+CSMP` command applying these parameters
+    pub fn to_command(&self) -> String {
+        let format_bits = self.validity_period.as_ref().map(|validity| validity.format_bits()).unwrap_or_default();
+        let first_octet = (self.first_octet & !SmsParameters::VALIDITY_FORMAT_MASK) | (format_bits << 3);
+
+        let validity_period = match &self.validity_period {
+            None => String::new(),
+            Some(validity @ ValidityPeriod::Relative(_)) => validity.relative_value().unwrap_or_default().to_string(),
+            Some(ValidityPeriod::Absolute(timestamp)) => format!("\"{}\"", datetime_to_timestamp(timestamp)),
+            Some(ValidityPeriod::Enhanced(octets)) => format!("\"{}\"", bytes_to_hex(octets))
+        };
+
+        format!("AT+CSMP={},{},{},{}\r", first_octet, validity_period, self.protocol_identifier, self.data_coding_scheme.0)
+    }
+}
+
+impl Default for SmsParameters {
+    /// The modem defaults, an SMS-SUBMIT valid for 24 hours with the default alphabet
+    fn default() -> Self {
+        SmsParameters {
+            first_octet: 17,
+            validity_period: Some(ValidityPeriod::from_relative_value(167)),
+            protocol_identifier: 0,
+            data_coding_scheme: DataCodingScheme(0)
         }
     }
 }
@@ -107,112 +330,341 @@ impl SmsStatus {
 // }
 
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SmsMessage {
     mem_index: u32,
+    /// Memory index of every part, in order, for messages reassembled from several parts
+    ///
+    /// Empty for messages routed straight to us with `+CMT`, which never touch storage.
+    part_indices: Vec<u32>,
+    status: SmsStatus,
+    /// The sender of received messages, or the recipient of outgoing ones
     address: String,
+    /// Name of the address in the phonebook, if the modem found one
+    alpha: Option<String>,
+    service_centre: Option<String>,
     content: String,
-    timestamp: DateTime<FixedOffset>
+    /// Only known in PDU mode, or in text mode with `AT+CSDH=1`
+    data_coding_scheme: Option<DataCodingScheme>,
+    /// Only available in PDU mode
+    user_data_header: Option<UserDataHeader>,
+    /// When the service centre received the message, outgoing messages have none
+    timestamp: Option<DateTime<FixedOffset>>
 }
 
 impl SmsMessage {
     /// Takes the modem output of AT+CMGR (getting a single message) and returns a SmsMessages struct
-    pub fn from_cmgr(raw_string: String, mem_index: u32) -> Result<SmsMessage, Box<dyn Error>> {
-        let msg_captures = Regex::new(r#"\+CMGR: "([A-Z ]*)","([0-9A-F]*)","","(\d{2}/\d{2}/\d{2},\d{2}:\d{2}:\d{2}-\d{0,3})"\r\n([0-9A-F]*)\r\n\r\nOK\r\n"#)?.captures(&raw_string).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse SMS message!")).unwrap();
-
-        let address = hex_to_utf16(msg_captures.get(2).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse phone number in SMS message!").unwrap())?.as_str())?;
+    ///
+    /// Handles both received and outgoing (`STO`) messages, with or without the extra header fields of `AT+CSDH=1`.
+    pub fn from_cmgr(raw_string: String, mem_index: u32) -> Result<SmsMessage> {
+        let mut lines = raw_string.split("\r\n").skip_while(|line| !line.starts_with("+CMGR: "));
 
-        let timestamp_iso8601 = timestamp_to_iso_8601(msg_captures.get(3).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse message timezone in SMS message!").unwrap())?.as_str())?;
+        let header = lines.next().ok_or_else(|| Error::parse("Failed to parse SMS message!", &raw_string))?;
+        let body = lines.next().ok_or_else(|| Error::parse("Failed to parse message content in SMS message!", &raw_string))?;
 
-        let timestamp = DateTime::parse_from_rfc3339(&timestamp_iso8601)?;
-
-        let content = hex_to_utf16(msg_captures.get(4).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse message content in SMS message!").unwrap())?.as_str())?;
-
-        Ok(SmsMessage { mem_index: mem_index, address: address, content: content, timestamp: timestamp })
-        
+        SmsMessage::from_text_header(&header["+CMGR: ".len()..], body, mem_index, &raw_string)
     }
 
     /// Takes the modem output of AT+CMGL (listing of multiple messages) and returns a vec of SmsMessages
-    pub fn from_cmgl(raw_string: String) -> Result<Vec<SmsMessage>, Box<dyn Error>> {
-        let msg_regex= Regex::new(r#"\+CMGL: (\d{0,3}),"[A-Z ]*","([0-9A-F]*)","","(\d{2}/\d{2}/\d{2},\d{2}:\d{2}:\d{2}-\d{0,3})"\r\n([0-9A-F]*)\r\n"#)?;
-
-        let msg_captures = msg_regex.captures_iter(&raw_string);
+    pub fn from_cmgl(raw_string: String) -> Result<Vec<SmsMessage>> {
+        let mut lines = raw_string.split("\r\n");
 
         let mut messages: Vec<SmsMessage> = Vec::new();
-        for msg_capture in msg_captures {
-            let index = msg_capture.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse message memory index!").unwrap())?.as_str().parse::<u32>()?;
+        while let Some(line) = lines.next() {
+            let Some(header) = line.strip_prefix("+CMGL: ") else { continue };
+            let body = lines.next().ok_or_else(|| Error::parse("Failed to parse message content in SMS message!", &raw_string))?;
+
+            // Same as the header of AT+CMGR, with the memory index in front
+            let (index, header) = header.split_once(',').ok_or_else(|| Error::parse("Failed to parse SMS message!", &raw_string))?;
+            let index = index.parse::<u32>().map_err(|_| Error::parse("Failed to parse message memory index!", &raw_string))?;
 
-            let address = hex_to_utf16(msg_capture.get(2).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse phone number in SMS message!").unwrap())?.as_str())?;
+            messages.push(SmsMessage::from_text_header(header, body, index, &raw_string)?);
+        }
+
+        Ok(messages)
+    }
 
-            let timestamp_iso8601 = timestamp_to_iso_8601(msg_capture.get(3).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse message timezone in SMS message!").unwrap())?.as_str())?;
+    /// Builds a message from a text mode header (after `+CMGR: ` or the index of `+CMGL: `) and the line after it
+    ///
+    /// The fields are `<stat>,<address>,[<alpha>]`, then for received messages `,<scts>`, then any of
+    /// `<tooa/toda>,<fo>,<pid>,<dcs>,...` that `AT+CSDH=1` (or the `AT+CMGL` format) adds.
+    fn from_text_header(header: &str, body: &str, mem_index: u32, raw_string: &str) -> Result<SmsMessage> {
+        let fields = split_fields(header);
+        let field = |i: usize| fields.get(i).map(|field| field.as_str()).unwrap_or_default();
+
+        let status = SmsStatus::try_from_text_status(String::from(field(0)))?;
+        let address = hex_to_utf16(field(1))?;
+        let alpha = Some(field(2)).filter(|alpha| !alpha.is_empty()).map(|alpha| hex_to_utf16(alpha).unwrap_or_else(|_| String::from(alpha)));
+
+        let (timestamp, details) = match status {
+            SmsStatus::ReceivedUnread | SmsStatus::ReceivedRead => {
+                let timestamp_iso8601 = timestamp_to_iso_8601(field(3))?;
+                let timestamp = DateTime::parse_from_rfc3339(&timestamp_iso8601).map_err(|_| Error::parse("Failed to parse message timestamp in SMS message!", raw_string))?;
+
+                (Some(timestamp), fields.get(4..).unwrap_or_default())
+            }
+            // Outgoing messages have no timestamp, though AT+CMGL still leaves an empty field for it
+            _ => match fields.get(3) {
+                Some(field) if field.is_empty() => (None, fields.get(4..).unwrap_or_default()),
+                _ => (None, fields.get(3..).unwrap_or_default())
+            }
+        };
+
+        // With AT+CSDH=1: <tooa/toda>,<fo>,<pid>,<dcs>, then the validity period (outgoing only), <sca>,<tosca>,<length>
+        let data_coding_scheme = details.get(3).and_then(|dcs| dcs.parse::<u8>().ok()).map(DataCodingScheme);
+        let service_centre_index = if status == SmsStatus::ReceivedUnread || status == SmsStatus::ReceivedRead { 4 } else { 5 };
+        let service_centre = details.get(service_centre_index)
+            .filter(|sca| !sca.is_empty())
+            .map(|sca| hex_to_utf16(sca).unwrap_or_else(|_| sca.clone()));
+
+        Ok(SmsMessage {
+            mem_index: mem_index,
+            part_indices: vec![mem_index],
+            status: status,
+            address: address,
+            alpha: alpha,
+            service_centre: service_centre,
+            content: hex_to_utf16(body)?,
+            data_coding_scheme: data_coding_scheme,
+            user_data_header: None,
+            timestamp: timestamp
+        })
+    }
 
-            let timestamp = DateTime::parse_from_rfc3339(&timestamp_iso8601)?;
+    /// Builds a message from a PDU-mode SMS-DELIVER, binary messages have their content as hex
+    ///
+    /// The status is always `ReceivedUnread`, use `from_stored_pdu` to keep the status from storage.
+    pub fn from_deliver(deliver: &SmsDeliver, mem_index: u32) -> SmsMessage {
+        SmsMessage {
+            mem_index: mem_index,
+            part_indices: vec![mem_index],
+            status: SmsStatus::ReceivedUnread,
+            address: deliver.originator.to_string(),
+            alpha: None,
+            service_centre: None,
+            content: user_data_content(&deliver.user_data),
+            data_coding_scheme: Some(deliver.data_coding_scheme),
+            user_data_header: deliver.user_data.header.clone(),
+            timestamp: Some(deliver.service_centre_timestamp)
+        }
+    }
 
-            let content = hex_to_utf16(msg_capture.get(4).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse message content in SMS message!").unwrap())?.as_str())?;
+    /// Builds a message from a PDU read from storage, received or outgoing
+    ///
+    /// Returns `None` for status reports, which aren't messages.
+    pub fn from_stored_pdu(stored: &StoredPdu) -> Option<SmsMessage> {
+        let mut message = match &stored.pdu.tpdu {
+            Tpdu::Deliver(deliver) => SmsMessage::from_deliver(deliver, stored.index),
+            Tpdu::Submit(submit) => SmsMessage {
+                mem_index: stored.index,
+                part_indices: vec![stored.index],
+                status: stored.status,
+                address: submit.destination.to_string(),
+                alpha: None,
+                service_centre: None,
+                content: user_data_content(&submit.user_data),
+                data_coding_scheme: Some(submit.data_coding_scheme),
+                user_data_header: submit.user_data.header.clone(),
+                timestamp: None
+            },
+            Tpdu::StatusReport(_) => return None
+        };
+        message.status = stored.status;
+        message.service_centre = stored.pdu.smsc.as_ref().map(|smsc| smsc.to_string());
+
+        Some(message)
+    }
 
-            messages.push(SmsMessage { mem_index: index, address: address, content: content, timestamp: timestamp });
+    /// Builds a message routed straight to us with `+CMT` in text mode, which has no memory index
+    pub(crate) fn direct(address: String, content: String, timestamp: DateTime<FixedOffset>) -> SmsMessage {
+        SmsMessage {
+            mem_index: 0,
+            part_indices: Vec::new(),
+            status: SmsStatus::ReceivedUnread,
+            address: address,
+            alpha: None,
+            service_centre: None,
+            content: content,
+            data_coding_scheme: None,
+            user_data_header: None,
+            timestamp: Some(timestamp)
         }
+    }
 
-        Ok(messages)
+    /// Same as `from_deliver`, for an SMS-DELIVER routed straight to us with `+CMT`
+    pub(crate) fn from_direct_deliver(deliver: &SmsDeliver) -> SmsMessage {
+        let mut message = SmsMessage::from_deliver(deliver, 0);
+        message.part_indices.clear();
+
+        message
+    }
+
+    /// Joins the parts of a concatenated message, which must be in order
+    ///
+    /// Everything but the content and memory indices is taken from the first part.
+    pub(crate) fn from_parts(parts: Vec<SmsMessage>) -> Option<SmsMessage> {
+        let first = parts.first()?;
+
+        Some(SmsMessage {
+            part_indices: parts.iter().flat_map(|part| part.part_indices.iter().copied()).collect(),
+            content: parts.iter().map(|part| part.content.as_str()).collect(),
+            ..first.clone()
+        })
     }
 
-    /// Returns the message memory index
+    /// Returns the message memory index, meaningless if the message isn't stored (see `is_stored`)
     pub fn memory_index(&self) -> u32 {
         self.mem_index
     }
 
-    /// Returns the message's associated address (phone number)
+    /// Returns the memory index of every part, a single index unless the message was reassembled from several parts
+    pub fn memory_indices(&self) -> Vec<u32> {
+        self.part_indices.clone()
+    }
+
+    /// Whether the message is in storage, messages routed straight to us with `+CMT` aren't
+    pub fn is_stored(&self) -> bool {
+        !self.part_indices.is_empty()
+    }
+
+    pub fn status(&self) -> SmsStatus {
+        self.status
+    }
+
+    /// Whether the message is one of ours waiting to be sent or already sent, rather than one we received
+    pub fn is_outgoing(&self) -> bool {
+        matches!(self.status, SmsStatus::StoredUnsent | SmsStatus::StoredSent)
+    }
+
+    /// Returns the message's associated address (phone number), the sender or the recipient if outgoing
     pub fn address(&self) -> String {
         self.address.clone()
     }
 
+    /// The address as a phone number, `None` for alphanumeric senders (ie. `ACME`)
+    pub fn phone_number(&self) -> Option<PhoneNumber> {
+        PhoneNumber::parse(&self.address).ok()
+    }
+
+    /// Returns the phonebook name of the address, if the modem found one
+    pub fn alpha(&self) -> Option<String> {
+        self.alpha.clone()
+    }
+
+    /// Returns the service centre the message came through, if the modem reported it
+    pub fn service_centre(&self) -> Option<String> {
+        self.service_centre.clone()
+    }
+
     /// Returns the message content
     pub fn content(&self) -> String {
         self.content.clone()
     }
 
-    /// Returns the message timestamp
-    pub fn timestamp(&self) -> DateTime<FixedOffset> {
+    /// Returns the data coding scheme, if the modem reported it
+    pub fn data_coding_scheme(&self) -> Option<DataCodingScheme> {
+        self.data_coding_scheme
+    }
+
+    /// Returns the alphabet the message was sent in over the air, if the modem reported it
+    pub fn encoding(&self) -> Option<Alphabet> {
+        self.data_coding_scheme.map(|dcs| dcs.alphabet())
+    }
+
+    /// Returns the message class (0 for flash messages up to 3), if it has one
+    pub fn class(&self) -> Option<u8> {
+        self.data_coding_scheme.and_then(|dcs| dcs.class())
+    }
+
+    /// Whether this is a flash (class 0) message, meant to be shown straight away rather than stored
+    pub fn is_flash(&self) -> bool {
+        self.class() == Some(0)
+    }
+
+    /// Every message waiting indication the message carries (ie. new voicemail)
+    ///
+    /// Indications in the user data header come with a count and take precedence over one of the
+    /// same kind in the data coding scheme.
+    pub fn message_waiting(&self) -> Vec<MessageWaiting> {
+        let mut indications = self.user_data_header.as_ref().map(|header| header.message_waiting()).unwrap_or_default();
+
+        if let Some(indication) = self.data_coding_scheme.and_then(|dcs| dcs.message_waiting())
+            && !indications.iter().any(|existing| existing.kind == indication.kind) {
+            indications.push(indication);
+        }
+
+        indications
+    }
+
+    /// Returns the user data header, only ever read in PDU mode
+    pub fn user_data_header(&self) -> Option<&UserDataHeader> {
+        self.user_data_header.as_ref()
+    }
+
+    /// Returns the message timestamp, outgoing messages have none
+    pub fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
         self.timestamp
     }
 }
 
 impl fmt::Display for SmsMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Memory: {}\nAddress: {}\nContent: \"{}\"\nTimestamp: {}", self.mem_index, self.address, self.content, self.timestamp)
+        let timestamp = self.timestamp.map(|timestamp| timestamp.to_string()).unwrap_or_else(|| String::from("None"));
+
+        write!(f, "Memory: {}\nStatus: {}\nAddress: {}\nContent: \"{}\"\nTimestamp: {}", self.mem_index, self.status.as_str(), self.address, self.content, timestamp)
     }
 }
 
-
-pub enum ResultCodes {
-    Ok,
-    Error,
-    ErrorAndCode,
-    AwaitingInput,
+/// The content of user data, with binary content as hex
+fn user_data_content(user_data: &UserData) -> String {
+    match &user_data.body {
+        UserDataBody::Text(text) => text.clone(),
+        UserDataBody::Binary(data) => bytes_to_hex(data)
+    }
 }
 
-impl ResultCodes {
-    pub fn as_regex_str(&self) -> &'static str {
-        match self {
-            ResultCodes::Ok => r"\r\nOK\r\n",
-            ResultCodes::Error => r"\r\nERROR\r\n",
-            // Capture both CMS & CME error codes - see "3.3 Summary of CME ERROR codes" & "3.4 Summary of CMS ERROR codes"
-            ResultCodes::ErrorAndCode => r"\r\n\+(CM(?:E|S)) ERROR: (\d{1,3})\r\n",
-            ResultCodes::AwaitingInput => r"\r\n> ",
+/// Splits the fields of a text mode header on commas outside quotes, removing the quotes
+fn split_fields(header: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+
+    for c in header.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c)
         }
     }
 
-    /// Concats the regex strs for both generic errors and typed errors
-    /// 
-    /// Still handles captures for type/code if typed error is thrown (ie. CME or CMS)
-    /// *concating regex prob isn't a greaaaaaat idea but this works for now*
-    pub fn get_error_catchall() -> String {
-        format!("({})|({})", ResultCodes::Error.as_regex_str(), ResultCodes::ErrorAndCode.as_regex_str())
+    fields
+}
+
+/// How long to wait for a command's final result code when nothing more specific applies
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the default deadline for a command
+///
+/// Most commands are answered straight away, but some wait on the network or SIM and can take far longer
+pub fn default_command_timeout(command: &str) -> Duration {
+    let command = command.trim().to_uppercase();
+
+    if command.starts_with("AT+COPS=?") {
+        // Scanning for every available operator can take minutes
+        Duration::from_secs(180)
+    } else if command.starts_with("AT+COPS") || command.starts_with("AT+CUSD") {
+        Duration::from_secs(120)
+    } else if command.starts_with("AT+CMGS") || command.starts_with("AT+CMSS") || command.starts_with("ATD") {
+        Duration::from_secs(60)
+    } else if command.starts_with("AT+CMGL") || command.starts_with("AT+CMGD") || command.starts_with("AT+CPMS") {
+        // Scales with how many messages are in storage
+        Duration::from_secs(30)
+    } else {
+        DEFAULT_COMMAND_TIMEOUT
     }
 }
 
 /// Result codes that are unsolicited and happen async
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UnsolicitedResultCode {
     /// The modem is ready to begin taking commands
     Ready,
@@ -240,30 +692,65 @@ pub enum UnsolicitedResultCode {
 
     /// SMS storage is full and needs to be cleared
     SmsFull,
+
+    /// A status report for a sent message, in the line itself in text mode or as a PDU on the next line
+    StatusReport,
+
+    /// A status report was saved to storage
+    StatusReportIndex,
+
+    /// A new SMS message routed straight to us without being stored, its content or PDU is on the next line
+    CMT,
+
+    /// A cell broadcast page routed straight to us, its content or PDU is on the next line
+    CBM,
+
+    /// A cell broadcast page was saved to storage
+    CBMI,
+
+    /// A USSD message from the network, answering a request or started by the network
+    Ussd,
 }
 
 impl UnsolicitedResultCode {
     /// Get the proper regex str for the corresponding URC
     /// 
+    /// Matched against a single line with its line ending stripped.
     /// If the URC returns data, it will (mostly) be captured by the regex str
     pub fn as_regex_str(&self) -> &'static str {
         match self {
-            UnsolicitedResultCode::Ready => r"RDY\r\n",
-            // Not entire sure what this digit is, memory location?
-            // TODO: find what digit this is
-            UnsolicitedResultCode::CMTI => r#"\+CMTI: "SM",(\d{1,3})\r\n"#,
-            UnsolicitedResultCode::Ring => r"RING\r\r\n",
+            UnsolicitedResultCode::Ready => r"^RDY$",
+            // Captures (1) the storage the message was saved to (ie. `SM`, or UCS2 hex on some modems) and (2) its memory index
+            UnsolicitedResultCode::CMTI => r#"^\+CMTI: "([A-Z]{2}|[0-9A-F]{8})",(\d{1,3})$"#,
+            UnsolicitedResultCode::Ring => r"^RING$",
             // Captures (1) the time the call was missed and (2) the number that called
             // Time format looks like it's 24H but still includes AM/PM which is weird
-            UnsolicitedResultCode::MissedCall => r"MISSED_CALL: (\d{2}:\d{2}[AP]M) (.*)\r\n",
-            UnsolicitedResultCode::NoCarrier => r"NO CARRIER\r\r\n",
-            UnsolicitedResultCode::VoiceCallBegin => r"VOICE CALL: BEGIN\r\n",
+            UnsolicitedResultCode::MissedCall => r"^MISSED_CALL: (\d{2}:\d{2}[AP]M) (.*)$",
+            UnsolicitedResultCode::NoCarrier => r"^NO CARRIER$",
+            UnsolicitedResultCode::VoiceCallBegin => r"^VOICE CALL: BEGIN$",
             // Captures the call time (in the format of HHMMSS)
-            UnsolicitedResultCode::VoiceCallEnd => r"VOICE CALL: END: (\d{6})",
-            // Will only extract the timezone digit, ignores other data
+            UnsolicitedResultCode::VoiceCallEnd => r"^VOICE CALL: END: (\d{6})$",
+            // Will only extract the timezone (in quarter hours from UTC), ignores other data
             // TODO: fully implement
-            UnsolicitedResultCode::TimeZoneChange => r"\r\n\+CTZV: (\d+)(?:,.*)?\r\n",
-            UnsolicitedResultCode::SmsFull => r"\r\n+SMS FULL\r\n"
+            UnsolicitedResultCode::TimeZoneChange => r"^\+CTZV: ([+-]?\d+)(?:,.*)?$",
+            UnsolicitedResultCode::SmsFull => r"^\+SMS FULL$",
+            // PDU mode only captures (1) the PDU length, the PDU itself is on the next line
+            // Text mode captures (1) first octet, (2) message reference, (3) recipient, (4) its type of address,
+            // (5) service centre timestamp, (6) discharge time and (7) status
+            UnsolicitedResultCode::StatusReport => r#"^\+CDS: (\d+)(?:,(\d+),"([^"]*)",(\d*),"([^"]+)","([^"]+)",(\d+))?$"#,
+            // Captures (1) the storage the report was saved to and (2) its memory index
+            UnsolicitedResultCode::StatusReportIndex => r#"^\+CDSI: "([A-Z]{2}|[0-9A-F]{8})",(\d{1,3})$"#,
+            // Text mode captures (1) the originator and (2) the service centre timestamp, ignoring the alpha and any
+            // header details, PDU mode only captures (3) the PDU length
+            UnsolicitedResultCode::CMT => r#"^\+CMT: (?:"([^"]*)",(?:"[^"]*")?,"([^"]+)"(?:,.*)?|(?:"[^"]*")?,(\d+))$"#,
+            // Text mode captures (1) serial number, (2) message identifier, (3) data coding scheme, (4) page and (5) pages,
+            // PDU mode only captures (6) the PDU length
+            UnsolicitedResultCode::CBM => r"^\+CBM: (?:(\d+),(\d+),(\d+),(\d+),(\d+)|(\d+))$",
+            // Captures (1) the storage the page was saved to and (2) its memory index
+            UnsolicitedResultCode::CBMI => r#"^\+CBMI: "([A-Z]{2}|[0-9A-F]{8})",(\d{1,3})$"#,
+            // Captures (1) the status, and optionally (2) the text and (3) its data coding scheme
+            // The text is UCS2 hex with AT+CSCS="UCS2", so a menu's line breaks never split the URC
+            UnsolicitedResultCode::Ussd => r#"^\+CUSD: (\d)(?:,"([^"]*)"(?:,(\d+))?)?$"#
         }
     }
 
@@ -281,13 +768,32 @@ impl UnsolicitedResultCode {
             UnsolicitedResultCode::VoiceCallEnd,
             UnsolicitedResultCode::TimeZoneChange,
             UnsolicitedResultCode::SmsFull,
+            UnsolicitedResultCode::StatusReport,
+            UnsolicitedResultCode::StatusReportIndex,
+            UnsolicitedResultCode::CMT,
+            UnsolicitedResultCode::CBM,
+            UnsolicitedResultCode::CBMI,
+            UnsolicitedResultCode::Ussd,
         ].iter().map(|&x| (x, Regex::new(x.as_regex_str()).unwrap())).collect()
 
-        
+
+    }
+
+    /// Whether the URC continues on the next line, given its first line
+    ///
+    /// The next line is attached to the URC's frame as its body, since it could look like anything.
+    pub fn expects_body(&self, line: &str) -> bool {
+        match self {
+            // Text mode status reports fit on one line, PDU mode ones only have the length there
+            UnsolicitedResultCode::StatusReport => !line.contains(','),
+            UnsolicitedResultCode::CMT | UnsolicitedResultCode::CBM => true,
+            _ => false
+        }
     }
 }
 
 /// Modem Error Types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ModemErrorType {
     CmeError,
     CmsError
@@ -304,6 +810,7 @@ impl ModemErrorType {
 }
 
 /// An error type for errors returned by the modem
+#[derive(Clone, Debug)]
 pub struct ModemError {
     e_type: ModemErrorType,
     code: i32,
@@ -312,172 +819,70 @@ pub struct ModemError {
 
 impl ModemError {
     /// Create a new error from an error type & code
-    /// 
-    /// Strings pulled from https://www.smssolutions.net/tutorials/gsm/gsmerrorcodes/
     pub fn new(e_type: ModemErrorType, code: i32) -> ModemError {
-        let cme_error_codes = HashMap::from([
-            (0, "Phone failure"),
-            (1, "No connection to phone"),
-            (2, "Phone adapter link reserved"),
-            (3, "Operation not allowed"),
-            (4, "Operation not supported"),
-            (5, "PH_SIM PIN required"),
-            (6, "PH_FSIM PIN required"),
-            (7, "PH_FSIM PUK required"),
-            (10, "SIM not inserted"),
-            (11, "SIM PIN required"),
-            (12, "SIM PUK required"),
-            (13, "SIM failure"),
-            (14, "SIM busy"),
-            (15, "SIM wrong"),
-            (16, "Incorrect password"),
-            (17, "SIM PIN2 required"),
-            (18, "SIM PUK2 required"),
-            (20, "Memory full"),
-            (21, "Invalid index"),
-            (22, "Not found"),
-            (23, "Memory failure"),
-            (24, "Text string too long"),
-            (25, "Invalid characters in text string"),
-            (26, "Dial string too long"),
-            (27, "Invalid characters in dial string"),
-            (30, "No network service"),
-            (31, "Network timeout"),
-            (32, "Network not allowed, emergency calls only"),
-            (40, "Network personalization PIN required"),
-            (41, "Network personalization PUK required"),
-            (42, "Network subset personalization PIN required"),
-            (43, "Network subset personalization PUK required"),
-            (44, "Service provider personalization PIN required"),
-            (45, "Service provider personalization PUK required"),
-            (46, "Corporate personalization PIN required"),
-            (47, "Corporate personalization PUK required"),
-            (48, "PH-SIM PUK required"),
-            (100, "Unknown error"),
-            (103, "Illegal MS"),
-            (106, "Illegal ME"),
-            (107, "GPRS services not allowed"),
-            (111, "PLMN not allowed"),
-            (112, "Location area not allowed"),
-            (113, "Roaming not allowed in this location area"),
-            (126, "Operation temporary not allowed"),
-            (132, "Service operation not supported"),
-            (133, "Requested service option not subscribed"),
-            (134, "Service option temporary out of order"),
-            (148, "Unspecified GPRS error"),
-            (149, "PDP authentication failure"),
-            (150, "Invalid mobile class"),
-            (256, "Operation temporarily not allowed"),
-            (257, "Call barred"),
-            (258, "Phone is busy"),
-            (259, "User abort"),
-            (260, "Invalid dial string"),
-            (261, "SS not executed"),
-            (262, "SIM Blocked"),
-            (263, "Invalid block"),
-            (772, "SIM powered down "),
-        ]);
-
-        let cms_error_codes = HashMap::from([
-            (1, "Unassigned number"),
-            (8, "Operator determined barring"),
-            (10, "Call bared"),
-            (21, "Short message transfer rejected"),
-            (27, "Destination out of service"),
-            (28, "Unindentified subscriber"),
-            (29, "Facility rejected"),
-            (30, "Unknown subscriber"),
-            (38, "Network out of order"),
-            (41, "Temporary failure"),
-            (42, "Congestion"),
-            (47, "Recources unavailable"),
-            (50, "Requested facility not subscribed"),
-            (69, "Requested facility not implemented"),
-            (81, "Invalid short message transfer reference value"),
-            (95, "Invalid message unspecified"),
-            (96, "Invalid mandatory information"),
-            (97, "Message type non existent or not implemented"),
-            (98, "Message not compatible with short message protocol"),
-            (99, "Information element non-existent or not implemente"),
-            (111, "Protocol error, unspecified"),
-            (127, "Internetworking , unspecified"),
-            (128, "Telematic internetworking not supported"),
-            (129, "Short message type 0 not supported"),
-            (130, "Cannot replace short message"),
-            (143, "Unspecified TP-PID error"),
-            (144, "Data code scheme not supported"),
-            (145, "Message class not supported"),
-            (159, "Unspecified TP-DCS error"),
-            (160, "Command cannot be actioned"),
-            (161, "Command unsupported"),
-            (175, "Unspecified TP-Command error"),
-            (176, "TPDU not supported"),
-            (192, "SC busy"),
-            (193, "No SC subscription"),
-            (194, "SC System failure"),
-            (195, "Invalid SME address"),
-            (196, "Destination SME barred"),
-            (197, "SM Rejected-Duplicate SM"),
-            (198, "TP-VPF not supported"),
-            (199, "TP-VP not supported"),
-            (208, "D0 SIM SMS Storage full"),
-            (209, "No SMS Storage capability in SIM"),
-            (210, "Error in MS"),
-            (211, "Memory capacity exceeded"),
-            (212, "Sim application toolkit busy"),
-            (213, "SIM data download error"),
-            (255, "Unspecified error cause"),
-            (300, "ME Failure"),
-            (301, "SMS service of ME reserved"),
-            (302, "Operation not allowed"),
-            (303, "Operation not supported"),
-            (304, "Invalid PDU mode parameter"),
-            (305, "Invalid Text mode parameter"),
-            (310, "SIM not inserted"),
-            (311, "SIM PIN required"),
-            (312, "PH-SIM PIN required"),
-            (313, "SIM failure"),
-            (314, "SIM busy"),
-            (315, "SIM wrong"),
-            (316, "SIM PUK required"),
-            (317, "SIM PIN2 required"),
-            (318, "SIM PUK2 required"),
-            (320, "Memory failure"),
-            (321, "Invalid memory index"),
-            (322, "Memory full"),
-            (330, "SMSC address unknown"),
-            (331, "No network service"),
-            (332, "Network timeout"),
-            (340, "No +CNMA expected"),
-            (500, "Unknown error"),
-            (512, "User abort"),
-            (513, "Unable to store"),
-            (514, "Invalid Status"),
-            (515, "Device busy or Invalid Character in string"),
-            (516, "Invalid length"),
-            (517, "Invalid character in PDU"),
-            (518, "Invalid parameter"),
-            (519, "Invalid length or character"),
-            (520, "Invalid character in text"),
-            (521, "Timer expired"),
-            (522, "Operation temporary not allowed"),
-            (532, "SIM not ready"),
-            (534, "Cell Broadcast error unknown"),
-            (535, "Protocol stack busy"),
-            (538, "Invalid parameter "),
-        ]);
-
-
         let text = match e_type {
-            ModemErrorType::CmsError => cms_error_codes.get(&code).map(|e| String::from(*e)),
-            ModemErrorType::CmeError => cme_error_codes.get(&code).map(|e| String::from(*e))
-        }.unwrap_or(String::from("Unrecognized error"));
+            ModemErrorType::CmsError => CmsErrorCode::from_code(code).description(),
+            ModemErrorType::CmeError => CmeErrorCode::from_code(code).description()
+        };
 
-        ModemError { e_type: e_type, code: code, text: text }
+        ModemError { e_type: e_type, code: code, text: String::from(text) }
     }
 
     /// Get the error as a String
     pub fn as_string(&self) -> String {
         return format!("{} Error: {}", self.e_type.as_str(), self.text)
-    } 
+    }
+
+    /// Whether this is a CME or CMS error
+    pub fn error_type(&self) -> ModemErrorType {
+        self.e_type
+    }
+
+    /// The numeric code returned by the modem
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// The description of the code
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The typed code, if this is a CME error
+    pub fn cme_code(&self) -> Option<CmeErrorCode> {
+        match self.e_type {
+            ModemErrorType::CmeError => Some(CmeErrorCode::from_code(self.code)),
+            ModemErrorType::CmsError => None
+        }
+    }
+
+    /// The typed code, if this is a CMS error
+    pub fn cms_code(&self) -> Option<CmsErrorCode> {
+        match self.e_type {
+            ModemErrorType::CmsError => Some(CmsErrorCode::from_code(self.code)),
+            ModemErrorType::CmeError => None
+        }
+    }
+
+    /// How the error should be handled (retry, prompt for a PIN, fix the request, give up)
+    pub fn category(&self) -> ErrorCategory {
+        match self.e_type {
+            ModemErrorType::CmeError => CmeErrorCode::from_code(self.code).category(),
+            ModemErrorType::CmsError => CmsErrorCode::from_code(self.code).category()
+        }
+    }
+
+    /// Whether the same command is worth retrying unchanged
+    pub fn is_retryable(&self) -> bool {
+        self.category().is_retryable()
+    }
+}
+
+impl fmt::Display for ModemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Error {}: {}", self.e_type.as_str(), self.code, self.text)
+    }
 }
+
+impl std::error::Error for ModemError {}
+
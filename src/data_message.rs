@@ -0,0 +1,96 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{constants::SmsMessage, pdu::{Alphabet, ApplicationPort}, utils::hex_to_bytes};
+
+/// A message addressed to an application port, meant for software rather than a person
+///
+/// These arrive as `ModemEvent::DataSmsReceived` instead of `ModemEvent::SmsReceived`, so they never show up
+/// as text (or in a `MessageArchive`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataMessage {
+    /// The sender, as the modem gave it
+    pub address: String,
+    pub port: ApplicationPort,
+    /// The payload, joined from every part. Port-addressed text messages have their text as UTF-8.
+    pub data: Vec<u8>,
+    /// When the service centre received the message
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    /// Memory index of every part, empty if the message was routed straight to us
+    pub memory_indices: Vec<u32>
+}
+
+impl DataMessage {
+    /// The data message carried by a received message, `None` if it isn't addressed to an application port
+    pub fn from_message(message: &SmsMessage) -> Option<DataMessage> {
+        let port = message.user_data_header()?.application_port()?;
+        let data = match message.encoding() {
+            // Binary content is kept as hex in `SmsMessage`
+            Some(Alphabet::EightBit) => hex_to_bytes(&message.content()).ok()?,
+            _ => message.content().into_bytes()
+        };
+
+        Some(DataMessage {
+            address: message.address(),
+            port: port,
+            data: data,
+            timestamp: message.timestamp(),
+            memory_indices: message.memory_indices()
+        })
+    }
+}
+
+type PortHandler = Box<dyn Fn(DataMessage) + Send + Sync>;
+
+/// Hands data messages to the handler registered for their destination port, see `GsmModem::spawn_port_router`
+///
+/// Handlers run on the router's task, so anything slow should be passed on (ie. over a channel) rather than done in place.
+#[derive(Default)]
+pub struct PortRouter {
+    handlers: HashMap<u16, PortHandler>,
+    fallback: Option<PortHandler>
+}
+
+impl PortRouter {
+    pub fn new() -> PortRouter {
+        PortRouter::default()
+    }
+
+    /// Handles messages addressed to `port`, replacing any handler already registered for it
+    pub fn with_handler<F: Fn(DataMessage) + Send + Sync + 'static>(mut self, port: u16, handler: F) -> Self {
+        self.handlers.insert(port, Box::new(handler));
+        self
+    }
+
+    /// Handles messages addressed to a port with no handler registered
+    pub fn with_fallback<F: Fn(DataMessage) + Send + Sync + 'static>(mut self, handler: F) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Ports with a handler registered
+    pub fn ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self.handlers.keys().copied().collect();
+        ports.sort();
+
+        ports
+    }
+
+    /// Passes the message to its port's handler (or the fallback), returning `false` if nothing handled it
+    pub fn dispatch(&self, message: DataMessage) -> bool {
+        match self.handlers.get(&message.port.destination).or(self.fallback.as_ref()) {
+            Some(handler) => {
+                handler(message);
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl fmt::Debug for PortRouter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PortRouter").field("ports", &self.ports()).field("fallback", &self.fallback.is_some()).finish()
+    }
+}
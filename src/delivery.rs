@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use chrono::{DateTime, FixedOffset, Utc};
+
+use crate::{pdu::{DeliveryState, DeliveryStatus, SmsStatusReport}, phone_number::PhoneNumber};
+
+/// How many sent messages are kept waiting on status reports, the oldest is dropped past this
+///
+/// Message references are only 8 bits, so tracking much more than this would just make matches ambiguous.
+const MAX_TRACKED_MESSAGES: usize = 256;
+
+/// A message sent with a status report requested
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentMessage {
+    pub destination: String,
+    pub content: String,
+    /// The message reference of every part, in order
+    pub message_references: Vec<u8>,
+    /// The latest status reported for every part, in order, `None` until the first report for that part
+    pub statuses: Vec<Option<DeliveryStatus>>,
+    pub sent_at: DateTime<Utc>
+}
+
+impl SentMessage {
+    pub fn new(destination: &str, content: &str, message_references: Vec<u8>) -> Self {
+        SentMessage {
+            destination: String::from(destination),
+            content: String::from(content),
+            statuses: vec![None; message_references.len()],
+            message_references: message_references,
+            sent_at: Utc::now()
+        }
+    }
+
+    /// The final state of the whole message, `None` while any part could still change
+    ///
+    /// A single failed part fails the message, since the recipient never gets all of it.
+    pub fn state(&self) -> Option<DeliveryState> {
+        let states: Vec<Option<DeliveryState>> = self.statuses.iter().map(|status| status.map(|status| status.state())).collect();
+
+        if states.contains(&Some(DeliveryState::Failed)) {
+            Some(DeliveryState::Failed)
+        } else if states.iter().all(|state| *state == Some(DeliveryState::Delivered)) {
+            Some(DeliveryState::Delivered)
+        } else {
+            None
+        }
+    }
+}
+
+/// A status report from the service centre, matched up with the message it's for when possible
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryReport {
+    pub message_reference: u8,
+    pub recipient: String,
+    pub status: DeliveryStatus,
+    /// When the service centre received the message
+    pub service_centre_timestamp: DateTime<FixedOffset>,
+    /// When the message was delivered, or the service centre gave up
+    pub discharge_time: DateTime<FixedOffset>,
+    /// The sent message this report is for, including this report, `None` if it couldn't be matched
+    pub message: Option<SentMessage>,
+    /// The final state of the whole message, `None` while waiting on reports for other parts
+    pub message_state: Option<DeliveryState>
+}
+
+impl DeliveryReport {
+    /// Builds an uncorrelated report
+    pub fn from_status_report(report: &SmsStatusReport) -> Self {
+        DeliveryReport {
+            message_reference: report.message_reference,
+            recipient: report.recipient.to_string(),
+            status: report.status,
+            service_centre_timestamp: report.service_centre_timestamp,
+            discharge_time: report.discharge_time,
+            message: None,
+            message_state: None
+        }
+    }
+}
+
+/// Keeps sent messages around until every part has a final status report
+///
+/// Cloning is cheap and every clone shares the same messages.
+#[derive(Clone, Default)]
+pub struct DeliveryTracker {
+    messages: Arc<Mutex<VecDeque<SentMessage>>>
+}
+
+impl DeliveryTracker {
+    pub(crate) fn track(&self, message: SentMessage) {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() >= MAX_TRACKED_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(message);
+    }
+
+    /// Matches a report to the most recent message sent to its recipient with its message reference
+    ///
+    /// Fills in `message` and `message_state`, and stops tracking the message once its state is final.
+    /// Returns whether a message was found.
+    pub fn correlate(&self, report: &mut DeliveryReport) -> bool {
+        let mut messages = self.messages.lock().unwrap();
+
+        let found = messages.iter().rposition(|message| {
+            message.message_references.contains(&report.message_reference) && same_number(&message.destination, &report.recipient)
+        });
+        let Some(position) = found else { return false };
+
+        let message = &mut messages[position];
+        // References wrap, so the same one can't come up twice within one message
+        let part = message.message_references.iter().position(|reference| *reference == report.message_reference).unwrap();
+        message.statuses[part] = Some(report.status);
+
+        report.message_state = message.state();
+        report.message = Some(message.clone());
+
+        if report.message_state.is_some() {
+            messages.remove(position);
+        }
+
+        true
+    }
+
+    /// Messages still waiting on a final status report for at least one part, oldest first
+    pub fn pending(&self) -> Vec<SentMessage> {
+        self.messages.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Compares two numbers, allowing either one to be missing a country code
+///
+/// An empty recipient (which some networks send) matches any number.
+fn same_number(sent: &str, reported: &str) -> bool {
+    if reported.is_empty() {
+        return true
+    }
+
+    match (PhoneNumber::parse(sent), PhoneNumber::parse(reported)) {
+        (Ok(sent), Ok(reported)) => sent.matches(&reported),
+        _ => sent == reported
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fmt, io, time::Duration};
+
+use crate::{constants::ModemError, error_codes::ErrorCategory};
+
+/// Every error the crate can return
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the transport (or the message archive) failed
+    Io(io::Error),
+
+    /// A command was issued before `open()` or after `close()`
+    NotOpen,
+
+    /// `open()` was called on a modem that is already open
+    AlreadyOpen,
+
+    /// The connection went away while a command was waiting on its response
+    ConnectionClosed,
+
+    /// A command didn't receive a final result code before its deadline
+    Timeout {
+        command: String,
+        timeout: Duration
+    },
+
+    /// The modem's response couldn't be understood, `raw` holds whatever was being parsed
+    Parse {
+        message: String,
+        raw: String
+    },
+
+    /// The modem returned a `+CME ERROR` or `+CMS ERROR`
+    Modem(ModemError),
+
+    /// The modem returned a plain `ERROR` with no code attached
+    CommandFailed,
+
+    /// A value passed in was rejected before anything was sent to the modem
+    InvalidArgument(String),
+
+    /// Events were dropped while waiting on one, so the answer may have been lost
+    EventsMissed(u64)
+}
+
+/// Shorthand for results using the crate error
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Builds a parse error, keeping the raw response for debugging
+    pub fn parse(message: &str, raw: &str) -> Error {
+        Error::Parse { message: String::from(message), raw: String::from(raw) }
+    }
+
+    /// Builds an invalid argument error
+    pub fn invalid_argument(message: &str) -> Error {
+        Error::InvalidArgument(String::from(message))
+    }
+
+    /// How the error should be handled
+    ///
+    /// Timeouts and dropped connections are transient, plain `ERROR`s and bad responses are permanent
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::Io(_) | Error::ConnectionClosed | Error::Timeout { .. } | Error::EventsMissed(_) => ErrorCategory::Transient,
+            Error::Modem(e) => e.category(),
+            Error::NotOpen | Error::AlreadyOpen | Error::InvalidArgument(_) => ErrorCategory::User,
+            Error::Parse { .. } | Error::CommandFailed => ErrorCategory::Permanent
+        }
+    }
+
+    /// Whether the operation is worth retrying unchanged
+    pub fn is_retryable(&self) -> bool {
+        self.category().is_retryable()
+    }
+
+    /// Returns the modem error if this is a CME/CMS error
+    pub fn modem_error(&self) -> Option<&ModemError> {
+        match self {
+            Error::Modem(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::NotOpen => write!(f, "Modem connection is not open!"),
+            Error::AlreadyOpen => write!(f, "Modem connection is already open!"),
+            Error::ConnectionClosed => write!(f, "Modem connection was closed before the command completed"),
+            Error::Timeout { command, timeout } => write!(f, "Command \"{}\" timed out after {:?}", command, timeout),
+            Error::Parse { message, raw } => write!(f, "{} (response: {:?})", message, raw),
+            Error::Modem(e) => write!(f, "{}", e),
+            Error::CommandFailed => write!(f, "Generic error was returned"),
+            Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Error::EventsMissed(missed) => write!(f, "Missed {} modem events while waiting on an answer", missed)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Modem(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ModemError> for Error {
+    fn from(e: ModemError) -> Self {
+        Error::Modem(e)
+    }
+}
+
+impl From<tokio_serial::Error> for Error {
+    fn from(e: tokio_serial::Error) -> Self {
+        Error::Io(e.into())
+    }
+}
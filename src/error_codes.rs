@@ -0,0 +1,256 @@
+/// How a modem error should be handled by whoever issued the command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The network, SIM or modem is temporarily unavailable, retrying later may succeed
+    Transient,
+
+    /// Retrying won't change anything
+    Permanent,
+
+    /// The SIM is missing, locked or needs a PIN/PUK before anything will work
+    SimAuth,
+
+    /// Something about the request itself was wrong (bad index, number, text, ...), it needs fixing before retrying
+    User
+}
+
+impl ErrorCategory {
+    /// Whether retrying the same command unchanged is worth it
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorCategory::Transient)
+    }
+}
+
+/// Builds an error code enum along with its code, description and category lookups
+///
+/// Anything not in the table is kept as `Unknown(code)` so no code returned by the modem is lost.
+macro_rules! error_codes {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $code:literal, $description:literal, $category:ident;)* }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)*
+            Unknown(i32)
+        }
+
+        impl $name {
+            /// Looks up the code returned by the modem
+            pub fn from_code(code: i32) -> $name {
+                match code {
+                    $($code => $name::$variant,)*
+                    _ => $name::Unknown(code)
+                }
+            }
+
+            /// Looks up the text returned by the modem in verbose mode (`AT+CMEE=2`), ignoring case
+            pub fn from_description(description: &str) -> Option<$name> {
+                [$($name::$variant,)*].into_iter().find(|code| code.description().eq_ignore_ascii_case(description.trim()))
+            }
+
+            /// The numeric code as returned by the modem
+            pub fn code(&self) -> i32 {
+                match self {
+                    $($name::$variant => $code,)*
+                    $name::Unknown(code) => *code
+                }
+            }
+
+            /// Human readable description of the code
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $description,)*
+                    $name::Unknown(_) => "Unrecognized error"
+                }
+            }
+
+            /// How the error should be handled, unrecognized codes are treated as permanent
+            pub fn category(&self) -> ErrorCategory {
+                match self {
+                    $($name::$variant => ErrorCategory::$category,)*
+                    $name::Unknown(_) => ErrorCategory::Permanent
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// `+CME ERROR` codes from 3GPP TS 27.007 section 9.2 plus SIM7600 vendor codes, where a code is in both the
+    /// SIM7600 meaning wins
+    CmeErrorCode {
+        PhoneFailure = 0, "Phone failure", Permanent;
+        NoConnectionToPhone = 1, "No connection to phone", Transient;
+        PhoneAdapterLinkReserved = 2, "Phone adapter link reserved", Transient;
+        OperationNotAllowed = 3, "Operation not allowed", User;
+        OperationNotSupported = 4, "Operation not supported", Permanent;
+        PhSimPinRequired = 5, "PH-SIM PIN required", SimAuth;
+        PhFsimPinRequired = 6, "PH-FSIM PIN required", SimAuth;
+        PhFsimPukRequired = 7, "PH-FSIM PUK required", SimAuth;
+        SimNotInserted = 10, "SIM not inserted", SimAuth;
+        SimPinRequired = 11, "SIM PIN required", SimAuth;
+        SimPukRequired = 12, "SIM PUK required", SimAuth;
+        SimFailure = 13, "SIM failure", SimAuth;
+        SimBusy = 14, "SIM busy", Transient;
+        SimWrong = 15, "SIM wrong", SimAuth;
+        IncorrectPassword = 16, "Incorrect password", SimAuth;
+        SimPin2Required = 17, "SIM PIN2 required", SimAuth;
+        SimPuk2Required = 18, "SIM PUK2 required", SimAuth;
+        MemoryFull = 20, "Memory full", User;
+        InvalidIndex = 21, "Invalid index", User;
+        NotFound = 22, "Not found", User;
+        MemoryFailure = 23, "Memory failure", Permanent;
+        TextStringTooLong = 24, "Text string too long", User;
+        InvalidCharactersInTextString = 25, "Invalid characters in text string", User;
+        DialStringTooLong = 26, "Dial string too long", User;
+        InvalidCharactersInDialString = 27, "Invalid characters in dial string", User;
+        NoNetworkService = 30, "No network service", Transient;
+        NetworkTimeout = 31, "Network timeout", Transient;
+        NetworkNotAllowedEmergencyCallsOnly = 32, "Network not allowed, emergency calls only", Permanent;
+        NetworkPersonalizationPinRequired = 40, "Network personalization PIN required", SimAuth;
+        NetworkPersonalizationPukRequired = 41, "Network personalization PUK required", SimAuth;
+        NetworkSubsetPersonalizationPinRequired = 42, "Network subset personalization PIN required", SimAuth;
+        NetworkSubsetPersonalizationPukRequired = 43, "Network subset personalization PUK required", SimAuth;
+        ServiceProviderPersonalizationPinRequired = 44, "Service provider personalization PIN required", SimAuth;
+        ServiceProviderPersonalizationPukRequired = 45, "Service provider personalization PUK required", SimAuth;
+        CorporatePersonalizationPinRequired = 46, "Corporate personalization PIN required", SimAuth;
+        CorporatePersonalizationPukRequired = 47, "Corporate personalization PUK required", SimAuth;
+        HiddenKeyRequired = 48, "Hidden key required", SimAuth;
+        EapMethodNotSupported = 49, "EAP method not supported", Permanent;
+        IncorrectParameters = 50, "Incorrect parameters", User;
+        CommandDisabled = 51, "Command implemented but currently disabled", Permanent;
+        CommandAbortedByUser = 52, "Command aborted by user", User;
+        NotAttachedDueToRestrictions = 53, "Not attached to network due to MT functionality restrictions", Permanent;
+        EmergencyCallsOnly = 54, "Modem not allowed, MT restricted to emergency calls only", Permanent;
+        NotAllowedDueToRestrictions = 55, "Operation not allowed because of MT functionality restrictions", Permanent;
+        FixedDialNumberOnly = 56, "Fixed dial number only allowed", Permanent;
+        TemporarilyOutOfService = 57, "Temporarily out of service due to other MT usage", Transient;
+        LanguageNotSupported = 58, "Language/alphabet not supported", User;
+        UnexpectedDataValue = 59, "Unexpected data value", User;
+        SystemFailure = 60, "System failure", Transient;
+        DataMissing = 61, "Data missing", User;
+        CallBarred = 62, "Call barred", Permanent;
+        MessageWaitingIndicationSubscriptionFailure = 63, "Message waiting indication subscription failure", Permanent;
+        UnknownError = 100, "Unknown error", Permanent;
+        IllegalMs = 103, "Illegal MS", Permanent;
+        IllegalMe = 106, "Illegal ME", Permanent;
+        GprsServicesNotAllowed = 107, "GPRS services not allowed", Permanent;
+        PlmnNotAllowed = 111, "PLMN not allowed", Permanent;
+        LocationAreaNotAllowed = 112, "Location area not allowed", Permanent;
+        RoamingNotAllowed = 113, "Roaming not allowed in this location area", Permanent;
+        OperationTemporarilyNotAllowed = 126, "Operation temporary not allowed", Transient;
+        ServiceOptionNotSupported = 132, "Service operation not supported", Permanent;
+        ServiceOptionNotSubscribed = 133, "Requested service option not subscribed", Permanent;
+        ServiceOptionOutOfOrder = 134, "Service option temporary out of order", Transient;
+        UnspecifiedGprsError = 148, "Unspecified GPRS error", Transient;
+        PdpAuthenticationFailure = 149, "PDP authentication failure", SimAuth;
+        InvalidMobileClass = 150, "Invalid mobile class", User;
+        // 256-263 are the SIM7600 vendor meanings, which take the place of the TS 27.007 ones for the same codes
+        Sim7600OperationTemporarilyNotAllowed = 256, "Operation temporarily not allowed", Transient;
+        Sim7600CallBarred = 257, "Call barred", Permanent;
+        PhoneBusy = 258, "Phone is busy", Transient;
+        UserAbort = 259, "User abort", User;
+        InvalidDialString = 260, "Invalid dial string", User;
+        SsNotExecuted = 261, "SS not executed", Permanent;
+        SimBlocked = 262, "SIM Blocked", SimAuth;
+        InvalidBlock = 263, "Invalid block", Permanent;
+        UnknownNetworkMessage = 264, "Unknown network message", Permanent;
+        MinimumTftsViolated = 273, "Minimum TFTS per PDP address violated", User;
+        TftPrecedenceIndexNotUnique = 274, "TFT precedence index not unique", User;
+        InvalidParameterCombination = 275, "Invalid parameter combination", User;
+        SimPoweredDown = 772, "SIM powered down", SimAuth;
+    }
+}
+
+error_codes! {
+    /// `+CMS ERROR` codes from 3GPP TS 27.005 section 3.2.5 (which pulls in TS 24.011 and TS 23.040 causes) plus SIM7600 vendor codes
+    CmsErrorCode {
+        UnassignedNumber = 1, "Unassigned number", User;
+        OperatorDeterminedBarring = 8, "Operator determined barring", Permanent;
+        CallBarred = 10, "Call barred", Permanent;
+        NetworkFailure = 17, "Network failure", Transient;
+        ShortMessageTransferRejected = 21, "Short message transfer rejected", Permanent;
+        MemoryCapacityExceededRp = 22, "Memory capacity exceeded", Transient;
+        DestinationOutOfService = 27, "Destination out of service", Transient;
+        UnidentifiedSubscriber = 28, "Unidentified subscriber", User;
+        FacilityRejected = 29, "Facility rejected", Permanent;
+        UnknownSubscriber = 30, "Unknown subscriber", User;
+        NetworkOutOfOrder = 38, "Network out of order", Transient;
+        TemporaryFailure = 41, "Temporary failure", Transient;
+        Congestion = 42, "Congestion", Transient;
+        ResourcesUnavailable = 47, "Resources unavailable", Transient;
+        RequestedFacilityNotSubscribed = 50, "Requested facility not subscribed", Permanent;
+        RequestedFacilityNotImplemented = 69, "Requested facility not implemented", Permanent;
+        InvalidTransferReference = 81, "Invalid short message transfer reference value", Permanent;
+        InvalidMessage = 95, "Invalid message unspecified", Permanent;
+        InvalidMandatoryInformation = 96, "Invalid mandatory information", Permanent;
+        MessageTypeNotImplemented = 97, "Message type non existent or not implemented", Permanent;
+        MessageNotCompatible = 98, "Message not compatible with short message protocol", Permanent;
+        InformationElementNotImplemented = 99, "Information element non-existent or not implemented", Permanent;
+        ProtocolError = 111, "Protocol error, unspecified", Permanent;
+        InterworkingUnspecified = 127, "Interworking, unspecified", Permanent;
+        TelematicInterworkingNotSupported = 128, "Telematic interworking not supported", Permanent;
+        ShortMessageType0NotSupported = 129, "Short message type 0 not supported", Permanent;
+        CannotReplaceShortMessage = 130, "Cannot replace short message", Permanent;
+        UnspecifiedTpPidError = 143, "Unspecified TP-PID error", User;
+        DataCodingSchemeNotSupported = 144, "Data code scheme not supported", User;
+        MessageClassNotSupported = 145, "Message class not supported", User;
+        UnspecifiedTpDcsError = 159, "Unspecified TP-DCS error", User;
+        CommandCannotBeActioned = 160, "Command cannot be actioned", Permanent;
+        CommandUnsupported = 161, "Command unsupported", Permanent;
+        UnspecifiedTpCommandError = 175, "Unspecified TP-Command error", Permanent;
+        TpduNotSupported = 176, "TPDU not supported", Permanent;
+        ScBusy = 192, "SC busy", Transient;
+        NoScSubscription = 193, "No SC subscription", Permanent;
+        ScSystemFailure = 194, "SC System failure", Transient;
+        InvalidSmeAddress = 195, "Invalid SME address", User;
+        DestinationSmeBarred = 196, "Destination SME barred", Permanent;
+        DuplicateShortMessageRejected = 197, "SM Rejected-Duplicate SM", Permanent;
+        TpVpfNotSupported = 198, "TP-VPF not supported", User;
+        TpVpNotSupported = 199, "TP-VP not supported", User;
+        SimSmsStorageFull = 208, "D0 SIM SMS Storage full", User;
+        NoSmsStorageCapabilityInSim = 209, "No SMS Storage capability in SIM", Permanent;
+        ErrorInMs = 210, "Error in MS", Transient;
+        MemoryCapacityExceeded = 211, "Memory capacity exceeded", User;
+        SimApplicationToolkitBusy = 212, "Sim application toolkit busy", Transient;
+        SimDataDownloadError = 213, "SIM data download error", Permanent;
+        UnspecifiedErrorCause = 255, "Unspecified error cause", Permanent;
+        MeFailure = 300, "ME Failure", Permanent;
+        SmsServiceReserved = 301, "SMS service of ME reserved", Transient;
+        OperationNotAllowed = 302, "Operation not allowed", User;
+        OperationNotSupported = 303, "Operation not supported", Permanent;
+        InvalidPduModeParameter = 304, "Invalid PDU mode parameter", User;
+        InvalidTextModeParameter = 305, "Invalid Text mode parameter", User;
+        SimNotInserted = 310, "SIM not inserted", SimAuth;
+        SimPinRequired = 311, "SIM PIN required", SimAuth;
+        PhSimPinRequired = 312, "PH-SIM PIN required", SimAuth;
+        SimFailure = 313, "SIM failure", SimAuth;
+        SimBusy = 314, "SIM busy", Transient;
+        SimWrong = 315, "SIM wrong", SimAuth;
+        SimPukRequired = 316, "SIM PUK required", SimAuth;
+        SimPin2Required = 317, "SIM PIN2 required", SimAuth;
+        SimPuk2Required = 318, "SIM PUK2 required", SimAuth;
+        MemoryFailure = 320, "Memory failure", Permanent;
+        InvalidMemoryIndex = 321, "Invalid memory index", User;
+        MemoryFull = 322, "Memory full", User;
+        SmscAddressUnknown = 330, "SMSC address unknown", User;
+        NoNetworkService = 331, "No network service", Transient;
+        NetworkTimeout = 332, "Network timeout", Transient;
+        NoCnmaExpected = 340, "No +CNMA expected", User;
+        UnknownError = 500, "Unknown error", Permanent;
+        UserAbort = 512, "User abort", User;
+        UnableToStore = 513, "Unable to store", Permanent;
+        InvalidStatus = 514, "Invalid Status", User;
+        DeviceBusy = 515, "Device busy or Invalid Character in string", Transient;
+        InvalidLength = 516, "Invalid length", User;
+        InvalidCharacterInPdu = 517, "Invalid character in PDU", User;
+        InvalidParameter = 518, "Invalid parameter", User;
+        InvalidLengthOrCharacter = 519, "Invalid length or character", User;
+        InvalidCharacterInText = 520, "Invalid character in text", User;
+        TimerExpired = 521, "Timer expired", Transient;
+        OperationTemporarilyNotAllowed = 522, "Operation temporary not allowed", Transient;
+        SimNotReady = 532, "SIM not ready", Transient;
+        CellBroadcastErrorUnknown = 534, "Cell Broadcast error unknown", Permanent;
+        ProtocolStackBusy = 535, "Protocol stack busy", Transient;
+        InvalidParameterValue = 538, "Invalid parameter", User;
+    }
+}
@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use regex::Captures;
+
+use crate::{broadcast::{CellBroadcast, CellBroadcastPage}, concat::IncompleteMessage, constants::{SmsMessage, UnsolicitedResultCode}, data_message::DataMessage, delivery::{DeliveryReport, SentMessage}, outbox::OutboxMessage, pdu::{Address, DeliveryStatus, Pdu, SmsDeliver, SmsStatusReport, Tpdu}, storage::storage_name, ussd::UssdResponse, utils::{decode_number, hex_to_utf16, timestamp_to_iso_8601}};
+
+/// Unsolicited events reported by the modem, delivered to everyone who called `GsmModem::subscribe()`
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModemEvent {
+    /// The modem is ready to begin taking commands
+    Ready,
+
+    /// A new SMS message was saved to `storage` (ie. `SM` for the SIM, see `SmsStorage`) at `index`
+    NewSms {
+        storage: String,
+        index: u32
+    },
+
+    /// Incoming call
+    Ring,
+
+    /// A call was missed, `time` is as reported by the modem (ie. `14:05PM`)
+    MissedCall {
+        time: String,
+        number: String
+    },
+
+    /// The carrier is unavailable
+    NoCarrier,
+
+    /// A voice call has started
+    VoiceCallBegin,
+
+    /// A voice call has ended after `duration`
+    VoiceCallEnd {
+        duration: Duration
+    },
+
+    /// The network reported a new timezone
+    TimeZoneChange {
+        offset: FixedOffset
+    },
+
+    /// SMS storage is full and needs to be cleared
+    SmsFull,
+
+    /// The service centre reported on a message sent with a status report requested
+    DeliveryReport(DeliveryReport),
+
+    /// A status report was saved to `storage` at `index`, read it with `GsmModem::read_delivery_report`
+    StatusReportStored {
+        storage: String,
+        index: u32
+    },
+
+    /// A message routed straight to us with `+CMT` instead of being stored
+    ///
+    /// Depending on `AT+CSMS` the modem may expect it to be acknowledged with `GsmModem::acknowledge_new_message`.
+    DirectSms(DirectSms),
+
+    /// A complete incoming message, from `GsmModem::spawn_message_pipeline`
+    SmsReceived {
+        message: SmsMessage,
+        /// Where the message is stored (ie. `SM`), `None` if it was routed straight to us and isn't stored
+        storage: Option<String>
+    },
+
+    /// A received message says voicemail is waiting, from `GsmModem::spawn_message_pipeline`
+    ///
+    /// Sent after the `SmsReceived` carrying the indication. `count` is `None` when the network only says that
+    /// there is voicemail, and `Some(0)` once it's all been listened to.
+    VoicemailWaiting {
+        count: Option<u8>
+    },
+
+    /// A complete incoming message addressed to an application port, from `GsmModem::spawn_message_pipeline`
+    ///
+    /// Sent instead of `SmsReceived`, route these to handlers with `GsmModem::spawn_port_router`.
+    DataSmsReceived {
+        message: DataMessage,
+        /// Where the message is stored, `None` if it was routed straight to us and isn't stored
+        storage: Option<String>
+    },
+
+    /// A page of a cell broadcast, followed by `CellBroadcast` once every page of the broadcast has arrived
+    CellBroadcastPage(CellBroadcastPage),
+
+    /// A complete cell broadcast (ie. a public warning, see `CellBroadcast::public_warning`)
+    ///
+    /// Networks repeat broadcasts, so the same one can arrive more than once with the same serial number.
+    CellBroadcast(CellBroadcast),
+
+    /// A cell broadcast page was saved to `storage` (usually `BM`) at `index`
+    CellBroadcastStored {
+        storage: String,
+        index: u32
+    },
+
+    /// A USSD message from the network
+    ///
+    /// Answers to `GsmModem::start_ussd` come through here too, as well as messages the network started
+    /// (ie. a promotion, or a menu when `status` is `UssdStatus::ActionRequired`, answered through `GsmModem::ussd_session`).
+    Ussd(UssdResponse),
+
+    /// A message was sent, with every part accepted by the network
+    SmsSent(SentMessage),
+
+    /// A message in the outbox changed state (or failed an attempt and was queued again), from `GsmModem::spawn_outbox`
+    OutboxUpdate(OutboxMessage),
+
+    /// A concatenated message that was given up on before every part arrived, from `GsmModem::spawn_message_pipeline`
+    IncompleteSms(IncompleteMessage),
+
+    /// A background task failed at something it had nobody to return the error to, `error` describes what
+    ///
+    /// The task carries on, except for the connection failing to read, which closes it.
+    BackgroundError {
+        task: BackgroundTask,
+        error: String
+    },
+
+    /// A background task fell too far behind on events and skipped `missed` of them (ie. messages it never saw)
+    EventsMissed {
+        task: BackgroundTask,
+        missed: u64
+    }
+}
+
+/// Where a `ModemEvent::BackgroundError` or `ModemEvent::EventsMissed` came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundTask {
+    /// The command actor that owns the connection
+    Connection,
+    /// `GsmModem::spawn_message_pipeline`
+    MessagePipeline,
+    /// `GsmModem::spawn_port_router`
+    PortRouter,
+    /// `GsmModem::spawn_archiver`
+    Archiver,
+    /// `GsmModem::spawn_outbox`
+    Outbox,
+    /// `GsmModem::spawn_storage_cleanup`
+    StorageCleanup
+}
+
+/// A message from `+CMT`, in whichever SMS format the modem was in
+#[derive(Clone, Debug, PartialEq)]
+pub enum DirectSms {
+    /// Text mode only gives the text, so concatenated messages arrive as separate parts with no way to join them
+    Text(SmsMessage),
+    Pdu(SmsDeliver)
+}
+
+impl ModemEvent {
+    /// Builds the event for a detected URC from the captures of its regex, and its body for URCs spanning two lines
+    ///
+    /// Returns `None` if the payload couldn't be parsed
+    pub fn from_urc(urc: UnsolicitedResultCode, captures: &Captures, body: Option<&str>) -> Option<ModemEvent> {
+        match urc {
+            UnsolicitedResultCode::Ready => Some(ModemEvent::Ready),
+            UnsolicitedResultCode::CMTI => Some(ModemEvent::NewSms {
+                storage: storage_name(captures.get(1)?.as_str()),
+                index: captures.get(2)?.as_str().parse().ok()?
+            }),
+            UnsolicitedResultCode::Ring => Some(ModemEvent::Ring),
+            UnsolicitedResultCode::MissedCall => Some(ModemEvent::MissedCall {
+                time: String::from(captures.get(1)?.as_str()),
+                number: String::from(captures.get(2)?.as_str().trim())
+            }),
+            UnsolicitedResultCode::NoCarrier => Some(ModemEvent::NoCarrier),
+            UnsolicitedResultCode::VoiceCallBegin => Some(ModemEvent::VoiceCallBegin),
+            UnsolicitedResultCode::VoiceCallEnd => {
+                // Call time comes through as HHMMSS
+                let call_time = captures.get(1)?.as_str();
+                let hours: u64 = call_time.get(0..2)?.parse().ok()?;
+                let minutes: u64 = call_time.get(2..4)?.parse().ok()?;
+                let seconds: u64 = call_time.get(4..6)?.parse().ok()?;
+
+                Some(ModemEvent::VoiceCallEnd { duration: Duration::from_secs(hours * 3600 + minutes * 60 + seconds) })
+            }
+            UnsolicitedResultCode::TimeZoneChange => {
+                let quarter_hours: i32 = captures.get(1)?.as_str().parse().ok()?;
+
+                Some(ModemEvent::TimeZoneChange { offset: FixedOffset::east_opt(quarter_hours * 15 * 60)? })
+            }
+            UnsolicitedResultCode::SmsFull => Some(ModemEvent::SmsFull),
+            UnsolicitedResultCode::StatusReport => {
+                let report = match captures.get(2) {
+                    Some(_) => text_status_report(captures)?,
+                    None => match Pdu::decode(body?).ok()?.tpdu {
+                        Tpdu::StatusReport(report) => report,
+                        _ => return None
+                    }
+                };
+
+                Some(ModemEvent::DeliveryReport(DeliveryReport::from_status_report(&report)))
+            }
+            UnsolicitedResultCode::StatusReportIndex => Some(ModemEvent::StatusReportStored {
+                storage: storage_name(captures.get(1)?.as_str()),
+                index: captures.get(2)?.as_str().parse().ok()?
+            }),
+            UnsolicitedResultCode::CMT => match captures.get(1) {
+                Some(originator) => {
+                    // Both are UCS2 hex since the modem is configured with AT+CSCS="UCS2"
+                    let address = hex_to_utf16(originator.as_str()).ok()?;
+                    let content = hex_to_utf16(body?).ok()?;
+                    let timestamp = DateTime::parse_from_rfc3339(&timestamp_to_iso_8601(captures.get(2)?.as_str()).ok()?).ok()?;
+
+                    Some(ModemEvent::DirectSms(DirectSms::Text(SmsMessage::direct(address, content, timestamp))))
+                }
+                None => match Pdu::decode(body?).ok()?.tpdu {
+                    Tpdu::Deliver(deliver) => Some(ModemEvent::DirectSms(DirectSms::Pdu(deliver))),
+                    _ => None
+                }
+            }
+            UnsolicitedResultCode::CBM => {
+                let page = match captures.get(1) {
+                    Some(serial_number) => CellBroadcastPage::from_text(
+                        serial_number.as_str().parse().ok()?,
+                        captures.get(2)?.as_str().parse().ok()?,
+                        captures.get(3)?.as_str().parse().ok()?,
+                        captures.get(4)?.as_str().parse().ok()?,
+                        captures.get(5)?.as_str().parse().ok()?,
+                        body?
+                    ),
+                    None => CellBroadcastPage::decode(body?).ok()?
+                };
+
+                Some(ModemEvent::CellBroadcastPage(page))
+            }
+            UnsolicitedResultCode::CBMI => Some(ModemEvent::CellBroadcastStored {
+                storage: storage_name(captures.get(1)?.as_str()),
+                index: captures.get(2)?.as_str().parse().ok()?
+            }),
+            UnsolicitedResultCode::Ussd => Some(ModemEvent::Ussd(UssdResponse::from_cusd(
+                captures.get(1)?.as_str().parse().ok()?,
+                captures.get(2).map(|message| message.as_str()),
+                captures.get(3).and_then(|dcs| dcs.as_str().parse().ok())
+            )?)),
+        }
+    }
+}
+
+/// Builds a status report from the captures of a text mode `+CDS`
+fn text_status_report(captures: &Captures) -> Option<SmsStatusReport> {
+    let recipient = decode_number(captures.get(3)?.as_str(), Some(captures.get(4)?.as_str()));
+
+    Some(SmsStatusReport {
+        message_reference: captures.get(2)?.as_str().parse().ok()?,
+        recipient: Address::new(&recipient),
+        service_centre_timestamp: DateTime::parse_from_rfc3339(&timestamp_to_iso_8601(captures.get(5)?.as_str()).ok()?).ok()?,
+        discharge_time: DateTime::parse_from_rfc3339(&timestamp_to_iso_8601(captures.get(6)?.as_str()).ok()?).ok()?,
+        status: DeliveryStatus(captures.get(7)?.as_str().parse().ok()?)
+    })
+}
@@ -0,0 +1,166 @@
+use regex::Regex;
+
+use crate::{constants::UnsolicitedResultCode, error_codes::{CmeErrorCode, CmsErrorCode}};
+
+/// What a single frame from the modem is
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameKind {
+    /// An unsolicited result code, never part of a command's response
+    Urc(UnsolicitedResultCode),
+
+    /// The `> ` prompt asking for input (ie. the body of `AT+CMGS`)
+    Prompt,
+
+    /// Any other line, part of the response to whatever command is in flight
+    Intermediate,
+
+    /// The final result code that ends a command's response
+    Final(FinalResult)
+}
+
+/// Final result codes that end a command's response
+#[derive(Clone, Debug, PartialEq)]
+pub enum FinalResult {
+    Ok,
+    Error,
+    CmeError(i32),
+    CmsError(i32)
+}
+
+/// A single line (or prompt) read from the modem
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The line with its line ending stripped
+    pub line: String,
+    /// The line following a URC that spans two lines (ie. the PDU after `+CDS: <length>`)
+    pub body: Option<String>,
+    /// Every byte this frame was built from, including the blank lines before it
+    ///
+    /// Concatenating the raw text of a response's frames gives back exactly what the modem sent for it.
+    pub raw: String
+}
+
+/// Splits the byte stream from the modem into classified frames
+///
+/// Bytes are buffered until a full line arrives, so reads that split a line (or hold several) are handled,
+/// and nothing is ever dropped: every byte ends up in exactly one frame.
+pub struct Framer {
+    buffer: Vec<u8>,
+    /// Blank lines waiting to be attached to the next frame
+    pending_raw: String,
+    /// The previous line was an SMS header, so the next line is message content no matter what it looks like
+    expecting_body: bool,
+    /// A URC header waiting on the line that completes it
+    pending_urc: Option<Frame>,
+    urc_regex: Vec<(UnsolicitedResultCode, Regex)>,
+    final_error_re: Regex
+}
+
+impl Framer {
+    pub fn new() -> Self {
+        Framer {
+            buffer: Vec::new(),
+            pending_raw: String::new(),
+            expecting_body: false,
+            pending_urc: None,
+            urc_regex: UnsolicitedResultCode::get_regex_array(),
+            final_error_re: Regex::new(r"^\+(CM(?:E|S)) ERROR:\s*(.*)$").unwrap()
+        }
+    }
+
+    /// Adds bytes read from the modem, returning any frames they completed
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Frame> {
+        self.buffer.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let raw_bytes: Vec<u8> = self.buffer.drain(..=end).collect();
+                let raw = String::from_utf8_lossy(&raw_bytes).into_owned();
+                let line = raw.trim_end_matches(['\r', '\n']).to_string();
+
+                // The line after a two line URC is its body no matter what it looks like, even when empty
+                if let Some(mut urc) = self.pending_urc.take() {
+                    urc.raw.push_str(&raw);
+                    urc.body = Some(line);
+                    frames.push(urc);
+                    continue
+                }
+
+                if line.is_empty() {
+                    // An empty message body looks like a blank line
+                    self.expecting_body = false;
+                    self.pending_raw.push_str(&raw);
+                    continue
+                }
+
+                let kind = self.classify(&line);
+                let frame = self.frame(kind, line, &raw);
+
+                match frame.kind {
+                    FrameKind::Urc(urc) if urc.expects_body(&frame.line) => self.pending_urc = Some(frame),
+                    _ => frames.push(frame)
+                }
+            } else if self.buffer.starts_with(b"> ") {
+                // The prompt is the only thing the modem sends without a line ending
+                self.buffer.drain(..2);
+                frames.push(self.frame(FrameKind::Prompt, String::from(">"), "> "));
+            } else {
+                return frames
+            }
+        }
+    }
+
+    fn frame(&mut self, kind: FrameKind, line: String, raw: &str) -> Frame {
+        let mut full_raw = std::mem::take(&mut self.pending_raw);
+        full_raw.push_str(raw);
+
+        Frame { kind: kind, line: line, body: None, raw: full_raw }
+    }
+
+    fn classify(&mut self, line: &str) -> FrameKind {
+        if self.expecting_body {
+            self.expecting_body = false;
+            return FrameKind::Intermediate
+        }
+
+        match line {
+            "OK" => return FrameKind::Final(FinalResult::Ok),
+            "ERROR" => return FrameKind::Final(FinalResult::Error),
+            _ => ()
+        }
+
+        if let Some(capture) = self.final_error_re.captures(line) {
+            // Verbose mode (AT+CMEE=2) gives the description instead of the code, text we don't know is a generic unknown error
+            let detail = &capture[2];
+            return match &capture[1] {
+                "CME" => {
+                    let code = detail.parse().ok().or_else(|| CmeErrorCode::from_description(detail).map(|code| code.code()));
+                    FrameKind::Final(FinalResult::CmeError(code.unwrap_or(CmeErrorCode::UnknownError.code())))
+                }
+                _ => {
+                    let code = detail.parse().ok().or_else(|| CmsErrorCode::from_description(detail).map(|code| code.code()));
+                    FrameKind::Final(FinalResult::CmsError(code.unwrap_or(CmsErrorCode::UnknownError.code())))
+                }
+            }
+        }
+
+        if let Some((urc, _)) = self.urc_regex.iter().find(|(_, regex)| regex.is_match(line)) {
+            return FrameKind::Urc(*urc)
+        }
+
+        // Message content follows these headers and could contain anything, including "OK" or "RING"
+        if line.starts_with("+CMGR:") || line.starts_with("+CMGL:") {
+            self.expecting_body = true;
+        }
+
+        FrameKind::Intermediate
+    }
+}
+
+impl Default for Framer {
+    fn default() -> Self {
+        Framer::new()
+    }
+}
@@ -0,0 +1,267 @@
+use std::iter;
+
+/// The GSM 03.38 default alphabet, indexed by septet value
+///
+/// `0x1B` is the escape to the extension table, it decodes to a space when not followed by anything known.
+pub const DEFAULT_ALPHABET: [char; 128] = [
+    '@', '£', '$', '¥', 'è', 'é', 'ù', 'ì', 'ò', 'Ç', '\n', 'Ø', 'ø', '\r', 'Å', 'å',
+    'Δ', '_', 'Φ', 'Γ', 'Λ', 'Ω', 'Π', 'Ψ', 'Σ', 'Θ', 'Ξ', '\u{1b}', 'Æ', 'æ', 'ß', 'É',
+    ' ', '!', '"', '#', '¤', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '¡', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ä', 'Ö', 'Ñ', 'Ü', '§',
+    '¿', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ä', 'ö', 'ñ', 'ü', 'à'
+];
+
+/// The default extension table, reached with the escape septet
+pub const EXTENSION_TABLE: [(u8, char); 10] = [
+    (0x0a, '\u{0c}'), (0x14, '^'), (0x28, '{'), (0x29, '}'), (0x2f, '\\'),
+    (0x3c, '['), (0x3d, '~'), (0x3e, ']'), (0x40, '|'), (0x65, '€')
+];
+
+/// Turkish locking shift table (3GPP 23.038 A.3.1)
+const TURKISH_LOCKING: [char; 128] = [
+    '@', '£', '$', '¥', '€', 'é', 'ù', 'ı', 'ò', 'Ç', '\n', 'Ğ', 'ğ', '\r', 'Å', 'å',
+    'Δ', '_', 'Φ', 'Γ', 'Λ', 'Ω', 'Π', 'Ψ', 'Σ', 'Θ', 'Ξ', '\u{1b}', 'Ş', 'ş', 'ß', 'É',
+    ' ', '!', '"', '#', '¤', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    'İ', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ä', 'Ö', 'Ñ', 'Ü', '§',
+    'ç', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ä', 'ö', 'ñ', 'ü', 'à'
+];
+
+/// Portuguese locking shift table (3GPP 23.038 A.3.3)
+const PORTUGUESE_LOCKING: [char; 128] = [
+    '@', '£', '$', '¥', 'ê', 'é', 'ú', 'í', 'ó', 'ç', '\n', 'Ô', 'ô', '\r', 'Á', 'á',
+    'Δ', '_', 'ª', 'Ç', 'À', '∞', '^', '\\', '€', 'Ó', '|', '\u{1b}', 'Â', 'â', 'Ê', 'É',
+    ' ', '!', '"', '#', 'º', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    'Í', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ã', 'Õ', 'Ú', 'Ü', '§',
+    '~', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ã', 'õ', '`', 'ü', 'à'
+];
+
+/// Turkish single shift table (3GPP 23.038 A.2.1)
+const TURKISH_SINGLE_SHIFT: [(u8, char); 17] = [
+    (0x0a, '\u{0c}'), (0x14, '^'), (0x28, '{'), (0x29, '}'), (0x2f, '\\'), (0x3c, '['), (0x3d, '~'), (0x3e, ']'), (0x40, '|'),
+    (0x47, 'Ğ'), (0x49, 'İ'), (0x53, 'Ş'), (0x63, 'ç'), (0x65, '€'), (0x67, 'ğ'), (0x69, 'ı'), (0x73, 'ş')
+];
+
+/// Spanish single shift table (3GPP 23.038 A.2.2)
+const SPANISH_SINGLE_SHIFT: [(u8, char); 19] = [
+    (0x09, 'ç'), (0x0a, '\u{0c}'), (0x14, '^'), (0x28, '{'), (0x29, '}'), (0x2f, '\\'), (0x3c, '['), (0x3d, '~'), (0x3e, ']'),
+    (0x40, '|'), (0x41, 'Á'), (0x49, 'Í'), (0x4f, 'Ó'), (0x55, 'Ú'), (0x61, 'á'), (0x65, '€'), (0x69, 'í'), (0x6f, 'ó'), (0x75, 'ú')
+];
+
+/// Portuguese single shift table (3GPP 23.038 A.2.3)
+const PORTUGUESE_SINGLE_SHIFT: [(u8, char); 37] = [
+    (0x05, 'ê'), (0x09, 'ç'), (0x0a, '\u{0c}'), (0x0b, 'Ô'), (0x0c, 'ô'), (0x0e, 'Á'), (0x0f, 'á'), (0x12, 'Φ'), (0x13, 'Γ'),
+    (0x14, '^'), (0x15, 'Ω'), (0x16, 'Π'), (0x17, 'Ψ'), (0x18, 'Σ'), (0x19, 'Θ'), (0x1f, 'Ê'), (0x28, '{'), (0x29, '}'),
+    (0x2f, '\\'), (0x3c, '['), (0x3d, '~'), (0x3e, ']'), (0x40, '|'), (0x41, 'À'), (0x49, 'Í'), (0x4f, 'Ó'), (0x55, 'Ú'),
+    (0x5b, 'Ã'), (0x5c, 'Õ'), (0x61, 'Â'), (0x65, '€'), (0x69, 'í'), (0x6f, 'ó'), (0x75, 'ú'), (0x7b, 'ã'), (0x7c, 'õ'),
+    (0x7f, 'â')
+];
+
+/// Septet that switches to the extension (or single shift) table for the following character
+pub const ESCAPE: u8 = 0x1b;
+
+/// User data header octets taken up by each national language shift element (IEI, length and language)
+pub const SHIFT_ELEMENT_LENGTH: usize = 3;
+
+/// National languages with their own shift tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NationalLanguage {
+    Turkish,
+    Spanish,
+    Portuguese
+}
+
+impl NationalLanguage {
+    /// Every supported language, in the order they're tried when picking an encoding
+    pub const ALL: [NationalLanguage; 3] = [NationalLanguage::Turkish, NationalLanguage::Spanish, NationalLanguage::Portuguese];
+
+    /// The language identifier used in the shift information elements
+    pub fn id(&self) -> u8 {
+        match self {
+            NationalLanguage::Turkish => 1,
+            NationalLanguage::Spanish => 2,
+            NationalLanguage::Portuguese => 3
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<NationalLanguage> {
+        NationalLanguage::ALL.into_iter().find(|language| language.id() == id)
+    }
+
+    /// Spanish only defines a single shift table
+    fn locking_table(&self) -> Option<&'static [char; 128]> {
+        match self {
+            NationalLanguage::Turkish => Some(&TURKISH_LOCKING),
+            NationalLanguage::Spanish => None,
+            NationalLanguage::Portuguese => Some(&PORTUGUESE_LOCKING)
+        }
+    }
+
+    fn single_shift_table(&self) -> &'static [(u8, char)] {
+        match self {
+            NationalLanguage::Turkish => &TURKISH_SINGLE_SHIFT,
+            NationalLanguage::Spanish => &SPANISH_SINGLE_SHIFT,
+            NationalLanguage::Portuguese => &PORTUGUESE_SINGLE_SHIFT
+        }
+    }
+}
+
+/// Which tables a message is encoded with, `None` being the default alphabet/extension table
+///
+/// National tables are only usable in PDU mode, where they're announced in the user data header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShiftTables {
+    pub locking: Option<NationalLanguage>,
+    pub single: Option<NationalLanguage>
+}
+
+impl ShiftTables {
+    fn locking_table(&self) -> &'static [char; 128] {
+        self.locking.and_then(|language| language.locking_table()).unwrap_or(&DEFAULT_ALPHABET)
+    }
+
+    fn single_shift_table(&self) -> &'static [(u8, char)] {
+        self.single.map(|language| language.single_shift_table()).unwrap_or(&EXTENSION_TABLE)
+    }
+
+    /// Encodes text into unpacked septets, escaping anything from the shift table
+    ///
+    /// Returns `None` if a character isn't in either table
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        let locking = self.locking_table();
+        let single = self.single_shift_table();
+        let mut septets = Vec::with_capacity(text.len());
+
+        for c in text.chars() {
+            if let Some(position) = locking.iter().position(|a| *a == c && c != '\u{1b}') {
+                septets.push(position as u8);
+            } else if let Some((code, _)) = single.iter().find(|(_, a)| *a == c) {
+                septets.extend([ESCAPE, *code]);
+            } else {
+                return None
+            }
+        }
+
+        Some(septets)
+    }
+
+    /// Decodes unpacked septets into text
+    ///
+    /// An escaped septet missing from the shift table falls back to the locking table, as the spec asks.
+    pub fn decode(&self, septets: &[u8]) -> String {
+        let locking = self.locking_table();
+        let single = self.single_shift_table();
+        let mut text = String::with_capacity(septets.len());
+        let mut septets = septets.iter().map(|s| s & 0x7f);
+
+        while let Some(septet) = septets.next() {
+            let c = match septet {
+                ESCAPE => match septets.next() {
+                    Some(code) => single.iter().find(|(c, _)| *c == code).map(|(_, a)| *a).unwrap_or(locking[code as usize]),
+                    None => ' '
+                },
+                _ => locking[septet as usize]
+            };
+
+            text.push(if c == '\u{1b}' { ' ' } else { c });
+        }
+
+        text
+    }
+
+    /// User data header octets needed to announce these tables, not counting the header length octet
+    pub fn header_length(&self) -> usize {
+        SHIFT_ELEMENT_LENGTH * (self.locking.is_some() as usize + self.single.is_some() as usize)
+    }
+}
+
+/// How a message's text should be sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gsm7(ShiftTables),
+    Ucs2
+}
+
+/// Picks whichever encoding fits the text in the fewest bits, header included
+///
+/// The default alphabet wins ties, since every phone can display it and it needs no header.
+pub fn cheapest_encoding(text: &str) -> Encoding {
+    let mut candidates = vec![ShiftTables::default()];
+    for language in NationalLanguage::ALL {
+        candidates.push(ShiftTables { locking: None, single: Some(language) });
+        if language.locking_table().is_some() {
+            candidates.push(ShiftTables { locking: Some(language), single: None });
+            candidates.push(ShiftTables { locking: Some(language), single: Some(language) });
+        }
+    }
+
+    let ucs2_bits = text.encode_utf16().count() * 16;
+
+    candidates.into_iter()
+        .filter_map(|tables| {
+            let septets = tables.encode(text)?.len();
+            let header_bits = match tables.header_length() {
+                0 => 0,
+                // Text after a header starts on the next septet boundary
+                length => ((length + 1) * 8).div_ceil(7) * 7
+            };
+
+            Some((header_bits + septets * 7, Encoding::Gsm7(tables)))
+        })
+        .chain(iter::once((ucs2_bits, Encoding::Ucs2)))
+        .min_by_key(|(bits, _)| *bits)
+        .map(|(_, encoding)| encoding)
+        .unwrap_or(Encoding::Ucs2)
+}
+
+/// Encodes text with the default alphabet and extension table, or `None` if a character isn't in either
+pub fn encode(text: &str) -> Option<Vec<u8>> {
+    ShiftTables::default().encode(text)
+}
+
+/// Decodes septets with the default alphabet and extension table
+pub fn decode(septets: &[u8]) -> String {
+    ShiftTables::default().decode(septets)
+}
+
+/// Packs septets into octets, starting `fill_bits` into the first octet
+///
+/// The fill bits are left as zero, they're used to align text after a user data header.
+pub fn pack_septets(septets: &[u8], fill_bits: usize) -> Vec<u8> {
+    let total_bits = fill_bits + septets.len() * 7;
+    let mut packed = vec![0u8; total_bits.div_ceil(8)];
+
+    for (i, septet) in septets.iter().enumerate() {
+        let bit = fill_bits + i * 7;
+        let (byte, shift) = (bit / 8, bit % 8);
+        let value = ((*septet & 0x7f) as u16) << shift;
+
+        packed[byte] |= value as u8;
+        if shift > 1 {
+            packed[byte + 1] |= (value >> 8) as u8;
+        }
+    }
+
+    packed
+}
+
+/// Unpacks `count` septets from octets, skipping `fill_bits` at the start
+pub fn unpack_septets(packed: &[u8], count: usize, fill_bits: usize) -> Vec<u8> {
+    (0..count).filter_map(|i| {
+        let bit = fill_bits + i * 7;
+        let (byte, shift) = (bit / 8, bit % 8);
+
+        let low = *packed.get(byte)? as u16;
+        let high = packed.get(byte + 1).copied().unwrap_or(0) as u16;
+
+        Some((((high << 8) | low) >> shift) as u8 & 0x7f)
+    }).collect()
+}
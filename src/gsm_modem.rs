@@ -1,260 +1,945 @@
 use dbus::Message;
-use regex::{Captures, Regex};
-use tokio::sync::mpsc::{Sender, Receiver};
-use tokio_serial::{SerialPortBuilderExt, SerialStream};
-use std::{error::Error, io, sync::{Arc, Mutex}, time::Duration};
+use regex::Regex;
+use tokio::{sync::{broadcast, mpsc::{self, Sender}, oneshot}, task::JoinHandle};
+use std::{sync::{atomic::{AtomicU16, Ordering}, Arc, Mutex}, time::Duration};
 
-use crate::{constants::{ModemError, ModemErrorType, ResultCodes, SmsFormat, SmsMessage, SmsStatus, UnsolicitedResultCode}, utils::is_valid_imei};
+use crate::{actor::{self, Command}, archive::MessageArchive, broadcast::BroadcastChannels, concat::{IncompleteMessage, Reassembler}, constants::{default_command_timeout, MessagePipelineOptions, NewMessageIndications, SendOptions, SmsFormat, SmsMessage, SmsParameters, SmsStatus}, data_message::{DataMessage, PortRouter}, delivery::{DeliveryReport, DeliveryTracker, SentMessage}, error::{Error, Result}, events::{BackgroundTask, DirectSms, ModemEvent}, gsm7, outbox::{Outbox, OutboxMessage, OutboxOptions, OutboxState}, pdu::{Address, Alphabet, ApplicationPort, ConcatReference, Pdu, SmsSubmit, StoredPdu, Tpdu, WaitingMessageKind}, phone_number::PhoneNumber, storage::{CleanupPolicy, DeleteFlag, PreferredStorage, SmsStorage}, transport::TransportConfig, ussd::{UssdResponse, UssdSession, DEFAULT_USSD_TIMEOUT}, utils::{bytes_to_hex, decode_number, is_valid_imei, utf16_to_hex}};
 
+/// Handle to a GSM modem
+///
+/// Cloning is cheap and every clone talks to the same connection, so the modem can be shared between tasks.
+#[derive(Clone)]
 pub struct GsmModem {
-    port_path: &'static str,
-    baud_rate: u32,
-    timeout_duration: Duration,
-    sender: Sender<String>,
-    receiver: Arc<Mutex<Receiver<String>>>
+    inner: Arc<ModemInner>
 }
 
+struct ModemInner {
+    transport: TransportConfig,
+    /// Queue into the command actor, `None` while the modem is closed
+    commands: Mutex<Option<Sender<Command>>>,
+    /// The actor task that owns the connection
+    actor: Mutex<Option<JoinHandle<()>>>,
+    /// Unsolicited events, outlives any single connection so subscriptions survive a reopen
+    events: broadcast::Sender<ModemEvent>,
+    /// Reference for the next concatenated message sent
+    concat_reference: AtomicU16,
+    /// Sent messages waiting on status reports, shared with the actor which matches reports to them
+    deliveries: DeliveryTracker,
+    /// Held across command sequences that depend on the SMS format or read storage selected on the modem,
+    /// so another task can't switch them in between (ie. the pipeline reading in PDU mode mid text send)
+    sms_context: tokio::sync::Mutex<()>
+}
 
-/*
 
-NOTE TO SELF
+impl GsmModem {
+    pub fn new(transport: TransportConfig) -> Self {
+        GsmModem {
+            inner: Arc::new(ModemInner {
+                transport: transport,
+                commands: Mutex::new(None),
+                actor: Mutex::new(None),
+                events: broadcast::channel(64).0,
+                concat_reference: AtomicU16::new(0),
+                deliveries: DeliveryTracker::default(),
+                sms_context: tokio::sync::Mutex::new(())
+            })
+        }
+    }
 
-Maybe map URCs to mutexes to be able to handle states of things, pass that into recieve loop?
+    /// Connects the transport, starts the command actor that owns it for the lifetime of the connection and configures the modem
+    ///
+    /// Port settings are applied here and only here, every command and URC share this one connection.
+    /// The modem is reset and set up the way every other method expects (no echo, numeric errors and
+    /// UCS2 between us and the modem), if that fails the connection is closed again.
+    pub async fn open(&self) -> Result<()> {
+        if self.is_open() {
+            return Err(Error::AlreadyOpen)
+        }
 
- - New texts
- - Ringing
- - Missed calls
- - etc
+        let transport = self.inner.transport.connect().await?;
+        let (tx, rx) = mpsc::channel(200);
 
-*/
+        {
+            let mut commands = self.inner.commands.lock().unwrap();
+            // Another task may have opened the modem while we were connecting
+            if commands.is_some() {
+                return Err(Error::AlreadyOpen)
+            }
 
+            *commands = Some(tx);
+            *self.inner.actor.lock().unwrap() = Some(tokio::spawn(actor::run(transport, rx, self.inner.events.clone(), self.inner.deliveries.clone())));
+        }
 
+        if let Err(e) = self.configure().await {
+            let _ = self.close().await;
+            return Err(e)
+        }
 
-impl GsmModem {
-    pub fn new(port_path: &'static str, baud_rate: u32, timeout_duration: Duration) -> Self {
-        let (tx, rx): (Sender<String>, Receiver<String>) = tokio::sync::mpsc::channel(200);
-        let safe_rx = Arc::new(Mutex::new(rx));
-        GsmModem { port_path: port_path, baud_rate: baud_rate, timeout_duration: timeout_duration, sender: tx, receiver: safe_rx}
+        Ok(())
+    }
+
+    /// Closes the connection, any commands still waiting on a response will fail
+    pub async fn close(&self) -> Result<()> {
+        if self.inner.commands.lock().unwrap().take().is_none() {
+            return Err(Error::NotOpen)
+        }
+
+        let actor = self.inner.actor.lock().unwrap().take();
+        if let Some(actor) = actor {
+            actor.abort();
+            // The only error here is the cancellation we just asked for
+            let _ = actor.await;
+        }
+
+        Ok(())
+    }
+
+    /// Subscribes to unsolicited events (new messages, calls, timezone changes, ...)
+    ///
+    /// Only events received after subscribing are delivered. A subscriber that falls too far behind
+    /// gets `RecvError::Lagged` and skips ahead to the oldest event still buffered.
+    pub fn subscribe(&self) -> broadcast::Receiver<ModemEvent> {
+        self.inner.events.subscribe()
     }
 
-    async fn configure(&self) -> Result<(), Box<dyn Error>> {
+    /// Whether `open()` has been called without a matching `close()`
+    pub fn is_open(&self) -> bool {
+        self.inner.commands.lock().unwrap().is_some()
+    }
+
+    async fn configure(&self) -> Result<()> {
         // Clear existing config on the modem
-        self.write_data(String::from("ATZ\r"), None).await?;
+        self.write_data(String::from("ATZ\r")).await?;
 
         // Disable echo on the modem
-        self.write_data(String::from("ATE0\r"), None).await?;
+        self.write_data(String::from("ATE0\r")).await?;
 
         // Set error codes to numeric
-        self.write_data(String::from("AT+CMEE=1\r"), None).await?;
+        self.write_data(String::from("AT+CMEE=1\r")).await?;
 
         // Set the PDU mode to text
-        self.write_data(String::from("AT+CMGF=1\r"), None).await?;
+        self.write_data(String::from("AT+CMGF=1\r")).await?;
 
         // Make all responses around SMS numbers/content hex that can be converted to UTF-16
-        self.write_data(String::from("AT+CSCS=\"UCS2\"\r"), None).await?;
+        // This is only the character set between us and the modem, the encoding used over the air is picked per message
+        self.write_data(String::from("AT+CSCS=\"UCS2\"\r")).await?;
 
         Ok(())
 
     }
 
-    fn get_port(&self) -> Result<SerialStream, Box<dyn Error>> {
-        let mut port = tokio_serial::new(self.port_path, self.baud_rate).timeout(self.timeout_duration).open_native_async()?;
-        port.set_exclusive(false)?;
-
-        Ok(port)
-    }
-
-    pub async fn recieve_data_loop(&self) -> Result<(), Box<dyn Error>> {
-        let mut port = self.get_port().unwrap();
-        // All lines should end with '\r\n' except when sending a text message, which prompts with '\r\n'
-        let line_end_re = Regex::new(r"(?:(\r\n)|(\r\n> ))$").unwrap();
-
-        let mut string_buf = String::new();
-        let mut serial_buf: Vec<u8> = vec![0; 1000];
-
-        let urc_regex = UnsolicitedResultCode::get_regex_array();
-        loop {
-            use tokio::io::AsyncReadExt;
-            match port.read(serial_buf.as_mut_slice()).await {
-                Ok(t) => {
-                    let mut urc_detected = false;
-                    let encoded_str = str::from_utf8(&serial_buf[..t]).unwrap();
-                    string_buf.push_str(encoded_str);
-                    urc_regex.iter().for_each(|(urc, regex)| {
-                        if !urc_detected {
-                            if let Some(cap) = regex.captures(&string_buf) {
-                                match urc {
-                                    UnsolicitedResultCode::MissedCall => {
-                                        println!("Missed call from {} at {}", cap.get(2).unwrap().as_str(), cap.get(1).unwrap().as_str())
-                                    }
-                                    _ => {
-                                        println!("URC DETECTED!");
-                                        println!("{:?}", string_buf);
-                                    }
-                                }
-                                urc_detected = true;
-                                string_buf.clear();
-                            }
-                        }
-                    });
-                    if line_end_re.is_match(&string_buf) && !urc_detected {
-                        println!("MATCHED: {:?}", string_buf);
-                        self.sender.send(string_buf.clone()).await.unwrap();
-                        string_buf.clear();
-                    }
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => println!("timed out"),
-                Err(e) => eprintln!("{:?}", e),
-            }
-        }
+    /// Queues a command and waits for its final result code, using the default timeout for the command
+    ///
+    /// Returns the full response on `OK`, or an error if the modem returned `ERROR`/`+CME ERROR`/`+CMS ERROR`
+    /// or `Error::Timeout` if nothing final came back in time.
+    ///
+    /// Cancel safe: dropping the future before the command is sent removes it from the queue,
+    /// and once sent the modem is still walked through to its final result code.
+    pub async fn write_data(&self, data: String) -> Result<String> {
+        let timeout = default_command_timeout(&data);
+        self.queue_command(data, None, timeout).await
     }
 
-    pub async fn write_data(&self, data: String, end_seqs: Option<(Regex, Regex)>) -> Result<String, Box<dyn Error>>{
-        let default_seqs = (Regex::new(ResultCodes::Ok.as_regex_str())?, Regex::new(&ResultCodes::get_error_catchall())?);
-        let end_seqs = end_seqs.unwrap_or(default_seqs);
-        
-        let (good_seq, error_seq) = end_seqs;
+    /// Same as `write_data` but with an explicit deadline
+    pub async fn write_data_with_timeout(&self, data: String, timeout: Duration) -> Result<String> {
+        self.queue_command(data, None, timeout).await
+    }
 
-        let mut port = self.get_port()?;
-        use tokio::io::AsyncWriteExt;
-        match port.write(data.as_bytes()).await {
-            Ok(_) => (),
-            Err(_) => return Err("Failed to write data".into())
-        }
+    /// Queues a command that prompts for input (ie. `AT+CMGS`), sending the payload once the modem prompts with `> `
+    ///
+    /// The command and payload are handled as one unit, so no other command can slip in between them.
+    /// If the future is dropped before the prompt arrives, the prompt is escaped instead of sending the payload.
+    pub async fn write_data_with_prompt(&self, data: String, payload: String) -> Result<String> {
+        let timeout = default_command_timeout(&data);
+        self.queue_command(data, Some(payload), timeout).await
+    }
 
-        let mut loop_iter = 0;
-        let mut recv_buf = String::new();
-
-        loop {
-            loop_iter += 1;
-            if let Some(received) = self.receiver.lock().unwrap().recv().await {
-                recv_buf.push_str(&received);
-                if good_seq.is_match(&recv_buf) {
-                    return Ok(recv_buf);
-                } else if error_seq.is_match(&recv_buf) {
-                    // If an error code was given, extract it
-                    if let Some(capture) = Regex::new(ResultCodes::ErrorAndCode.as_regex_str())?.captures(&recv_buf) {
-                        let Some(error_type) = (match &capture[1] {
-                            "CME" => Some(ModemErrorType::CmeError),
-                            "CMS" => Some(ModemErrorType::CmsError),
-                            _ => None
-                        }) else {
-                            return Err("Failed to parse modem error type!".into())
-                        };
+    async fn queue_command(&self, data: String, payload: Option<String>, timeout: Duration) -> Result<String> {
+        let sender = self.inner.commands.lock().unwrap().clone().ok_or(Error::NotOpen)?;
+        let (responder, response) = oneshot::channel();
 
-                        let error_code: i32 = capture[2].parse()?;
+        sender.send(Command { data: data, payload: payload, timeout: timeout, responder: responder }).await.map_err(|_| Error::ConnectionClosed)?;
 
-                        // TODO: Definitely a better way to go about this
-                        return Err(ModemError::new(error_type, error_code).as_string().into())
-                    }
+        response.await.map_err(|_| Error::ConnectionClosed)?
+    }
 
-                    return Err("Generic error was returned".into())
-                }
-            };
-            // TODO: Debug print, remove
-            if loop_iter > 1000 {
-                println!("WRITE DATA RECIEVE LOOP HAS RAN 1000 TIMES!")
-            }
-        }
+    /// Sends a text message in text mode
+    ///
+    /// The content always goes to the modem as UCS2 hex, but the message is sent over the air as GSM 7-bit
+    /// when every character fits the default alphabet, which more than doubles how much fits in one SMS.
+    /// National shift tables and concatenation headers need a user data header, which text mode can't send,
+    /// so content too long for one message is sent in parts with `send_pdu_sms` instead.
+    ///
+    /// Use `MessageLength::measure` to find out how many parts a message takes before sending it.
+    ///
+    /// The destination is checked with `PhoneNumber::parse` first, so an invalid number fails with
+    /// `Error::InvalidArgument` without reaching the modem.
+    ///
+    /// Returns the message reference assigned by the modem to every part, in order
+    pub async fn send_text_sms(&self, destination: &str, content: &str) -> Result<Vec<u8>> {
+        self.send_text_sms_with_options(destination, content, &SendOptions::default()).await
     }
 
-    pub async fn send_text_sms(&self, destination: &String, content: &String) -> Result<(), Box<dyn Error>> {
-        self.set_sms_format(SmsFormat::Text).await?;
-        let command = format!("AT+CMGS=\"{}\"\r", destination);
-        let message = format!("{}\x1a", content);
-        let expected_seqs = (Regex::new(ResultCodes::AwaitingInput.as_regex_str()).unwrap(), Regex::new(ResultCodes::get_error_catchall().as_str()).unwrap());
-        self.write_data(command, Some(expected_seqs)).await?;
-        self.write_data(message, None).await?;
+    /// Same as `send_text_sms`, with the option to request a status report
+    ///
+    /// Status reports arrive as `ModemEvent::DeliveryReport` (or `ModemEvent::StatusReportStored` if the modem
+    /// is set to store them), matched up with the message they're for. Set `SendOptions::class` to 0 to send
+    /// a flash message.
+    pub async fn send_text_sms_with_options(&self, destination: &str, content: &str, options: &SendOptions) -> Result<Vec<u8>> {
+        let number = PhoneNumber::parse(destination)?;
+        let (dcs, fits) = match gsm7::encode(content) {
+            Some(septets) => (options.data_coding_scheme(Alphabet::Gsm7)?, septets.len() <= 160),
+            None => (options.data_coding_scheme(Alphabet::Ucs2)?, content.encode_utf16().count() <= 70)
+        };
+
+        if !fits {
+            return self.send_pdu_sms_with_options(destination, content, options).await
+        }
 
-        Ok(())
+        let _context = self.inner.sms_context.lock().await;
+        self.write_sms_format(SmsFormat::Text).await?;
+
+        // TP-SRR (0x20) on top of the defaults requests a status report
+        let defaults = SmsParameters::default();
+        let parameters = SmsParameters {
+            first_octet: if options.status_report { defaults.first_octet | 0x20 } else { defaults.first_octet },
+            validity_period: options.validity_period.clone().or(defaults.validity_period),
+            protocol_identifier: 0,
+            data_coding_scheme: dcs
+        };
+        self.set_sms_parameters(&parameters).await?;
+
+        let command = format!("AT+CMGS=\"{}\",{}\r", utf16_to_hex(&number.to_string()), number.type_of_address());
+        let message = format!("{}\x1a", utf16_to_hex(content));
+        let resp = self.write_data_with_prompt(command, message).await?;
+        let reference = parse_message_reference(&resp)?;
+
+        self.sent(SentMessage::new(&number.to_string(), content, vec![reference]), options.status_report);
+
+        Ok(vec![reference])
     }
 
-    pub async fn get_imei(&self) -> Result<String, Box<dyn Error>> {
-        let resp = self.write_data(String::from("AT+SIMEI?\r"), None).await?;
+    pub async fn get_imei(&self) -> Result<String> {
+        let resp = self.write_data(String::from("AT+SIMEI?\r")).await?;
 
-        let imei_captures: Result<regex::Captures<'_>, Box<dyn Error>> = Regex::new(r"\+SIMEI: (\d{15})")?.captures(&resp).ok_or_else(|| Err("Failed to parse IMEI!").unwrap());
+        let imei_captures = Regex::new(r"\+SIMEI: (\d{15})").unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to parse IMEI!", &resp))?;
 
-        Ok(String::from(imei_captures?.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse IMEI!").unwrap())?.as_str()))
+        Ok(String::from(imei_captures.get(1).ok_or_else(|| Error::parse("Failed to parse IMEI!", &resp))?.as_str()))
 
     }
 
-    pub async fn set_imei(&self, intended_imei: String) -> Result<(), Box<dyn Error>> {
+    pub async fn set_imei(&self, intended_imei: String) -> Result<()> {
         if !is_valid_imei(&intended_imei) {
-            return Err("IMEI is not valid!".into())
+            return Err(Error::invalid_argument("IMEI is not valid!"))
         }
         let command = format!("AT+SIMEI={}\r", intended_imei);
-        self.write_data(command, None).await?;
+        self.write_data(command).await?;
 
         Ok(())
     }
 
-    pub async fn get_sms_format(&self) -> Result<SmsFormat, Box<dyn Error>> {
-        let resp = self.write_data(String::from("AT+CMGF?\r"), None).await?;
+    pub async fn get_sms_format(&self) -> Result<SmsFormat> {
+        let resp = self.write_data(String::from("AT+CMGF?\r")).await?;
+
+        let mode_captures = Regex::new(r"\+CMGF: (1|0)").unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to retrieve SMS format!", &resp))?;
+
+        SmsFormat::try_from(String::from(mode_captures.get(1).ok_or_else(|| Error::parse("Failed to retrieve SMS format!", &resp))?.as_str()))
+    }
 
-        let mode_captures: Result<regex::Captures<'_>, Box<dyn Error>> = Regex::new(r"\+CMGF: (1|0)")?.captures(&resp).ok_or_else(|| Err("Failed to retrieve SMS format!").unwrap());
+    /// Sets the SMS format, methods that depend on it set it themselves so this only matters for raw commands
+    pub async fn set_sms_format(&self, format: SmsFormat) -> Result<()> {
+        let _context = self.inner.sms_context.lock().await;
 
-        SmsFormat::try_from(String::from(mode_captures?.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to retrieve SMS format!").unwrap())?.as_str()))
+        self.write_sms_format(format).await
     }
 
-    pub async fn set_sms_format(&self, format: SmsFormat) -> Result<(), Box<dyn Error>> {
+    /// Sets the SMS format, callers hold the SMS context lock for as long as they depend on it
+    async fn write_sms_format(&self, format: SmsFormat) -> Result<()> {
         let command = format!("AT+CMGF={}\r", Into::<u8>::into(format));
-        self.write_data(command, None).await?;
+        self.write_data(command).await?;
 
         Ok(())
     }
     
-    pub async fn get_signal_quality(&self) -> Result<(u8, u8), Box<dyn Error>> {
+    /// The service centre number messages are sent through, as set on the SIM
+    pub async fn get_service_centre(&self) -> Result<PhoneNumber> {
+        let resp = self.write_data(String::from("AT+CSCA?\r")).await?;
+
+        let captures = Regex::new(r#"\+CSCA: "([^"]*)",(\d+)"#).unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to parse service centre!", &resp))?;
+
+        let raw_number = captures.get(1).ok_or_else(|| Error::parse("Failed to parse service centre!", &resp))?.as_str();
+        let number = decode_number(raw_number, captures.get(2).map(|toa| toa.as_str()));
+
+        PhoneNumber::parse(&number).map_err(|_| Error::parse("Invalid service centre number!", &resp))
+    }
+
+    /// Sets the service centre number messages are sent through, ie. to fix a SIM with a wrong or missing one
+    pub async fn set_service_centre(&self, number: &PhoneNumber) -> Result<()> {
+        let command = format!("AT+CSCA=\"{}\",{}\r", utf16_to_hex(&number.to_string()), number.type_of_address());
+        self.write_data(command).await?;
+
+        Ok(())
+    }
+
+    /// Sets the parameters for messages sent in text mode (`AT+CSMP`)
+    ///
+    /// `send_text_sms` sets these itself for every message it sends, use `SendOptions::validity_period`
+    /// to control how long its messages are valid for.
+    pub async fn set_sms_parameters(&self, parameters: &SmsParameters) -> Result<()> {
+        self.write_data(parameters.to_command()).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_sms_parameters(&self) -> Result<SmsParameters> {
+        let resp = self.write_data(String::from("AT+CSMP?\r")).await?;
+
+        SmsParameters::from_csmp(resp)
+    }
+
+    /// Whether text mode message listings include the header details (`AT+CSDH`), ie. the service centre and data coding scheme
+    pub async fn set_show_text_mode_headers(&self, enable: bool) -> Result<()> {
+        let command = format!("AT+CSDH={}\r", enable as u8);
+        self.write_data(command).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_show_text_mode_headers(&self) -> Result<bool> {
+        let resp = self.write_data(String::from("AT+CSDH?\r")).await?;
+
+        let mode_captures = Regex::new(r"\+CSDH: (1|0)").unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to retrieve text mode header config!", &resp))?;
+
+        Ok(&mode_captures[1] == "1")
+    }
+
+    pub async fn get_signal_quality(&self) -> Result<(u8, u8)> {
         // Helpful for understanding CSQ values: https://m2msupport.net/m2msupport/atcsq-signal-quality/
 
-        let resp = self.write_data(String::from("AT+CSQ\r"), None).await?;
+        let resp = self.write_data(String::from("AT+CSQ\r")).await?;
 
-        let csq_captures = Regex::new(r"\+CSQ: (\d{0,3}),(\d{0,2})")?.captures(&resp).ok_or_else(|| Err::<String, Box<dyn Error>>("Failed to parse CSQ & Bit Error Rate!".into()).unwrap())?;
+        let csq_captures = Regex::new(r"\+CSQ: (\d{0,3}),(\d{0,2})").unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to parse CSQ & Bit Error Rate!", &resp))?;
 
-        let csq = csq_captures.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse CSQ value!").unwrap())?.as_str().parse::<u8>()?;
+        let csq = csq_captures.get(1)
+            .and_then(|csq| csq.as_str().parse::<u8>().ok())
+            .ok_or_else(|| Error::parse("Failed to parse CSQ value!", &resp))?;
 
         // Seems like in most cases the bit error rate is unused (?) but include it anyway
-        let ber = csq_captures.get(2).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse bit error rate!").unwrap())?.as_str().parse::<u8>()?;
+        let ber = csq_captures.get(2)
+            .and_then(|ber| ber.as_str().parse::<u8>().ok())
+            .ok_or_else(|| Error::parse("Failed to parse bit error rate!", &resp))?;
 
         Ok((csq, ber))
 
     }
 
-    pub async fn set_auto_timezone_updates_config(&self, enable: bool) -> Result<(), Box<dyn Error>> {
+    pub async fn set_auto_timezone_updates_config(&self, enable: bool) -> Result<()> {
         let setting = if enable {"1"} else {"0"};
 
         let command = format!("AT+CTZU={}\r", setting);
 
-        self.write_data(command, None).await?;
+        self.write_data(command).await?;
 
         Ok(())
     }
 
-    pub async fn get_auto_timezone_updates_config(&self) -> Result<bool, Box<dyn Error>> {
-        let resp = self.write_data(String::from("AT+CTZU?\r"), None).await?;
+    pub async fn get_auto_timezone_updates_config(&self) -> Result<bool> {
+        let resp = self.write_data(String::from("AT+CTZU?\r")).await?;
 
-        let mode_captures: Result<regex::Captures<'_>, Box<dyn Error>> = Regex::new(r"\+CTZU: (1|0)")?.captures(&resp).ok_or_else(|| Err("Failed to retrieve SMS format!").unwrap());
+        let mode_captures = Regex::new(r"\+CTZU: (1|0)").unwrap().captures(&resp).ok_or_else(|| Error::parse("Failed to retrieve timezone update config!", &resp))?;
 
-        let mode = mode_captures?.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to retrieve SMS format!").unwrap())?.as_str();
+        let mode = mode_captures.get(1).ok_or_else(|| Error::parse("Failed to retrieve timezone update config!", &resp))?.as_str();
 
         match mode {
             "0" => Ok(false),
             "1" => Ok(true),
-            _ => Err("Invalid timezone update config returned from modem".into())
+            _ => Err(Error::parse("Invalid timezone update config returned from modem", &resp))
         }
     }
 
-    pub async fn get_sms_message(&self, mem_index: u32) -> Result<SmsMessage, Box<dyn Error>> {
+    pub async fn get_sms_message(&self, mem_index: u32) -> Result<SmsMessage> {
+        let _context = self.inner.sms_context.lock().await;
+        self.write_sms_format(SmsFormat::Text).await?;
         let command = format!("AT+CMGR={}\r", mem_index);
-        let resp = self.write_data(command, None).await?;
+        let resp = self.write_data(command).await?;
 
         SmsMessage::from_cmgr(resp, mem_index)
     }
 
-    pub async fn get_sms_messages(&self, status: SmsStatus) -> Result<Vec<SmsMessage>, Box<dyn Error>> {
+    pub async fn get_sms_messages(&self, status: SmsStatus) -> Result<Vec<SmsMessage>> {
+        let _context = self.inner.sms_context.lock().await;
+        self.write_sms_format(SmsFormat::Text).await?;
         let command = format!("AT+CMGL=\"{}\"\r", status.as_str());
-        let resp = self.write_data(command, None).await?;
+        let resp = self.write_data(command).await?;
 
         SmsMessage::from_cmgl(resp)
     }
 
+    /// Sends a text message in PDU mode, picking GSM 7-bit or UCS2 for the content and splitting it into
+    /// concatenated parts if it doesn't fit in one message
+    ///
+    /// Returns the message reference assigned by the modem to every part, in order
+    pub async fn send_pdu_sms(&self, destination: &str, content: &str) -> Result<Vec<u8>> {
+        self.send_pdu_sms_with_options(destination, content, &SendOptions::default()).await
+    }
+
+    /// Same as `send_pdu_sms`, with the option to request a status report for every part and pick the concatenation reference
+    ///
+    /// The message is only reported delivered once every part is.
+    pub async fn send_pdu_sms_with_options(&self, destination: &str, content: &str, options: &SendOptions) -> Result<Vec<u8>> {
+        let number = PhoneNumber::parse(destination)?;
+        let reference = options.concat_reference.unwrap_or_else(|| ConcatReference::EightBit(self.next_concat_reference() as u8));
+
+        let mut references = Vec::new();
+        for mut segment in SmsSubmit::segments(Address::from(&number), content, reference)? {
+            segment.data_coding_scheme = options.data_coding_scheme(segment.data_coding_scheme.alphabet())?;
+            segment.status_report_request = options.status_report;
+            segment.validity_period = options.validity_period.clone();
+            references.push(self.send_submit(&segment).await?);
+        }
+
+        self.sent(SentMessage::new(&number.to_string(), content, references.clone()), options.status_report);
+
+        Ok(references)
+    }
+
+    /// Sends 8-bit data to an application port on the destination (ie. a device in the field), in PDU mode
+    ///
+    /// Data that doesn't fit in one message is sent in concatenated parts, each addressed to the port.
+    /// The destination is checked with `PhoneNumber::parse` first.
+    ///
+    /// Returns the message reference assigned by the modem to every part, in order
+    pub async fn send_data_sms(&self, destination: &str, port: ApplicationPort, data: &[u8]) -> Result<Vec<u8>> {
+        self.send_data_sms_with_options(destination, port, data, &SendOptions::default()).await
+    }
+
+    /// Same as `send_data_sms`, with the options of `send_pdu_sms_with_options`
+    ///
+    /// Data messages aren't reported as `ModemEvent::SmsSent`, which is for messages people read, but they
+    /// are still matched up with status reports when one is requested.
+    pub async fn send_data_sms_with_options(&self, destination: &str, port: ApplicationPort, data: &[u8], options: &SendOptions) -> Result<Vec<u8>> {
+        let number = PhoneNumber::parse(destination)?;
+        let reference = options.concat_reference.unwrap_or_else(|| ConcatReference::EightBit(self.next_concat_reference() as u8));
+
+        let mut references = Vec::new();
+        for mut segment in SmsSubmit::data_segments(Address::from(&number), port, data, reference)? {
+            segment.data_coding_scheme = options.data_coding_scheme(Alphabet::EightBit)?;
+            segment.status_report_request = options.status_report;
+            segment.validity_period = options.validity_period.clone();
+            references.push(self.send_submit(&segment).await?);
+        }
+
+        if options.status_report {
+            self.inner.deliveries.track(SentMessage::new(&number.to_string(), &bytes_to_hex(data), references.clone()));
+        }
+
+        Ok(references)
+    }
+
+    /// Returns a new concatenation reference, truncate it for an 8-bit reference
+    pub fn next_concat_reference(&self) -> u16 {
+        self.inner.concat_reference.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sends an SMS-SUBMIT as-is in PDU mode, using the modem's configured service centre
+    ///
+    /// Returns the message reference assigned by the modem. If the SMS-SUBMIT requests a status report,
+    /// the message is tracked on its own for `ModemEvent::DeliveryReport`.
+    pub async fn send_pdu(&self, submit: &SmsSubmit) -> Result<u8> {
+        let reference = self.send_submit(submit).await?;
+
+        let content = submit.user_data.text().unwrap_or_default();
+        self.sent(SentMessage::new(&submit.destination.to_string(), content, vec![reference]), submit.status_report_request);
+
+        Ok(reference)
+    }
+
+    /// Emits `ModemEvent::SmsSent`, and tracks the message for status reports if one was requested
+    fn sent(&self, message: SentMessage, status_report: bool) {
+        if status_report {
+            self.inner.deliveries.track(message.clone());
+        }
+        self.emit(ModemEvent::SmsSent(message));
+    }
+
+    async fn send_submit(&self, submit: &SmsSubmit) -> Result<u8> {
+        let (pdu, length) = Pdu { smsc: None, tpdu: Tpdu::Submit(submit.clone()) }.encode()?;
+
+        let _context = self.inner.sms_context.lock().await;
+        self.write_sms_format(SmsFormat::ProtocolDataUnit).await?;
+        let command = format!("AT+CMGS={}\r", length);
+        let resp = self.write_data_with_prompt(command, format!("{}\x1a", pdu)).await?;
+
+        parse_message_reference(&resp)
+    }
+
+    /// Reads a status report saved to storage (ie. after `ModemEvent::StatusReportStored`), matched up with the message it's for
+    pub async fn read_delivery_report(&self, mem_index: u32) -> Result<DeliveryReport> {
+        let stored = self.get_pdu_sms_message(mem_index).await?;
+
+        let Tpdu::StatusReport(report) = &stored.pdu.tpdu else {
+            return Err(Error::invalid_argument("Message is not a status report!"))
+        };
+
+        let mut report = DeliveryReport::from_status_report(report);
+        self.inner.deliveries.correlate(&mut report);
+
+        Ok(report)
+    }
+
+    /// Sent messages still waiting on a final status report for at least one part, oldest first
+    pub fn pending_deliveries(&self) -> Vec<SentMessage> {
+        self.inner.deliveries.pending()
+    }
+
+    /// Reads a single message in PDU mode, keeping all of its metadata
+    pub async fn get_pdu_sms_message(&self, mem_index: u32) -> Result<StoredPdu> {
+        let _context = self.inner.sms_context.lock().await;
+
+        self.read_pdu(mem_index).await
+    }
+
+    /// Same as `get_pdu_sms_message` for a message in the given storage (ie. from `ModemEvent::NewSms`)
+    ///
+    /// The storage is only selected for reading while the message is read, the read storage set with
+    /// `set_preferred_storage` is put back afterwards.
+    pub async fn get_pdu_sms_message_from(&self, storage: SmsStorage, mem_index: u32) -> Result<StoredPdu> {
+        let _context = self.inner.sms_context.lock().await;
+
+        let read_storage = PreferredStorage::from_cpms_query(self.write_data(String::from("AT+CPMS?\r")).await?)?.read.storage;
+        if read_storage == storage {
+            return self.read_pdu(mem_index).await
+        }
+
+        self.write_data(format!("AT+CPMS=\"{}\"\r", storage.as_str())).await?;
+        let stored = self.read_pdu(mem_index).await;
+        self.write_data(format!("AT+CPMS=\"{}\"\r", read_storage.as_str())).await?;
+
+        stored
+    }
+
+    /// Reads a message in PDU mode, callers hold the SMS context lock
+    async fn read_pdu(&self, mem_index: u32) -> Result<StoredPdu> {
+        self.write_sms_format(SmsFormat::ProtocolDataUnit).await?;
+        let command = format!("AT+CMGR={}\r", mem_index);
+        let resp = self.write_data(command).await?;
+
+        StoredPdu::from_cmgr(resp, mem_index)
+    }
+
+    /// Lists messages with the given status in PDU mode
+    pub async fn get_pdu_sms_messages(&self, status: SmsStatus) -> Result<Vec<StoredPdu>> {
+        let _context = self.inner.sms_context.lock().await;
+        self.write_sms_format(SmsFormat::ProtocolDataUnit).await?;
+        let command = format!("AT+CMGL={}\r", Into::<u8>::into(status));
+        let resp = self.write_data(command).await?;
+
+        StoredPdu::from_cmgl(resp)
+    }
+
+    /// Lists received messages with the given status, joining the parts of concatenated messages
+    ///
+    /// Returns the complete messages, and the messages with parts missing from storage.
+    /// Only received messages are listed, use `get_pdu_sms_messages` for stored outgoing ones.
+    pub async fn get_concatenated_sms_messages(&self, status: SmsStatus) -> Result<(Vec<SmsMessage>, Vec<IncompleteMessage>)> {
+        let mut reassembler = Reassembler::default();
+        let mut messages = Vec::new();
+
+        for stored in self.get_pdu_sms_messages(status).await? {
+            messages.extend(reassembler.push_stored(&stored));
+        }
+
+        Ok((messages, reassembler.take_incomplete()))
+    }
+
+    /// Sets how the modem tells us about new messages, status reports and cell broadcasts
+    pub async fn set_new_message_indications(&self, indications: &NewMessageIndications) -> Result<()> {
+        self.write_data(indications.to_command()).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_new_message_indications(&self) -> Result<NewMessageIndications> {
+        let resp = self.write_data(String::from("AT+CNMI?\r")).await?;
+
+        NewMessageIndications::from_cnmi(resp)
+    }
+
+    /// Sets which cell broadcasts the modem passes on
+    ///
+    /// Broadcasts also need `NewMessageIndications::broadcasts` set to arrive as `ModemEvent::CellBroadcast`.
+    pub async fn set_broadcast_channels(&self, channels: &BroadcastChannels) -> Result<()> {
+        let (message_identifiers, languages) = channels.to_lists();
+        let command = format!("AT+CSCB={},\"{}\",\"{}\"\r", channels.reject as u8, utf16_to_hex(&message_identifiers), utf16_to_hex(&languages));
+        self.write_data(command).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_broadcast_channels(&self) -> Result<BroadcastChannels> {
+        let resp = self.write_data(String::from("AT+CSCB?\r")).await?;
+
+        BroadcastChannels::from_cscb(resp)
+    }
+
+    /// Acknowledges the last message routed straight to us with `+CMT`
+    ///
+    /// Only needed after `AT+CSMS=1`, where the network resends unacknowledged messages
+    /// and the modem falls back to storing them.
+    pub async fn acknowledge_new_message(&self) -> Result<()> {
+        self.write_data(String::from("AT+CNMA\r")).await?;
+
+        Ok(())
+    }
+
+    /// Sends a USSD request (ie. `*100#` for a balance check) and waits for the network's answer
+    ///
+    /// If the answer is a menu (`UssdSession::is_open`), reply to it with `UssdSession::reply` until the
+    /// network ends the session. Gives up with `Error::Timeout` after `DEFAULT_USSD_TIMEOUT`.
+    pub async fn start_ussd(&self, request: &str) -> Result<UssdSession> {
+        self.start_ussd_with_timeout(request, DEFAULT_USSD_TIMEOUT).await
+    }
+
+    /// Same as `start_ussd` but with an explicit deadline for every answer in the session
+    pub async fn start_ussd_with_timeout(&self, request: &str, timeout: Duration) -> Result<UssdSession> {
+        if request.is_empty() {
+            return Err(Error::invalid_argument("USSD request can't be empty!"))
+        }
+
+        UssdSession::start(self, request, timeout).await
+    }
+
+    /// Picks up a menu the network started, from `ModemEvent::Ussd`, so it can be replied to
+    pub fn ussd_session(&self, response: UssdResponse) -> UssdSession {
+        UssdSession::resume(self, response, DEFAULT_USSD_TIMEOUT)
+    }
+
+    /// Ends the open USSD session, if there is one
+    pub async fn cancel_ussd(&self) -> Result<()> {
+        self.write_data(String::from("AT+CUSD=2\r")).await?;
+
+        Ok(())
+    }
+
+    /// Starts a task that turns new message notifications into `ModemEvent::SmsReceived` with the full message
+    ///
+    /// Messages indicated with `+CMTI` are read in PDU mode from the storage they were saved to, and messages routed straight to us
+    /// with `+CMT` are acknowledged (per the options). Concatenated messages are only emitted once every part
+    /// has arrived, or as `ModemEvent::IncompleteSms` once given up on. Messages addressed to an application
+    /// port are emitted as `ModemEvent::DataSmsReceived` instead.
+    ///
+    /// The task runs until the returned handle is aborted.
+    pub fn spawn_message_pipeline(&self, options: MessagePipelineOptions) -> JoinHandle<()> {
+        let modem = self.clone();
+        let mut events = self.subscribe();
+
+        tokio::spawn(async move {
+            let mut reassembler = Reassembler::new(options.reassembly_timeout);
+            // A zero interval would panic, and checking more often than this gains nothing
+            let mut expiry = tokio::time::interval(options.reassembly_timeout.clamp(Duration::from_millis(10), Duration::from_secs(60)));
+
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            modem.emit(ModemEvent::EventsMissed { task: BackgroundTask::MessagePipeline, missed: missed });
+                            continue
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return
+                    },
+                    _ = expiry.tick() => {
+                        for incomplete in reassembler.expire() {
+                            modem.emit(ModemEvent::IncompleteSms(incomplete));
+                        }
+                        continue
+                    }
+                };
+
+                match event {
+                    ModemEvent::NewSms { storage, index } => match modem.read_new_message(&storage, index).await {
+                        Ok(stored) => {
+                            if let Some(message) = reassembler.push_stored(&stored) {
+                                modem.received(message, Some(storage));
+                            }
+                        }
+                        Err(e) => modem.background_error(BackgroundTask::MessagePipeline, format!("Failed to read new message {}: {}", index, e))
+                    },
+                    ModemEvent::DirectSms(direct) => {
+                        if options.acknowledge && let Err(e) = modem.acknowledge_new_message().await {
+                            modem.background_error(BackgroundTask::MessagePipeline, format!("Failed to acknowledge new message: {}", e));
+                        }
+
+                        let message = match direct {
+                            DirectSms::Text(message) => Some(message),
+                            DirectSms::Pdu(deliver) => reassembler.push_direct(&deliver)
+                        };
+                        if let Some(message) = message {
+                            modem.received(message, None);
+                        }
+                    }
+                    _ => ()
+                }
+            }
+        })
+    }
+
+    /// Starts a task that hands every `ModemEvent::DataSmsReceived` to the router's handler for its destination port
+    ///
+    /// Data messages only arrive while the message pipeline is running. Messages no handler takes are dropped
+    /// and reported as `ModemEvent::BackgroundError`. The task runs until the returned handle is aborted.
+    pub fn spawn_port_router(&self, router: PortRouter) -> JoinHandle<()> {
+        let modem = self.clone();
+        let mut events = self.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(ModemEvent::DataSmsReceived { message, .. }) => {
+                        let port = message.port.destination;
+                        if !router.dispatch(message) {
+                            modem.background_error(BackgroundTask::PortRouter, format!("No handler for data message to port {}", port));
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => modem.emit(ModemEvent::EventsMissed { task: BackgroundTask::PortRouter, missed: missed }),
+                    Err(broadcast::error::RecvError::Closed) => return
+                }
+            }
+        })
+    }
+
+    /// Reads a message from `ModemEvent::NewSms`, from the storage it was saved to
+    async fn read_new_message(&self, storage: &str, index: u32) -> Result<StoredPdu> {
+        self.get_pdu_sms_message_from(SmsStorage::try_from_name(storage)?, index).await
+    }
+
+    /// Emits a complete received message, as `ModemEvent::DataSmsReceived` if it's addressed to an application port,
+    /// followed by `ModemEvent::VoicemailWaiting` if it indicates voicemail
+    fn received(&self, message: SmsMessage, storage: Option<String>) {
+        let voicemail = message.message_waiting().into_iter().find(|indication| indication.kind == WaitingMessageKind::Voicemail);
+
+        match DataMessage::from_message(&message) {
+            Some(data) => self.emit(ModemEvent::DataSmsReceived { message: data, storage: storage }),
+            None => self.emit(ModemEvent::SmsReceived { message: message, storage: storage })
+        }
+
+        if let Some(voicemail) = voicemail {
+            let count = if voicemail.active { voicemail.count } else { Some(0) };
+            self.emit(ModemEvent::VoicemailWaiting { count: count });
+        }
+    }
+
+    /// Starts a task that records every message sent, and every message from `spawn_message_pipeline`, in the archive
+    ///
+    /// Received messages are only archived while the message pipeline is running. The task runs until the
+    /// returned handle is aborted, and failed writes are reported as `ModemEvent::BackgroundError`.
+    pub fn spawn_archiver(&self, archive: MessageArchive) -> JoinHandle<()> {
+        let modem = self.clone();
+        let mut events = self.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let recorded = match events.recv().await {
+                    Ok(ModemEvent::SmsReceived { message, .. }) => archive.record(&message),
+                    Ok(ModemEvent::SmsSent(message)) => archive.record_sent(&message),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        modem.emit(ModemEvent::EventsMissed { task: BackgroundTask::Archiver, missed: missed });
+                        continue
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return
+                };
+
+                if let Err(e) = recorded {
+                    modem.background_error(BackgroundTask::Archiver, format!("Failed to archive message: {}", e));
+                }
+            }
+        })
+    }
+
+    /// Starts a task that sends every message queued in the outbox, oldest first, reporting each change of state
+    /// with `ModemEvent::OutboxUpdate`
+    ///
+    /// Attempts that fail with a retryable error (ie. `+CMS ERROR: 332`) are retried with exponential backoff, holding
+    /// back the rest of the queue so messages stay in order. Other errors, or running out of attempts, fail the message.
+    /// While the modem isn't open messages wait in the queue without using up their attempts.
+    ///
+    /// A timeout is retried too, but the modem can't say whether a `+CMGS` that timed out reached the network, so
+    /// a retry after a timeout can deliver the message twice.
+    ///
+    /// The task runs until the returned handle is aborted, run only one per outbox.
+    pub fn spawn_outbox(&self, outbox: Outbox, options: OutboxOptions) -> JoinHandle<()> {
+        let modem = self.clone();
+
+        tokio::spawn(async move {
+            let mut last_attempt: Option<tokio::time::Instant> = None;
+
+            loop {
+                let Some(mut message) = outbox.next() else {
+                    outbox.queued().await;
+                    continue
+                };
+
+                if let Some(last_attempt) = last_attempt {
+                    tokio::time::sleep_until(last_attempt + options.send_interval).await;
+                }
+                last_attempt = Some(tokio::time::Instant::now());
+
+                message.attempts += 1;
+                message.state = OutboxState::Sending;
+                modem.update_outbox(&outbox, &message);
+
+                let send_options = SendOptions { status_report: message.status_report, ..Default::default() };
+                match modem.send_text_sms_with_options(&message.destination, &message.content, &send_options).await {
+                    Ok(references) => {
+                        message.state = OutboxState::Sent;
+                        message.message_references = references;
+                        modem.update_outbox(&outbox, &message);
+                    }
+                    Err(Error::NotOpen) => {
+                        // Nothing reached the modem, so this doesn't count as an attempt
+                        message.attempts -= 1;
+                        message.state = OutboxState::Queued;
+                        message.last_error = Some(Error::NotOpen.to_string());
+                        modem.update_outbox(&outbox, &message);
+                        tokio::time::sleep(options.initial_backoff).await;
+                    }
+                    Err(e) => {
+                        message.last_error = Some(e.to_string());
+
+                        if e.is_retryable() && message.attempts < options.max_attempts {
+                            message.state = OutboxState::Queued;
+                            modem.update_outbox(&outbox, &message);
+                            tokio::time::sleep(options.backoff(message.attempts)).await;
+                        } else {
+                            message.state = OutboxState::Failed;
+                            modem.update_outbox(&outbox, &message);
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn update_outbox(&self, outbox: &Outbox, message: &OutboxMessage) {
+        if let Err(e) = outbox.update(message) {
+            self.background_error(BackgroundTask::Outbox, format!("Failed to save outbox message {}: {}", message.id, e));
+        }
+        self.emit(ModemEvent::OutboxUpdate(message.clone()));
+    }
+
+    /// Broadcasts an event that didn't come from a URC
+    fn emit(&self, event: ModemEvent) {
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.inner.events.send(event);
+    }
+
+    /// Reports an error from a background task, which has nobody else to return it to
+    fn background_error(&self, task: BackgroundTask, error: String) {
+        self.emit(ModemEvent::BackgroundError { task: task, error: error });
+    }
+
+    /// The storage used for reading, writing and receiving messages, and how full each one is
+    pub async fn get_preferred_storage(&self) -> Result<PreferredStorage> {
+        let _context = self.inner.sms_context.lock().await;
+        let resp = self.write_data(String::from("AT+CPMS?\r")).await?;
+
+        PreferredStorage::from_cpms_query(resp)
+    }
+
+    /// Sets the storage used for reading (and deleting), writing (and sending from) and receiving messages
+    ///
+    /// Memory indices from `+CMTI` and `get_sms_message` refer to whichever storage is selected at the time.
+    pub async fn set_preferred_storage(&self, read: SmsStorage, write: SmsStorage, receive: SmsStorage) -> Result<PreferredStorage> {
+        let _context = self.inner.sms_context.lock().await;
+        let command = format!("AT+CPMS=\"{}\",\"{}\",\"{}\"\r", read.as_str(), write.as_str(), receive.as_str());
+        let resp = self.write_data(command).await?;
+
+        PreferredStorage::from_cpms_set(resp, [read, write, receive])
+    }
+
+    /// Deletes the message at the given index of the read storage
+    pub async fn delete_sms_message(&self, mem_index: u32) -> Result<()> {
+        let _context = self.inner.sms_context.lock().await;
+        let command = format!("AT+CMGD={}\r", mem_index);
+        self.write_data(command).await?;
+
+        Ok(())
+    }
+
+    /// Deletes every message in the read storage matching the flag
+    pub async fn delete_sms_messages(&self, flag: DeleteFlag) -> Result<()> {
+        let _context = self.inner.sms_context.lock().await;
+        let command = format!("AT+CMGD=0,{}\r", u8::from(flag));
+        self.write_data(command).await?;
+
+        Ok(())
+    }
+
+    /// Starts a task that deletes messages per the policy whenever the modem reports `ModemEvent::SmsFull`
+    /// (and, with a threshold, whenever a new message arrives with storage past it)
+    ///
+    /// Messages are deleted from the receive storage, switching the read storage to it for the cleanup if they differ.
+    ///
+    /// The task runs until the returned handle is aborted. Failed cleanups are reported as `ModemEvent::BackgroundError`
+    /// and retried on the next trigger.
+    pub fn spawn_storage_cleanup(&self, policy: CleanupPolicy) -> JoinHandle<()> {
+        let modem = self.clone();
+        let mut events = self.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    // Missed events could have included SmsFull, so check rather than risk staying full
+                    Err(broadcast::error::RecvError::Lagged(_)) => ModemEvent::SmsFull,
+                    Err(broadcast::error::RecvError::Closed) => return
+                };
+
+                let threshold = match (event, policy.threshold) {
+                    (ModemEvent::SmsFull, _) => None,
+                    (ModemEvent::NewSms { .. }, Some(threshold)) => Some(threshold),
+                    _ => continue
+                };
+
+                if let Err(e) = modem.clean_receive_storage(policy.delete, threshold).await {
+                    modem.background_error(BackgroundTask::StorageCleanup, format!("Failed to clean up SMS storage: {}", e));
+                }
+            }
+        })
+    }
+
+    /// Deletes every message in the receive storage matching the flag, if it has at least `threshold` messages
+    ///
+    /// `AT+CMGD` deletes from the read storage, so that's switched to the receive storage while deleting.
+    async fn clean_receive_storage(&self, flag: DeleteFlag, threshold: Option<u32>) -> Result<()> {
+        let _context = self.inner.sms_context.lock().await;
+
+        let storage = PreferredStorage::from_cpms_query(self.write_data(String::from("AT+CPMS?\r")).await?)?;
+        if threshold.is_some_and(|threshold| storage.receive.used < threshold) {
+            return Ok(())
+        }
+
+        let command = format!("AT+CMGD=0,{}\r", u8::from(flag));
+        if storage.read.storage == storage.receive.storage {
+            return self.write_data(command).await.map(|_| ())
+        }
+
+        self.write_data(format!("AT+CPMS=\"{}\"\r", storage.receive.storage.as_str())).await?;
+        let deleted = self.write_data(command).await;
+        self.write_data(format!("AT+CPMS=\"{}\"\r", storage.read.storage.as_str())).await?;
+
+        deleted.map(|_| ())
+    }
+
+}
+
+/// Pulls the message reference out of the response to `AT+CMGS`
+fn parse_message_reference(resp: &str) -> Result<u8> {
+    let reference_captures = Regex::new(r"\+CMGS: (\d{1,3})").unwrap().captures(resp).ok_or_else(|| Error::parse("Failed to parse message reference!", resp))?;
+
+    reference_captures.get(1)
+        .and_then(|reference| reference.as_str().parse::<u8>().ok())
+        .ok_or_else(|| Error::parse("Failed to parse message reference!", resp))
 }
\ No newline at end of file
@@ -1,4 +1,22 @@
 pub mod gsm_modem;
+pub mod archive;
+pub mod broadcast;
+pub mod concat;
 pub mod constants;
+pub mod data_message;
+pub mod delivery;
+pub mod error;
+pub mod error_codes;
+pub mod events;
+pub mod framer;
+pub mod gsm7;
+pub mod outbox;
+pub mod pdu;
+pub mod phone_number;
+pub mod ussd;
 pub mod utils;
-mod dbus_utils;
\ No newline at end of file
+pub mod transport;
+pub mod simulator;
+pub mod storage;
+mod dbus_utils;
+mod actor;
\ No newline at end of file
@@ -4,6 +4,7 @@ use async_modem::gsm_modem::GsmModem;
 use tokio_serial::SerialPortBuilderExt;
 use tokio::sync::mpsc::{Receiver, Sender};
 use async_modem::constants::{SmsFormat, SmsStatus};
+use async_modem::transport::TransportConfig;
 
 use async_modem::utils::timestamp_to_iso_8601;
 
@@ -18,7 +19,7 @@ async fn dummy_send(modem: &GsmModem) {
     // modem.send_text_sms(&String::from("13153352552"), &String::from("they wouldn't be your friend\nif it wasn't worth it")).await.unwrap();
 
     // clear all messages:
-    // modem.write_data(String::from("AT+CMGD=0,4\r"), None).await.unwrap();
+    // modem.delete_sms_messages(DeleteFlag::All).await.unwrap();
     // modem.set_sms_format(SmsFormat::Text).await.unwrap();AT+CMEE=1\r
     //println!("{}", modem.write_data(String::from("AT+CMEE=1\r"), None).await.unwrap());
     //println!("{}", modem.write_data(String::from("AT+CMGR=56\r"), None).await.unwrap());
@@ -66,16 +67,13 @@ async fn main() {
     //     GsmModem::recieve_data(port).await;
     // });
 
-    let port_path = "/dev/ttyS0";
+    let transport = TransportConfig::serial("/dev/ttyS0", 115_200, Duration::from_millis(10));
 
-    let mut modem = GsmModem::new(port_path, 115_200, Duration::from_millis(10));
+    let modem = GsmModem::new(transport);
 
-    let res = tokio::join!(
-        modem.recieve_data_loop(),
-        dummy_send(&modem)
-    );
+    modem.open().await.expect("Failed to open port");
 
-    res.1;
+    dummy_send(&modem).await;
 
     // let mut port = tokio_serial::new("/dev/ttyS0", 115_200).timeout(Duration::from_millis(10)).open_native_async().expect("Failed to open port");
     // port.set_exclusive(false).unwrap();
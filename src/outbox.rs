@@ -0,0 +1,295 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Write}, path::Path, sync::{Arc, Mutex}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+use crate::{error::{Error, Result}, phone_number::PhoneNumber, utils::{escape_field, unescape_field}};
+
+/// First line of every outbox file, so a file that isn't an outbox is never overwritten
+const HEADER: &str = "# async-modem outbox v1";
+
+/// Where a message is in the outbox
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutboxState {
+    /// Waiting to be sent, including between retries
+    Queued,
+    /// Being sent to the modem
+    Sending,
+    /// Every part was accepted by the network
+    Sent,
+    /// Gave up on the message, `last_error` has the reason
+    Failed
+}
+
+impl OutboxState {
+    /// Whether the message is done with, either way
+    pub fn is_final(&self) -> bool {
+        matches!(self, OutboxState::Sent | OutboxState::Failed)
+    }
+}
+
+/// A message accepted by the outbox
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxMessage {
+    /// Unique within the outbox, increasing in the order messages were queued
+    pub id: u64,
+    pub destination: String,
+    pub content: String,
+    pub status_report: bool,
+    pub state: OutboxState,
+    /// How many times sending has been attempted since the outbox was opened
+    pub attempts: u32,
+    /// The message reference of every part, in order, once sent
+    pub message_references: Vec<u8>,
+    /// The error from the latest failed attempt
+    pub last_error: Option<String>,
+    pub queued_at: DateTime<Utc>
+}
+
+/// How `GsmModem::spawn_outbox` sends messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutboxOptions {
+    /// Attempts made before a message fails, including the first
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after every attempt after that
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts
+    pub max_backoff: Duration,
+    /// Shortest time between the start of two attempts, to stay under the network's rate limits
+    pub send_interval: Duration
+}
+
+impl OutboxOptions {
+    /// How long to wait after the given number of failed attempts
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(16);
+
+        self.initial_backoff.saturating_mul(1 << doublings).min(self.max_backoff)
+    }
+}
+
+impl Default for OutboxOptions {
+    fn default() -> Self {
+        OutboxOptions {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(300),
+            send_interval: Duration::from_secs(1)
+        }
+    }
+}
+
+/// Messages waiting to be sent by `GsmModem::spawn_outbox`, strictly in the order they were queued
+///
+/// A persistent outbox is backed by an append-only file, so queued messages survive a restart. Messages
+/// still queued (or being sent) when the process stopped are sent again, so a message can be sent twice
+/// if the process stopped mid-send. Sent and failed messages are dropped from the file the next time
+/// it's opened, use a `MessageArchive` to keep them.
+///
+/// Cloning is cheap and every clone shares the same outbox.
+#[derive(Clone)]
+pub struct Outbox {
+    inner: Arc<Mutex<OutboxInner>>,
+    queued: Arc<Notify>
+}
+
+struct OutboxInner {
+    /// `None` for an in-memory outbox
+    file: Option<File>,
+    messages: Vec<OutboxMessage>,
+    next_id: u64
+}
+
+impl Outbox {
+    /// An outbox kept only in memory
+    pub fn new() -> Outbox {
+        Outbox::from_inner(OutboxInner { file: None, messages: Vec::new(), next_id: 1 })
+    }
+
+    /// Opens the outbox at `path`, creating it if it doesn't exist
+    ///
+    /// A partly written last record (ie. from a crash mid-write) is dropped. The file is compacted by writing
+    /// what's still queued to a temporary file next to it and renaming that over it, so a crash part way
+    /// through leaves either the old file or the new one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Outbox> {
+        let path = path.as_ref();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into())
+        };
+        if !contents.is_empty() && !contents.starts_with(HEADER) {
+            return Err(Error::parse("File is not an outbox!", contents.lines().next().unwrap_or_default()))
+        }
+
+        // Only complete lines count, the rest is dropped with the compaction below
+        let complete = contents.rfind('\n').map(|end| end + 1).unwrap_or_default();
+        let mut inner = OutboxInner { file: None, messages: Vec::new(), next_id: 1 };
+        for line in contents[..complete].lines().filter(|line| !line.starts_with('#')) {
+            inner.replay(line)?;
+        }
+        inner.messages.retain(|message| !message.state.is_final());
+
+        // Compact the file down to what's still queued
+        let mut temp_name = path.file_name().ok_or_else(|| Error::invalid_argument("Outbox path has no file name!"))?.to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let mut temp = File::create(&temp_path)?;
+        writeln!(temp, "{}", HEADER)?;
+        // Keeps ids from being reused for messages that were dropped
+        writeln!(temp, "N\t{}", inner.next_id)?;
+        for message in inner.messages.iter() {
+            writeln!(temp, "{}", queued_record(message))?;
+        }
+        temp.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // Makes the rename itself durable, not every platform can open a directory so this is best effort
+        if let Some(directory) = path.parent().and_then(|parent| File::open(if parent.as_os_str().is_empty() { Path::new(".") } else { parent }).ok()) {
+            let _ = directory.sync_all();
+        }
+
+        inner.file = Some(OpenOptions::new().append(true).open(path)?);
+
+        Ok(Outbox::from_inner(inner))
+    }
+
+    fn from_inner(inner: OutboxInner) -> Outbox {
+        let queued = Arc::new(Notify::new());
+        if !inner.messages.is_empty() {
+            queued.notify_one();
+        }
+
+        Outbox { inner: Arc::new(Mutex::new(inner)), queued: queued }
+    }
+
+    /// Queues a message to be sent after everything queued before it, returning its id
+    ///
+    /// The destination is checked and normalized with `PhoneNumber::parse`, so an invalid number is rejected here.
+    pub fn enqueue(&self, destination: &str, content: &str, status_report: bool) -> Result<u64> {
+        let destination = PhoneNumber::parse(destination)?.to_string();
+        let mut inner = self.inner.lock().unwrap();
+
+        let message = OutboxMessage {
+            id: inner.next_id,
+            destination: destination,
+            content: String::from(content),
+            status_report: status_report,
+            state: OutboxState::Queued,
+            attempts: 0,
+            message_references: Vec::new(),
+            last_error: None,
+            queued_at: Utc::now()
+        };
+        if let Some(file) = inner.file.as_mut() {
+            writeln!(file, "{}", queued_record(&message))?;
+        }
+
+        let id = message.id;
+        inner.next_id += 1;
+        inner.messages.push(message);
+        self.queued.notify_one();
+
+        Ok(id)
+    }
+
+    /// The message with the given id, if the outbox still has it
+    pub fn get(&self, id: u64) -> Option<OutboxMessage> {
+        self.inner.lock().unwrap().messages.iter().find(|message| message.id == id).cloned()
+    }
+
+    /// Every message in the outbox, in the order they were queued
+    pub fn messages(&self) -> Vec<OutboxMessage> {
+        self.inner.lock().unwrap().messages.clone()
+    }
+
+    /// Messages that haven't been sent or failed yet, in the order they were queued
+    pub fn pending(&self) -> Vec<OutboxMessage> {
+        self.inner.lock().unwrap().messages.iter().filter(|message| !message.state.is_final()).cloned().collect()
+    }
+
+    /// The oldest message that hasn't been sent or failed yet
+    pub(crate) fn next(&self) -> Option<OutboxMessage> {
+        self.inner.lock().unwrap().messages.iter().find(|message| !message.state.is_final()).cloned()
+    }
+
+    /// Waits until a message is queued (or returns straight away if one was queued since the last wait)
+    pub(crate) async fn queued(&self) {
+        self.queued.notified().await
+    }
+
+    /// Replaces the message with the same id, recording it in the file once it's sent or failed
+    pub(crate) fn update(&self, message: &OutboxMessage) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(existing) = inner.messages.iter_mut().find(|existing| existing.id == message.id) {
+            *existing = message.clone();
+        }
+
+        let record = match message.state {
+            OutboxState::Sent => {
+                let references: Vec<String> = message.message_references.iter().map(|reference| reference.to_string()).collect();
+                format!("S\t{}\t{}", message.id, references.join(","))
+            }
+            OutboxState::Failed => format!("F\t{}\t{}", message.id, escape_field(message.last_error.as_deref().unwrap_or_default())),
+            OutboxState::Queued | OutboxState::Sending => return Ok(())
+        };
+        if let Some(file) = inner.file.as_mut() {
+            writeln!(file, "{}", record)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Outbox::new()
+    }
+}
+
+impl OutboxInner {
+    /// Applies a single record read back from the file
+    fn replay(&mut self, line: &str) -> Result<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let invalid = || Error::parse("Invalid outbox record!", line);
+
+        match fields.as_slice() {
+            ["Q", id, status_report, queued_at, destination, content] => {
+                let message = OutboxMessage {
+                    id: id.parse().map_err(|_| invalid())?,
+                    destination: unescape_field(destination),
+                    content: unescape_field(content),
+                    status_report: *status_report == "1",
+                    state: OutboxState::Queued,
+                    attempts: 0,
+                    message_references: Vec::new(),
+                    last_error: None,
+                    queued_at: DateTime::parse_from_rfc3339(queued_at).map_err(|_| invalid())?.to_utc()
+                };
+                self.next_id = self.next_id.max(message.id + 1);
+                self.messages.push(message);
+            }
+            ["N", next_id] => {
+                self.next_id = self.next_id.max(next_id.parse().map_err(|_| invalid())?);
+            }
+            ["S", id, _] | ["F", id, _] => {
+                let id: u64 = id.parse().map_err(|_| invalid())?;
+                let state = if fields[0] == "S" { OutboxState::Sent } else { OutboxState::Failed };
+                if let Some(message) = self.messages.iter_mut().find(|message| message.id == id) {
+                    message.state = state;
+                }
+            }
+            _ => return Err(invalid())
+        }
+
+        Ok(())
+    }
+}
+
+fn queued_record(message: &OutboxMessage) -> String {
+    format!(
+        "Q\t{}\t{}\t{}\t{}\t{}",
+        message.id, message.status_report as u8, message.queued_at.to_rfc3339(), escape_field(&message.destination), escape_field(&message.content)
+    )
+}
@@ -0,0 +1,1259 @@
+use std::{fmt, time::Duration};
+
+use chrono::{DateTime, Datelike, FixedOffset, TimeZone, Timelike};
+use regex::Regex;
+
+use crate::{constants::SmsStatus, error::{Error, Result}, gsm7::{self, Encoding, NationalLanguage, ShiftTables}, utils::{bytes_to_hex, hex_to_bytes}};
+
+/// Type of number, bits 6-4 of the type-of-address octet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeOfNumber {
+    Unknown,
+    International,
+    National,
+    NetworkSpecific,
+    Subscriber,
+    /// The address is text (ie. a sender name) packed with the GSM 7-bit alphabet
+    Alphanumeric,
+    Abbreviated,
+    Reserved
+}
+
+impl TypeOfNumber {
+    fn from_bits(bits: u8) -> TypeOfNumber {
+        match bits & 0x07 {
+            0 => TypeOfNumber::Unknown,
+            1 => TypeOfNumber::International,
+            2 => TypeOfNumber::National,
+            3 => TypeOfNumber::NetworkSpecific,
+            4 => TypeOfNumber::Subscriber,
+            5 => TypeOfNumber::Alphanumeric,
+            6 => TypeOfNumber::Abbreviated,
+            _ => TypeOfNumber::Reserved
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            TypeOfNumber::Unknown => 0,
+            TypeOfNumber::International => 1,
+            TypeOfNumber::National => 2,
+            TypeOfNumber::NetworkSpecific => 3,
+            TypeOfNumber::Subscriber => 4,
+            TypeOfNumber::Alphanumeric => 5,
+            TypeOfNumber::Abbreviated => 6,
+            TypeOfNumber::Reserved => 7
+        }
+    }
+}
+
+/// Numbering plan identification, bits 3-0 of the type-of-address octet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberingPlan {
+    Unknown,
+    /// E.164, used for regular phone numbers
+    Isdn,
+    Data,
+    Telex,
+    National,
+    Private,
+    Ermes,
+    Other(u8)
+}
+
+impl NumberingPlan {
+    fn from_bits(bits: u8) -> NumberingPlan {
+        match bits & 0x0f {
+            0 => NumberingPlan::Unknown,
+            1 => NumberingPlan::Isdn,
+            3 => NumberingPlan::Data,
+            4 => NumberingPlan::Telex,
+            8 => NumberingPlan::National,
+            9 => NumberingPlan::Private,
+            10 => NumberingPlan::Ermes,
+            other => NumberingPlan::Other(other)
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            NumberingPlan::Unknown => 0,
+            NumberingPlan::Isdn => 1,
+            NumberingPlan::Data => 3,
+            NumberingPlan::Telex => 4,
+            NumberingPlan::National => 8,
+            NumberingPlan::Private => 9,
+            NumberingPlan::Ermes => 10,
+            NumberingPlan::Other(bits) => bits & 0x0f
+        }
+    }
+}
+
+/// An originator, destination or service centre address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Address {
+    /// The digits (or text, for alphanumeric addresses) without any leading `+`
+    pub number: String,
+    pub type_of_number: TypeOfNumber,
+    pub numbering_plan: NumberingPlan
+}
+
+impl Address {
+    /// Builds an address from a number as a user would write it
+    ///
+    /// A leading `+` makes it international, anything that isn't a dialable digit makes it alphanumeric.
+    pub fn new(number: &str) -> Address {
+        if let Some(digits) = number.strip_prefix('+') {
+            Address { number: String::from(digits), type_of_number: TypeOfNumber::International, numbering_plan: NumberingPlan::Isdn }
+        } else if number.chars().all(|c| digit_to_nibble(c).is_some()) {
+            Address { number: String::from(number), type_of_number: TypeOfNumber::Unknown, numbering_plan: NumberingPlan::Isdn }
+        } else {
+            Address { number: String::from(number), type_of_number: TypeOfNumber::Alphanumeric, numbering_plan: NumberingPlan::Unknown }
+        }
+    }
+
+    /// The type-of-address octet (ie. `145` for international numbers)
+    pub fn type_of_address(&self) -> u8 {
+        0x80 | (self.type_of_number.bits() << 4) | self.numbering_plan.bits()
+    }
+
+    fn from_type_of_address(number: String, toa: u8) -> Address {
+        Address { number: number, type_of_number: TypeOfNumber::from_bits(toa >> 4), numbering_plan: NumberingPlan::from_bits(toa) }
+    }
+
+    /// Encodes as a TP address (originator/destination), where the length is counted in digits
+    fn encode(&self) -> Result<Vec<u8>> {
+        if self.type_of_number == TypeOfNumber::Alphanumeric {
+            let septets = gsm7::encode(&self.number).ok_or_else(|| Error::invalid_argument("Alphanumeric address must use the GSM 7-bit alphabet!"))?;
+            let packed = gsm7::pack_septets(&septets, 0);
+            if packed.len() > 10 {
+                return Err(Error::invalid_argument("Alphanumeric address is too long!"))
+            }
+
+            let mut encoded = vec![(septets.len() * 7).div_ceil(4) as u8, self.type_of_address()];
+            encoded.extend(packed);
+            return Ok(encoded)
+        }
+
+        if self.number.len() > 20 {
+            return Err(Error::invalid_argument("Address is too long!"))
+        }
+
+        let mut encoded = vec![self.number.len() as u8, self.type_of_address()];
+        encoded.extend(encode_semi_octets(&self.number)?);
+        Ok(encoded)
+    }
+
+    /// Encodes as a service centre address, where the length is counted in octets
+    fn encode_smsc(smsc: Option<&Address>) -> Result<Vec<u8>> {
+        let Some(smsc) = smsc else { return Ok(vec![0]) };
+
+        let digits = encode_semi_octets(&smsc.number)?;
+        let mut encoded = vec![digits.len() as u8 + 1, smsc.type_of_address()];
+        encoded.extend(digits);
+        Ok(encoded)
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Address> {
+        let length = reader.byte()? as usize;
+        let toa = reader.byte()?;
+        let octets = reader.take(length.div_ceil(2))?;
+
+        let number = if TypeOfNumber::from_bits(toa >> 4) == TypeOfNumber::Alphanumeric {
+            gsm7::decode(&gsm7::unpack_septets(octets, length * 4 / 7, 0))
+        } else {
+            decode_semi_octets(octets).chars().take(length).collect()
+        };
+
+        Ok(Address::from_type_of_address(number, toa))
+    }
+
+    fn decode_smsc(reader: &mut Reader) -> Result<Option<Address>> {
+        let length = reader.byte()? as usize;
+        if length == 0 {
+            return Ok(None)
+        }
+
+        let toa = reader.byte()?;
+        let number = decode_semi_octets(reader.take(length - 1)?);
+
+        Ok(Some(Address::from_type_of_address(number, toa)))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.type_of_number {
+            TypeOfNumber::International => write!(f, "+{}", self.number),
+            _ => write!(f, "{}", self.number)
+        }
+    }
+}
+
+fn digit_to_nibble(digit: char) -> Option<u8> {
+    match digit {
+        '0'..='9' => Some(digit as u8 - b'0'),
+        '*' => Some(0x0a),
+        '#' => Some(0x0b),
+        'a' => Some(0x0c),
+        'b' => Some(0x0d),
+        'c' => Some(0x0e),
+        _ => None
+    }
+}
+
+/// Packs digits two to an octet, low nibble first, padding an odd digit out with `F`
+fn encode_semi_octets(digits: &str) -> Result<Vec<u8>> {
+    let nibbles = digits.chars()
+        .map(|c| digit_to_nibble(c).ok_or_else(|| Error::invalid_argument(&format!("\"{}\" is not a valid digit in an address!", c))))
+        .collect::<Result<Vec<u8>>>()?;
+
+    Ok(nibbles.chunks(2).map(|pair| pair[0] | (pair.get(1).copied().unwrap_or(0x0f) << 4)).collect())
+}
+
+/// Unpacks semi-octet digits, stopping at the `F` filler
+fn decode_semi_octets(octets: &[u8]) -> String {
+    octets.iter()
+        .flat_map(|octet| [octet & 0x0f, octet >> 4])
+        .take_while(|nibble| *nibble != 0x0f)
+        .map(|nibble| match nibble {
+            0..=9 => (b'0' + nibble) as char,
+            0x0a => '*',
+            0x0b => '#',
+            0x0c => 'a',
+            0x0d => 'b',
+            _ => 'c'
+        })
+        .collect()
+}
+
+/// A decimal value stored as a swapped semi-octet (ie. `31` is stored as `0x13`)
+fn decode_swapped(octet: u8) -> u32 {
+    ((octet & 0x0f) * 10 + (octet >> 4)) as u32
+}
+
+fn encode_swapped(value: u32) -> u8 {
+    (((value % 10) << 4) | ((value / 10) % 10)) as u8
+}
+
+/// Decodes a 7 octet service centre timestamp (or absolute validity period)
+fn decode_timestamp(reader: &mut Reader) -> Result<DateTime<FixedOffset>> {
+    let octets = reader.take(7)?;
+
+    // The timezone is in quarter hours, with the sign in bit 3 of what would otherwise be the tens digit
+    let quarter_hours = ((octets[6] & 0x07) * 10 + (octets[6] >> 4)) as i32;
+    let quarter_hours = if octets[6] & 0x08 != 0 { -quarter_hours } else { quarter_hours };
+
+    let offset = FixedOffset::east_opt(quarter_hours * 15 * 60).ok_or_else(|| Error::parse("Invalid timezone in PDU timestamp!", reader.raw))?;
+
+    offset.with_ymd_and_hms(
+        2000 + decode_swapped(octets[0]) as i32,
+        decode_swapped(octets[1]),
+        decode_swapped(octets[2]),
+        decode_swapped(octets[3]),
+        decode_swapped(octets[4]),
+        decode_swapped(octets[5])
+    ).single().ok_or_else(|| Error::parse("Invalid PDU timestamp!", reader.raw))
+}
+
+fn encode_timestamp(timestamp: &DateTime<FixedOffset>) -> Vec<u8> {
+    let quarter_hours = timestamp.offset().local_minus_utc() / (15 * 60);
+    let mut timezone = encode_swapped(quarter_hours.unsigned_abs());
+    if quarter_hours < 0 {
+        timezone |= 0x08;
+    }
+
+    vec![
+        encode_swapped(timestamp.year().rem_euclid(100) as u32),
+        encode_swapped(timestamp.month()),
+        encode_swapped(timestamp.day()),
+        encode_swapped(timestamp.hour()),
+        encode_swapped(timestamp.minute()),
+        encode_swapped(timestamp.second()),
+        timezone
+    ]
+}
+
+/// How long the service centre should keep trying to deliver a message
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidityPeriod {
+    /// Relative to when the service centre received the message, rounded up to what the single octet can hold
+    Relative(Duration),
+    /// Give up at this time
+    Absolute(DateTime<FixedOffset>),
+    /// The raw enhanced format octets, rarely supported
+    Enhanced([u8; 7])
+}
+
+impl ValidityPeriod {
+    /// The period for a relative TP-VP octet (ie. `167` is 24 hours), as `AT+CSMP` takes it in text mode
+    pub fn from_relative_value(value: u8) -> ValidityPeriod {
+        let value = value as u64;
+        let minutes = match value {
+            0..=143 => (value + 1) * 5,
+            144..=167 => 12 * 60 + (value - 143) * 30,
+            168..=196 => (value - 166) * 24 * 60,
+            _ => (value - 192) * 7 * 24 * 60
+        };
+
+        ValidityPeriod::Relative(Duration::from_secs(minutes * 60))
+    }
+
+    /// The relative TP-VP octet, `None` unless the period is relative
+    pub fn relative_value(&self) -> Option<u8> {
+        match self {
+            ValidityPeriod::Relative(duration) => Some(relative_value(duration)),
+            _ => None
+        }
+    }
+
+    /// The TP-VPF bits for the first octet
+    pub(crate) fn format_bits(&self) -> u8 {
+        match self {
+            ValidityPeriod::Relative(_) => 0b10,
+            ValidityPeriod::Enhanced(_) => 0b01,
+            ValidityPeriod::Absolute(_) => 0b11
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            ValidityPeriod::Relative(duration) => vec![relative_value(duration)],
+            ValidityPeriod::Absolute(timestamp) => encode_timestamp(timestamp),
+            ValidityPeriod::Enhanced(octets) => octets.to_vec()
+        }
+    }
+
+    fn decode(format: u8, reader: &mut Reader) -> Result<Option<ValidityPeriod>> {
+        match format {
+            0b10 => Ok(Some(ValidityPeriod::from_relative_value(reader.byte()?))),
+            0b01 => {
+                let mut octets = [0; 7];
+                octets.copy_from_slice(reader.take(7)?);
+                Ok(Some(ValidityPeriod::Enhanced(octets)))
+            }
+            0b11 => Ok(Some(ValidityPeriod::Absolute(decode_timestamp(reader)?))),
+            _ => Ok(None)
+        }
+    }
+}
+
+fn relative_value(duration: &Duration) -> u8 {
+    let minutes = duration.as_secs().div_ceil(60);
+    let value = if minutes <= 12 * 60 {
+        minutes.div_ceil(5).max(1) - 1
+    } else if minutes <= 24 * 60 {
+        143 + (minutes - 12 * 60).div_ceil(30)
+    } else if minutes <= 30 * 24 * 60 {
+        166 + minutes.div_ceil(24 * 60)
+    } else {
+        (192 + minutes.div_ceil(7 * 24 * 60)).min(255)
+    };
+
+    value as u8
+}
+
+/// How the user data is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alphabet {
+    /// GSM 7-bit default alphabet, 160 characters per message
+    Gsm7,
+    /// Binary data, 140 octets per message
+    EightBit,
+    /// UTF-16, 70 characters per message
+    Ucs2
+}
+
+/// The TP-DCS octet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataCodingScheme(pub u8);
+
+impl DataCodingScheme {
+    /// Builds a general data coding DCS, optionally with a message class (0-3)
+    pub fn new(alphabet: Alphabet, class: Option<u8>) -> DataCodingScheme {
+        let alphabet_bits = match alphabet {
+            Alphabet::Gsm7 => 0x00,
+            Alphabet::EightBit => 0x04,
+            Alphabet::Ucs2 => 0x08
+        };
+
+        match class {
+            Some(class) => DataCodingScheme(0x10 | alphabet_bits | (class & 0x03)),
+            None => DataCodingScheme(alphabet_bits)
+        }
+    }
+
+    pub fn alphabet(&self) -> Alphabet {
+        match self.0 >> 4 {
+            // General data coding, with or without automatic deletion
+            0x0..=0x7 => match (self.0 >> 2) & 0x03 {
+                0b01 => Alphabet::EightBit,
+                0b10 => Alphabet::Ucs2,
+                _ => Alphabet::Gsm7
+            },
+            // Message waiting indication, store as UCS2
+            0xE => Alphabet::Ucs2,
+            // Data coding/message class
+            0xF if self.0 & 0x04 != 0 => Alphabet::EightBit,
+            _ => Alphabet::Gsm7
+        }
+    }
+
+    /// The message class (0-3) if one is set
+    pub fn class(&self) -> Option<u8> {
+        match self.0 >> 4 {
+            0x0..=0x7 if self.0 & 0x10 != 0 => Some(self.0 & 0x03),
+            0xF => Some(self.0 & 0x03),
+            _ => None
+        }
+    }
+
+    /// Whether the user data is compressed, which isn't supported
+    pub fn is_compressed(&self) -> bool {
+        self.0 >> 4 <= 0x7 && self.0 & 0x20 != 0
+    }
+
+    /// The message waiting indication of the message waiting groups (`0xC0` to `0xEF`), which never have a count
+    pub fn message_waiting(&self) -> Option<MessageWaiting> {
+        let store = match self.0 >> 4 {
+            0xC => false,
+            0xD | 0xE => true,
+            _ => return None
+        };
+
+        Some(MessageWaiting { kind: WaitingMessageKind::from_bits(self.0), active: self.0 & 0x08 != 0, count: None, store: store })
+    }
+}
+
+/// What kind of message is waiting, from a message waiting indication
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WaitingMessageKind {
+    Voicemail,
+    Fax,
+    Email,
+    Other
+}
+
+impl WaitingMessageKind {
+    /// From the indication type in the two lowest bits
+    fn from_bits(bits: u8) -> WaitingMessageKind {
+        match bits & 0x03 {
+            0 => WaitingMessageKind::Voicemail,
+            1 => WaitingMessageKind::Fax,
+            2 => WaitingMessageKind::Email,
+            _ => WaitingMessageKind::Other
+        }
+    }
+}
+
+/// A message waiting indication (ie. new voicemail), from the data coding scheme or the user data header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageWaiting {
+    pub kind: WaitingMessageKind,
+    /// Whether messages are waiting, `false` once they've all been read (or listened to)
+    pub active: bool,
+    /// How many messages are waiting, only given by the special SMS message indication header element
+    pub count: Option<u8>,
+    /// Whether the message carrying the indication should be kept, rather than discarded once the indication is read
+    pub store: bool
+}
+
+/// Information element for a concatenated message part with an 8-bit reference
+pub const IE_CONCATENATED_8BIT: u8 = 0x00;
+
+/// Information element for a concatenated message part with a 16-bit reference
+pub const IE_CONCATENATED_16BIT: u8 = 0x08;
+
+/// Information element indicating waiting messages (ie. voicemail), with how many are waiting
+pub const IE_SPECIAL_SMS_INDICATION: u8 = 0x01;
+
+/// Information element addressing an application port with 8-bit port numbers
+pub const IE_APPLICATION_PORT_8BIT: u8 = 0x04;
+
+/// Information element addressing an application port with 16-bit port numbers
+pub const IE_APPLICATION_PORT_16BIT: u8 = 0x05;
+
+/// Information element selecting a national single shift table
+pub const IE_NATIONAL_SINGLE_SHIFT: u8 = 0x24;
+
+/// Information element selecting a national locking shift table
+pub const IE_NATIONAL_LOCKING_SHIFT: u8 = 0x25;
+
+/// A single information element from a user data header
+#[derive(Clone, Debug, PartialEq)]
+pub struct InformationElement {
+    pub id: u8,
+    pub data: Vec<u8>
+}
+
+/// Reference shared by every part of a concatenated message
+///
+/// 16-bit references make it less likely that two long messages from the same sender get mixed up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConcatReference {
+    EightBit(u8),
+    SixteenBit(u16)
+}
+
+/// Where a part belongs in a concatenated message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Concatenation {
+    pub reference: ConcatReference,
+    /// How many parts the full message has
+    pub total: u8,
+    /// This part's position, starting from 1
+    pub sequence: u8
+}
+
+impl Concatenation {
+    fn to_element(self) -> InformationElement {
+        match self.reference {
+            ConcatReference::EightBit(reference) => InformationElement { id: IE_CONCATENATED_8BIT, data: vec![reference, self.total, self.sequence] },
+            ConcatReference::SixteenBit(reference) => {
+                let [high, low] = reference.to_be_bytes();
+                InformationElement { id: IE_CONCATENATED_16BIT, data: vec![high, low, self.total, self.sequence] }
+            }
+        }
+    }
+}
+
+/// Which application a message is for, like a UDP port (ie. `2948` for WAP push)
+///
+/// Port-addressed messages are meant for software on the device rather than the person using it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ApplicationPort {
+    pub destination: u16,
+    pub source: u16
+}
+
+impl ApplicationPort {
+    fn to_element(self) -> InformationElement {
+        let mut data = self.destination.to_be_bytes().to_vec();
+        data.extend(self.source.to_be_bytes());
+
+        InformationElement { id: IE_APPLICATION_PORT_16BIT, data: data }
+    }
+}
+
+/// The user data header, present when TP-UDHI is set
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserDataHeader {
+    pub elements: Vec<InformationElement>
+}
+
+impl UserDataHeader {
+    /// Builds the header announcing national shift tables, `None` for the default alphabet
+    pub fn from_shift_tables(tables: ShiftTables) -> Option<UserDataHeader> {
+        let elements: Vec<InformationElement> = [(IE_NATIONAL_LOCKING_SHIFT, tables.locking), (IE_NATIONAL_SINGLE_SHIFT, tables.single)]
+            .into_iter()
+            .filter_map(|(id, language)| Some(InformationElement { id: id, data: vec![language?.id()] }))
+            .collect();
+
+        match elements.is_empty() {
+            true => None,
+            false => Some(UserDataHeader { elements: elements })
+        }
+    }
+
+    /// The shift tables GSM 7-bit text after this header is encoded with
+    pub fn shift_tables(&self) -> ShiftTables {
+        let language = |id: u8| self.elements.iter()
+            .find(|element| element.id == id)
+            .and_then(|element| NationalLanguage::from_id(*element.data.first()?));
+
+        ShiftTables { locking: language(IE_NATIONAL_LOCKING_SHIFT), single: language(IE_NATIONAL_SINGLE_SHIFT) }
+    }
+
+    /// The concatenation element, if this is part of a longer message
+    ///
+    /// Parts with a sequence outside `1..=total` are treated as standalone messages.
+    pub fn concatenation(&self) -> Option<Concatenation> {
+        self.elements.iter().find_map(|element| {
+            let (reference, total, sequence) = match (element.id, element.data.as_slice()) {
+                (IE_CONCATENATED_8BIT, [reference, total, sequence]) => (ConcatReference::EightBit(*reference), *total, *sequence),
+                (IE_CONCATENATED_16BIT, [high, low, total, sequence]) => (ConcatReference::SixteenBit(u16::from_be_bytes([*high, *low])), *total, *sequence),
+                _ => return None
+            };
+
+            match sequence >= 1 && sequence <= total {
+                true => Some(Concatenation { reference: reference, total: total, sequence: sequence }),
+                false => None
+            }
+        })
+    }
+
+    /// Every special SMS message indication in the header, a count of 0 clears the indication
+    pub fn message_waiting(&self) -> Vec<MessageWaiting> {
+        self.elements.iter().filter_map(|element| match (element.id, element.data.as_slice()) {
+            (IE_SPECIAL_SMS_INDICATION, [indicator, count]) => Some(MessageWaiting {
+                kind: WaitingMessageKind::from_bits(*indicator),
+                active: *count > 0,
+                count: Some(*count),
+                store: indicator & 0x80 != 0
+            }),
+            _ => None
+        }).collect()
+    }
+
+    /// The application port the message is addressed to, if any
+    pub fn application_port(&self) -> Option<ApplicationPort> {
+        self.elements.iter().find_map(|element| match (element.id, element.data.as_slice()) {
+            (IE_APPLICATION_PORT_8BIT, [destination, source]) => Some(ApplicationPort { destination: *destination as u16, source: *source as u16 }),
+            (IE_APPLICATION_PORT_16BIT, [destination_high, destination_low, source_high, source_low]) => Some(ApplicationPort {
+                destination: u16::from_be_bytes([*destination_high, *destination_low]),
+                source: u16::from_be_bytes([*source_high, *source_low])
+            }),
+            _ => None
+        })
+    }
+
+    /// Octets the encoded header takes up, including its length octet
+    fn encoded_length(&self) -> usize {
+        1 + self.elements.iter().map(|element| 2 + element.data.len()).sum::<usize>()
+    }
+
+    /// Encodes the header including its leading length octet
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![0];
+        for element in &self.elements {
+            encoded.push(element.id);
+            encoded.push(element.data.len() as u8);
+            encoded.extend_from_slice(&element.data);
+        }
+        encoded[0] = (encoded.len() - 1) as u8;
+
+        encoded
+    }
+
+    /// Decodes the header from the start of the user data, returning it and its length including the length octet
+    fn decode(user_data: &[u8], raw: &str) -> Result<(UserDataHeader, usize)> {
+        let length = *user_data.first().ok_or_else(|| Error::parse("User data header is missing!", raw))? as usize;
+        let mut bytes = user_data.get(1..=length).ok_or_else(|| Error::parse("User data header is truncated!", raw))?;
+        let mut elements = Vec::new();
+
+        while let [id, length, rest @ ..] = bytes {
+            let data = rest.get(..*length as usize).ok_or_else(|| Error::parse("Information element is truncated!", raw))?;
+            elements.push(InformationElement { id: *id, data: data.to_vec() });
+            bytes = &rest[*length as usize..];
+        }
+
+        Ok((UserDataHeader { elements: elements }, length + 1))
+    }
+}
+
+/// The message body, text for GSM 7-bit and UCS2 or raw octets for 8-bit data
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserDataBody {
+    Text(String),
+    Binary(Vec<u8>)
+}
+
+/// TP-UD, the optional header and body of a message
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserData {
+    pub header: Option<UserDataHeader>,
+    pub body: UserDataBody
+}
+
+impl UserData {
+    /// Builds text user data with the cheapest encoding, returning the matching data coding scheme
+    ///
+    /// National shift tables are announced in the header when they save space over the default alphabet or UCS2.
+    pub fn from_text(content: &str) -> (DataCodingScheme, UserData) {
+        let body = UserDataBody::Text(String::from(content));
+
+        match gsm7::cheapest_encoding(content) {
+            Encoding::Gsm7(tables) => (DataCodingScheme::new(Alphabet::Gsm7, None), UserData { header: UserDataHeader::from_shift_tables(tables), body: body }),
+            Encoding::Ucs2 => (DataCodingScheme::new(Alphabet::Ucs2, None), UserData { header: None, body: body })
+        }
+    }
+
+    /// Returns the text of the body, or `None` for binary data
+    pub fn text(&self) -> Option<&str> {
+        match &self.body {
+            UserDataBody::Text(text) => Some(text),
+            UserDataBody::Binary(_) => None
+        }
+    }
+
+    /// Encodes the user data, returning TP-UDL (in septets for GSM 7-bit, octets otherwise) and the encoded octets
+    fn encode(&self, dcs: DataCodingScheme) -> Result<(u8, Vec<u8>)> {
+        let header = self.header.as_ref().map(|h| h.encode()).unwrap_or_default();
+
+        let (length, encoded) = match (dcs.alphabet(), &self.body) {
+            (Alphabet::Gsm7, UserDataBody::Text(text)) => {
+                let tables = self.header.as_ref().map(|h| h.shift_tables()).unwrap_or_default();
+                let septets = tables.encode(text).ok_or_else(|| Error::invalid_argument("Text contains characters outside the GSM 7-bit alphabet!"))?;
+                // The text starts on the first septet boundary after the header
+                let header_septets = (header.len() * 8).div_ceil(7);
+                let mut encoded = gsm7::pack_septets(&septets, header_septets * 7);
+                encoded[..header.len()].copy_from_slice(&header);
+
+                if header_septets + septets.len() > 160 {
+                    return Err(Error::invalid_argument("Message is too long for a single SMS!"))
+                }
+                (header_septets + septets.len(), encoded)
+            }
+            (Alphabet::Ucs2, UserDataBody::Text(text)) => {
+                let mut encoded = header;
+                encoded.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+                (encoded.len(), encoded)
+            }
+            (Alphabet::EightBit, UserDataBody::Binary(data)) => {
+                let mut encoded = header;
+                encoded.extend_from_slice(data);
+                (encoded.len(), encoded)
+            }
+            _ => return Err(Error::invalid_argument("Message body doesn't match the data coding scheme!"))
+        };
+
+        if encoded.len() > 140 {
+            return Err(Error::invalid_argument("Message is too long for a single SMS!"))
+        }
+
+        Ok((length as u8, encoded))
+    }
+
+    fn decode(dcs: DataCodingScheme, has_header: bool, reader: &mut Reader) -> Result<UserData> {
+        if dcs.is_compressed() {
+            return Err(Error::parse("Compressed user data isn't supported!", reader.raw))
+        }
+
+        let length = reader.byte()? as usize;
+        let octets = match dcs.alphabet() {
+            Alphabet::Gsm7 => reader.take((length * 7).div_ceil(8))?,
+            _ => reader.take(length)?
+        };
+
+        let (header, header_length) = match has_header {
+            true => {
+                let (header, header_length) = UserDataHeader::decode(octets, reader.raw)?;
+                (Some(header), header_length)
+            }
+            false => (None, 0)
+        };
+
+        let body = match dcs.alphabet() {
+            Alphabet::Gsm7 => {
+                let header_septets = (header_length * 8).div_ceil(7);
+                let septets = gsm7::unpack_septets(octets, length.saturating_sub(header_septets), header_septets * 7);
+                let tables = header.as_ref().map(|h| h.shift_tables()).unwrap_or_default();
+                UserDataBody::Text(tables.decode(&septets))
+            }
+            Alphabet::Ucs2 => {
+                let units: Vec<u16> = octets[header_length..].chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+                UserDataBody::Text(String::from_utf16(&units).map_err(|_| Error::parse("Invalid UCS2 in user data!", reader.raw))?)
+            }
+            Alphabet::EightBit => UserDataBody::Binary(octets[header_length..].to_vec())
+        };
+
+        Ok(UserData { header: header, body: body })
+    }
+}
+
+/// A message sent from the phone to the service centre
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmsSubmit {
+    /// Ask the service centre to drop this message if it has one with the same reference and destination
+    pub reject_duplicates: bool,
+    pub reply_path: bool,
+    pub status_report_request: bool,
+    /// Normally left as 0, the modem assigns its own reference when sending
+    pub message_reference: u8,
+    pub destination: Address,
+    pub protocol_identifier: u8,
+    pub data_coding_scheme: DataCodingScheme,
+    pub validity_period: Option<ValidityPeriod>,
+    pub user_data: UserData
+}
+
+impl SmsSubmit {
+    /// Builds a text message with whichever of GSM 7-bit (with national shift tables) or UCS2 is cheapest
+    pub fn new(destination: Address, content: &str) -> SmsSubmit {
+        let (data_coding_scheme, user_data) = UserData::from_text(content);
+
+        SmsSubmit {
+            reject_duplicates: false,
+            reply_path: false,
+            status_report_request: false,
+            message_reference: 0,
+            destination: destination,
+            protocol_identifier: 0,
+            data_coding_scheme: data_coding_scheme,
+            validity_period: None,
+            user_data: user_data
+        }
+    }
+
+    /// Builds as many messages as the text needs, each with a concatenation header when there's more than one
+    ///
+    /// Every part uses the encoding that's cheapest for the whole text, and characters (including escaped
+    /// GSM 7-bit characters and UTF-16 surrogate pairs) are never split between parts.
+    pub fn segments(destination: Address, content: &str, reference: ConcatReference) -> Result<Vec<SmsSubmit>> {
+        let single = SmsSubmit::new(destination, content);
+        let alphabet = single.data_coding_scheme.alphabet();
+        let shift_header = single.user_data.header.clone().unwrap_or_default();
+        let tables = shift_header.shift_tables();
+
+        let single_header_length = match shift_header.elements.is_empty() {
+            true => 0,
+            false => shift_header.encoded_length()
+        };
+        if split_text(content, alphabet, tables, single_header_length).len() <= 1 {
+            return Ok(vec![single])
+        }
+
+        let concat_length = Concatenation { reference: reference, total: 0, sequence: 0 }.to_element().data.len() + 2;
+        let parts = split_text(content, alphabet, tables, shift_header.encoded_length() + concat_length);
+        if parts.len() > 255 {
+            return Err(Error::invalid_argument("Message is too long, it would need more than 255 parts!"))
+        }
+
+        let total = parts.len() as u8;
+        Ok(parts.into_iter().enumerate().map(|(i, part)| {
+            let mut elements = vec![Concatenation { reference: reference, total: total, sequence: i as u8 + 1 }.to_element()];
+            elements.extend(shift_header.elements.iter().cloned());
+
+            SmsSubmit {
+                user_data: UserData { header: Some(UserDataHeader { elements: elements }), body: UserDataBody::Text(part) },
+                ..single.clone()
+            }
+        }).collect())
+    }
+
+    /// Builds 8-bit data messages addressed to an application port, in as many parts as the data needs
+    ///
+    /// Every part carries the port, and a concatenation header when there's more than one.
+    pub fn data_segments(destination: Address, port: ApplicationPort, data: &[u8], reference: ConcatReference) -> Result<Vec<SmsSubmit>> {
+        let port_element = port.to_element();
+        let single_header = UserDataHeader { elements: vec![port_element.clone()] };
+
+        let chunks: Vec<&[u8]> = if data.len() <= part_capacity(Alphabet::EightBit, single_header.encoded_length()) {
+            vec![data]
+        } else {
+            let concat_length = Concatenation { reference: reference, total: 0, sequence: 0 }.to_element().data.len() + 2;
+            data.chunks(part_capacity(Alphabet::EightBit, single_header.encoded_length() + concat_length)).collect()
+        };
+        if chunks.len() > 255 {
+            return Err(Error::invalid_argument("Data is too long, it would need more than 255 parts!"))
+        }
+
+        let total = chunks.len() as u8;
+        Ok(chunks.into_iter().enumerate().map(|(i, chunk)| {
+            let mut elements = Vec::new();
+            if total > 1 {
+                elements.push(Concatenation { reference: reference, total: total, sequence: i as u8 + 1 }.to_element());
+            }
+            elements.push(port_element.clone());
+
+            SmsSubmit {
+                reject_duplicates: false,
+                reply_path: false,
+                status_report_request: false,
+                message_reference: 0,
+                destination: destination.clone(),
+                protocol_identifier: 0,
+                data_coding_scheme: DataCodingScheme::new(Alphabet::EightBit, None),
+                validity_period: None,
+                user_data: UserData { header: Some(UserDataHeader { elements: elements }), body: UserDataBody::Binary(chunk.to_vec()) }
+            }
+        }).collect())
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let (user_data_length, user_data) = self.user_data.encode(self.data_coding_scheme)?;
+
+        let mut first_octet = 0x01;
+        if self.reject_duplicates { first_octet |= 0x04 }
+        if let Some(validity) = &self.validity_period { first_octet |= validity.format_bits() << 3 }
+        if self.status_report_request { first_octet |= 0x20 }
+        if self.user_data.header.is_some() { first_octet |= 0x40 }
+        if self.reply_path { first_octet |= 0x80 }
+
+        let mut encoded = vec![first_octet, self.message_reference];
+        encoded.extend(self.destination.encode()?);
+        encoded.push(self.protocol_identifier);
+        encoded.push(self.data_coding_scheme.0);
+        if let Some(validity) = &self.validity_period {
+            encoded.extend(validity.encode());
+        }
+        encoded.push(user_data_length);
+        encoded.extend(user_data);
+
+        Ok(encoded)
+    }
+
+    fn decode(first_octet: u8, reader: &mut Reader) -> Result<SmsSubmit> {
+        let message_reference = reader.byte()?;
+        let destination = Address::decode(reader)?;
+        let protocol_identifier = reader.byte()?;
+        let data_coding_scheme = DataCodingScheme(reader.byte()?);
+        let validity_period = ValidityPeriod::decode((first_octet >> 3) & 0x03, reader)?;
+        let user_data = UserData::decode(data_coding_scheme, first_octet & 0x40 != 0, reader)?;
+
+        Ok(SmsSubmit {
+            reject_duplicates: first_octet & 0x04 != 0,
+            reply_path: first_octet & 0x80 != 0,
+            status_report_request: first_octet & 0x20 != 0,
+            message_reference: message_reference,
+            destination: destination,
+            protocol_identifier: protocol_identifier,
+            data_coding_scheme: data_coding_scheme,
+            validity_period: validity_period,
+            user_data: user_data
+        })
+    }
+}
+
+/// Splits text into chunks that each fit in one message after a header of `header_length` octets
+fn split_text(content: &str, alphabet: Alphabet, tables: ShiftTables, header_length: usize) -> Vec<String> {
+    let capacity = part_capacity(alphabet, header_length);
+
+    let mut parts = vec![String::new()];
+    let mut used = 0;
+    for c in content.chars() {
+        let cost = match alphabet {
+            Alphabet::Gsm7 => tables.encode(c.encode_utf8(&mut [0; 4])).map(|septets| septets.len()).unwrap_or(1),
+            _ => c.len_utf16() * 2
+        };
+
+        if used + cost > capacity {
+            parts.push(String::new());
+            used = 0;
+        }
+        used += cost;
+        parts.last_mut().unwrap().push(c);
+    }
+
+    parts
+}
+
+/// How much text fits in one part after a header of the given length
+///
+/// GSM 7-bit is counted in septets and UCS2 in octets, with the header rounded up to whole septets
+fn part_capacity(alphabet: Alphabet, header_length: usize) -> usize {
+    match alphabet {
+        Alphabet::Gsm7 => 160 - (header_length * 8).div_ceil(7),
+        _ => 140 - header_length
+    }
+}
+
+/// How a text would be sent, worked out without sending it (ie. for a live character counter)
+///
+/// Matches what `GsmModem::send_pdu_sms` sends with an 8-bit concatenation reference. `GsmModem::send_text_sms`
+/// sends the same number of segments, but sends a single message that needs a national shift table as UCS2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageLength {
+    pub encoding: Encoding,
+    /// Length of the text alone, in septets for GSM 7-bit (escaped characters take two) or octets for UCS2
+    pub length: usize,
+    /// How many messages the text is sent as
+    pub segments: usize,
+    /// How many more characters fit in the last segment before another is needed
+    ///
+    /// Counted in septets for GSM 7-bit and UTF-16 units for UCS2, so escaped characters and emoji take two.
+    /// Adding to a single segment message can take away more than this, since a second part needs a header.
+    pub remaining: usize,
+    /// Characters outside the GSM 7-bit default alphabet and extension table, in the order they first appear,
+    /// only set when they forced UCS2
+    pub ucs2_characters: Vec<char>
+}
+
+impl MessageLength {
+    pub fn measure(content: &str) -> MessageLength {
+        let encoding = gsm7::cheapest_encoding(content);
+        let (alphabet, tables) = match encoding {
+            Encoding::Gsm7(tables) => (Alphabet::Gsm7, tables),
+            Encoding::Ucs2 => (Alphabet::Ucs2, ShiftTables::default())
+        };
+        let cost = |text: &str| match alphabet {
+            Alphabet::Gsm7 => tables.encode(text).map(|septets| septets.len()).unwrap_or_default(),
+            _ => text.encode_utf16().count() * 2
+        };
+
+        // Same header lengths as SmsSubmit::segments, concatenation takes 5 octets on top of the shift elements
+        let mut header_length = match tables.header_length() {
+            0 => 0,
+            length => length + 1
+        };
+        let mut parts = split_text(content, alphabet, tables, header_length);
+        if parts.len() > 1 {
+            header_length = tables.header_length() + 6;
+            parts = split_text(content, alphabet, tables, header_length);
+        }
+
+        let left = part_capacity(alphabet, header_length) - parts.last().map(|part| cost(part)).unwrap_or_default();
+        let mut ucs2_characters: Vec<char> = Vec::new();
+        if encoding == Encoding::Ucs2 {
+            for c in content.chars() {
+                if gsm7::encode(c.encode_utf8(&mut [0; 4])).is_none() && !ucs2_characters.contains(&c) {
+                    ucs2_characters.push(c);
+                }
+            }
+        }
+
+        MessageLength {
+            encoding: encoding,
+            length: cost(content),
+            segments: parts.len(),
+            remaining: if alphabet == Alphabet::Gsm7 { left } else { left / 2 },
+            ucs2_characters: ucs2_characters
+        }
+    }
+}
+
+/// A message delivered from the service centre to the phone
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmsDeliver {
+    /// The service centre has more messages waiting for this phone
+    pub more_messages_to_send: bool,
+    pub reply_path: bool,
+    /// The sender asked for a status report
+    pub status_report_indication: bool,
+    pub originator: Address,
+    pub protocol_identifier: u8,
+    pub data_coding_scheme: DataCodingScheme,
+    /// When the service centre received the message
+    pub service_centre_timestamp: DateTime<FixedOffset>,
+    pub user_data: UserData
+}
+
+impl SmsDeliver {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let (user_data_length, user_data) = self.user_data.encode(self.data_coding_scheme)?;
+
+        // TP-MMS is inverted, the bit is set when there are no more messages
+        let mut first_octet = 0x00;
+        if !self.more_messages_to_send { first_octet |= 0x04 }
+        if self.status_report_indication { first_octet |= 0x20 }
+        if self.user_data.header.is_some() { first_octet |= 0x40 }
+        if self.reply_path { first_octet |= 0x80 }
+
+        let mut encoded = vec![first_octet];
+        encoded.extend(self.originator.encode()?);
+        encoded.push(self.protocol_identifier);
+        encoded.push(self.data_coding_scheme.0);
+        encoded.extend(encode_timestamp(&self.service_centre_timestamp));
+        encoded.push(user_data_length);
+        encoded.extend(user_data);
+
+        Ok(encoded)
+    }
+
+    fn decode(first_octet: u8, reader: &mut Reader) -> Result<SmsDeliver> {
+        let originator = Address::decode(reader)?;
+        let protocol_identifier = reader.byte()?;
+        let data_coding_scheme = DataCodingScheme(reader.byte()?);
+        let service_centre_timestamp = decode_timestamp(reader)?;
+        let user_data = UserData::decode(data_coding_scheme, first_octet & 0x40 != 0, reader)?;
+
+        Ok(SmsDeliver {
+            more_messages_to_send: first_octet & 0x04 == 0,
+            reply_path: first_octet & 0x80 != 0,
+            status_report_indication: first_octet & 0x20 != 0,
+            originator: originator,
+            protocol_identifier: protocol_identifier,
+            data_coding_scheme: data_coding_scheme,
+            service_centre_timestamp: service_centre_timestamp,
+            user_data: user_data
+        })
+    }
+}
+
+/// Where a sent message is in being delivered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// The recipient has the message
+    Delivered,
+    /// The service centre is still trying
+    Pending,
+    /// The service centre has given up
+    Failed
+}
+
+/// TP-ST, the status carried by a status report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeliveryStatus(pub u8);
+
+impl DeliveryStatus {
+    pub fn state(&self) -> DeliveryState {
+        match self.0 {
+            0x00..=0x1f => DeliveryState::Delivered,
+            0x20..=0x3f => DeliveryState::Pending,
+            // Permanent errors, and temporary errors the service centre stopped retrying
+            _ => DeliveryState::Failed
+        }
+    }
+
+    /// Whether no further reports will follow for the message
+    pub fn is_final(&self) -> bool {
+        self.state() != DeliveryState::Pending
+    }
+
+    /// Description of the status (see 3GPP 23.040 9.2.3.15)
+    pub fn description(&self) -> &'static str {
+        match self.0 {
+            0x00 => "Received by the recipient",
+            0x01 => "Forwarded, but delivery couldn't be confirmed",
+            0x02 => "Replaced by the service centre",
+            0x20 | 0x60 => "Congestion",
+            0x21 | 0x61 => "Recipient busy",
+            0x22 | 0x62 => "No response from recipient",
+            0x23 | 0x63 => "Service rejected",
+            0x24 | 0x44 | 0x64 => "Quality of service not available",
+            0x25 | 0x65 => "Error in recipient",
+            0x40 => "Remote procedure error",
+            0x41 => "Incompatible destination",
+            0x42 => "Connection rejected by recipient",
+            0x43 => "Not obtainable",
+            0x45 => "No interworking available",
+            0x46 => "Validity period expired",
+            0x47 => "Deleted by the sender",
+            0x48 => "Deleted by the service centre",
+            0x49 => "Message doesn't exist",
+            0x03..=0x1f => "Delivered",
+            0x26..=0x3f => "Temporary error, still trying",
+            0x66..=0x7f => "Temporary error, no longer trying",
+            _ => "Permanent error"
+        }
+    }
+}
+
+/// A report from the service centre on a message sent with TP-SRR set
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmsStatusReport {
+    /// The message reference returned by `AT+CMGS` when the message was sent
+    pub message_reference: u8,
+    pub recipient: Address,
+    /// When the service centre received the message
+    pub service_centre_timestamp: DateTime<FixedOffset>,
+    /// When the message was delivered, or the service centre gave up
+    pub discharge_time: DateTime<FixedOffset>,
+    pub status: DeliveryStatus
+}
+
+impl SmsStatusReport {
+    fn encode(&self) -> Result<Vec<u8>> {
+        // SMS-STATUS-REPORT with no more messages waiting
+        let mut encoded = vec![0x06, self.message_reference];
+        encoded.extend(self.recipient.encode()?);
+        encoded.extend(encode_timestamp(&self.service_centre_timestamp));
+        encoded.extend(encode_timestamp(&self.discharge_time));
+        encoded.push(self.status.0);
+
+        Ok(encoded)
+    }
+
+    /// Anything after the status (parameter indicator, PID, DCS and user data) is optional and ignored
+    fn decode(reader: &mut Reader) -> Result<SmsStatusReport> {
+        Ok(SmsStatusReport {
+            message_reference: reader.byte()?,
+            recipient: Address::decode(reader)?,
+            service_centre_timestamp: decode_timestamp(reader)?,
+            discharge_time: decode_timestamp(reader)?,
+            status: DeliveryStatus(reader.byte()?)
+        })
+    }
+}
+
+/// The transfer protocol data unit, picked by the message type indicator
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tpdu {
+    Deliver(SmsDeliver),
+    Submit(SmsSubmit),
+    StatusReport(SmsStatusReport)
+}
+
+/// A full PDU as exchanged with the modem, the service centre address followed by the TPDU
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pdu {
+    /// `None` uses the service centre configured on the modem
+    pub smsc: Option<Address>,
+    pub tpdu: Tpdu
+}
+
+impl Pdu {
+    /// Encodes to hex, also returning the TPDU length in octets (the length `AT+CMGS` wants)
+    pub fn encode(&self) -> Result<(String, usize)> {
+        let mut encoded = Address::encode_smsc(self.smsc.as_ref())?;
+        let smsc_length = encoded.len();
+
+        encoded.extend(match &self.tpdu {
+            Tpdu::Deliver(deliver) => deliver.encode()?,
+            Tpdu::Submit(submit) => submit.encode()?,
+            Tpdu::StatusReport(report) => report.encode()?
+        });
+
+        Ok((bytes_to_hex(&encoded), encoded.len() - smsc_length))
+    }
+
+    /// Decodes a hex PDU as read from the modem
+    pub fn decode(hex: &str) -> Result<Pdu> {
+        let bytes = hex_to_bytes(hex.trim())?;
+        let mut reader = Reader { bytes: &bytes, position: 0, raw: hex };
+
+        let smsc = Address::decode_smsc(&mut reader)?;
+        let first_octet = reader.byte()?;
+
+        let tpdu = match first_octet & 0x03 {
+            0b00 => Tpdu::Deliver(SmsDeliver::decode(first_octet, &mut reader)?),
+            0b01 => Tpdu::Submit(SmsSubmit::decode(first_octet, &mut reader)?),
+            0b10 => Tpdu::StatusReport(SmsStatusReport::decode(&mut reader)?),
+            _ => return Err(Error::parse("Unsupported PDU message type!", hex))
+        };
+
+        Ok(Pdu { smsc: smsc, tpdu: tpdu })
+    }
+}
+
+/// A PDU read from the modem's storage with `AT+CMGR`/`AT+CMGL` in PDU mode
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredPdu {
+    pub index: u32,
+    pub status: SmsStatus,
+    pub pdu: Pdu
+}
+
+impl StoredPdu {
+    /// Takes the modem output of AT+CMGR in PDU mode
+    pub fn from_cmgr(raw_string: String, mem_index: u32) -> Result<StoredPdu> {
+        let captures = Regex::new(r"\+CMGR: (\d),[^,\r\n]*,\d+\r\n([0-9A-Fa-f]+)\r\n").unwrap().captures(&raw_string).ok_or_else(|| Error::parse("Failed to parse PDU message!", &raw_string))?;
+
+        let status = SmsStatus::try_from_pdu_status(captures[1].parse().map_err(|_| Error::parse("Failed to parse PDU message status!", &raw_string))?)?;
+
+        Ok(StoredPdu { index: mem_index, status: status, pdu: Pdu::decode(&captures[2])? })
+    }
+
+    /// Takes the modem output of AT+CMGL in PDU mode
+    pub fn from_cmgl(raw_string: String) -> Result<Vec<StoredPdu>> {
+        let msg_regex = Regex::new(r"\+CMGL: (\d{1,3}),(\d),[^,\r\n]*,\d+\r\n([0-9A-Fa-f]+)\r\n").unwrap();
+
+        msg_regex.captures_iter(&raw_string).map(|captures| {
+            let index = captures[1].parse::<u32>().map_err(|_| Error::parse("Failed to parse message memory index!", &raw_string))?;
+            let status = SmsStatus::try_from_pdu_status(captures[2].parse().map_err(|_| Error::parse("Failed to parse PDU message status!", &raw_string))?)?;
+
+            Ok(StoredPdu { index: index, status: status, pdu: Pdu::decode(&captures[3])? })
+        }).collect()
+    }
+}
+
+/// Reads through the octets of a PDU, turning running out of data into a parse error
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// The hex being decoded, for errors
+    raw: &'a str
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position + count).ok_or_else(|| Error::parse("PDU ended early!", self.raw))?;
+        self.position += count;
+
+        Ok(bytes)
+    }
+}
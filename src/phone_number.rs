@@ -0,0 +1,139 @@
+use std::{fmt, str::FromStr};
+
+use crate::{error::{Error, Result}, pdu::{Address, NumberingPlan, TypeOfNumber}};
+
+/// Longest international number allowed by E.164, not counting the `+`
+const MAX_INTERNATIONAL_DIGITS: usize = 15;
+
+/// Longest number that fits in a TP address
+const MAX_DIGITS: usize = 20;
+
+/// Longest country code in E.164
+const MAX_COUNTRY_CODE_DIGITS: usize = 3;
+
+/// Shortest national significant number `matches` compares against an international one, so a short code
+/// or a few trailing digits never match a full number
+const MIN_NATIONAL_DIGITS: usize = 7;
+
+/// Characters people put in numbers to make them readable, dropped when parsing
+const SEPARATORS: &[char] = &[' ', '-', '.', '(', ')', '/'];
+
+/// A validated phone number, either international (E.164, with a country code) or national
+///
+/// Parse destinations with this before sending, so a typo fails here instead of with `+CME ERROR: 27`
+/// from the modem, and compare received addresses with `matches` to find messages from the same contact.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PhoneNumber {
+    digits: String,
+    international: bool
+}
+
+impl PhoneNumber {
+    /// Parses a number as a person would write it, ie. `+1 (315) 555-0123` or `0171 1234567`
+    ///
+    /// A leading `+` makes the number international. Spaces, dashes, dots, slashes and parentheses are
+    /// dropped, anything else that isn't a digit is rejected.
+    pub fn parse(number: &str) -> Result<PhoneNumber> {
+        let number = number.trim();
+        let (international, rest) = match number.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, number)
+        };
+
+        let digits: String = rest.chars().filter(|c| !SEPARATORS.contains(c)).collect();
+        if digits.is_empty() {
+            return Err(Error::invalid_argument("Phone number has no digits!"))
+        }
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::invalid_argument("Phone number can only have digits after a leading `+`!"))
+        }
+        if international && digits.len() > MAX_INTERNATIONAL_DIGITS {
+            return Err(Error::invalid_argument("International phone number is longer than 15 digits!"))
+        }
+        if digits.len() > MAX_DIGITS {
+            return Err(Error::invalid_argument("Phone number is longer than 20 digits!"))
+        }
+
+        Ok(PhoneNumber { digits: digits, international: international })
+    }
+
+    /// The digits, without the `+` of international numbers
+    pub fn digits(&self) -> &str {
+        &self.digits
+    }
+
+    pub fn is_international(&self) -> bool {
+        self.international
+    }
+
+    /// The type-of-address used with `AT+CMGS` and in PDUs, `145` for international numbers and `129` otherwise
+    pub fn type_of_address(&self) -> u8 {
+        if self.international { 145 } else { 129 }
+    }
+
+    /// Parses a number that already starts with its country code but is missing the `+`, ie. `13155550123`
+    pub fn parse_without_plus(number: &str) -> Result<PhoneNumber> {
+        PhoneNumber::parse(&format!("+{}", number.trim().trim_start_matches('+')))
+    }
+
+    /// Makes a national number international with the given country code (ie. `"44"`), dropping its trunk prefix
+    ///
+    /// The country code is always added, use `parse_without_plus` for numbers that already have one.
+    /// International numbers are returned as they are.
+    pub fn with_country_code(&self, country_code: &str) -> Result<PhoneNumber> {
+        if self.international {
+            return Ok(self.clone())
+        }
+
+        PhoneNumber::parse(&format!("+{}{}", country_code, self.digits.trim_start_matches('0')))
+    }
+
+    /// Whether both numbers reach the same contact, allowing either one to be missing its country code
+    ///
+    /// National numbers are compared as the end of international ones, ignoring their trunk prefix. What's left
+    /// of the international number has to be a country code (at most 3 digits) and the national number has to
+    /// have at least 7 digits, so `5550123` doesn't match `+13155550123`.
+    pub fn matches(&self, other: &PhoneNumber) -> bool {
+        match (self.international, other.international) {
+            (true, true) | (false, false) => self.digits == other.digits,
+            (true, false) => ends_with_national(&self.digits, &other.digits),
+            (false, true) => ends_with_national(&other.digits, &self.digits)
+        }
+    }
+}
+
+impl FromStr for PhoneNumber {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PhoneNumber::parse(s)
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.international {
+            write!(f, "+{}", self.digits)
+        } else {
+            write!(f, "{}", self.digits)
+        }
+    }
+}
+
+impl From<&PhoneNumber> for Address {
+    fn from(number: &PhoneNumber) -> Address {
+        Address {
+            number: number.digits.clone(),
+            type_of_number: if number.international { TypeOfNumber::International } else { TypeOfNumber::Unknown },
+            numbering_plan: NumberingPlan::Isdn
+        }
+    }
+}
+
+fn ends_with_national(international: &str, national: &str) -> bool {
+    let national = national.trim_start_matches('0');
+
+    national.len() >= MIN_NATIONAL_DIGITS
+        && international.len() - national.len().min(international.len()) <= MAX_COUNTRY_CODE_DIGITS
+        && international.ends_with(national)
+}
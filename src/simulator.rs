@@ -0,0 +1,857 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt, DuplexStream}, sync::mpsc::{self, UnboundedReceiver, UnboundedSender}, time::Instant};
+
+use crate::{broadcast::CellBroadcastPage, gsm7, pdu::{Address, DeliveryStatus, Pdu, SmsDeliver, SmsStatusReport, SmsSubmit, Tpdu, UserData, UserDataBody}, transport::TransportConfig, utils::{bytes_to_hex, datetime_to_timestamp, hex_to_utf16, timestamp_to_iso_8601, utf16_to_hex}};
+
+/// A fake SIM7600 that answers AT commands over an in-memory transport
+///
+/// Like the real modem it echoes commands until `ATE0`, and responses mirror what it returns once `GsmModem::open`
+/// has configured it with `ATE0`, `AT+CMEE=1` and `AT+CSCS="UCS2"`, so every `GsmModem` method can be exercised
+/// without hardware attached.
+pub struct SimulatedModem {
+    state: SimulatorState
+}
+
+/// A message held in the simulator's SMS storage
+#[derive(Clone, Debug)]
+pub struct SimulatedSms {
+    pub index: u32,
+    /// Text mode status (ie. `REC UNREAD`)
+    pub status: String,
+    pub address: String,
+    pub content: String,
+    /// Timestamp in the modem's format (ie. `25/06/01,12:00:00-16`)
+    pub timestamp: String,
+    /// The exact PDU (with SMSC) to return in PDU mode, instead of one built from the fields above
+    pub pdu: Option<String>
+}
+
+/// A message the simulator was asked to send via `AT+CMGS`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentSms {
+    pub destination: String,
+    /// Binary messages have their content as hex
+    pub content: String
+}
+
+#[derive(Clone)]
+struct SimulatorState {
+    echo: bool,
+    imei: String,
+    service_centre: String,
+    signal_quality: (u8, u8),
+    sms_format: u8,
+    auto_timezone_updates: bool,
+    messages: Vec<SimulatedSms>,
+    sent: Vec<SentSms>,
+    /// Every SMS-SUBMIT sent in PDU mode, as decoded
+    sent_submits: Vec<SmsSubmit>,
+    commands: Vec<String>,
+    /// Commands starting with any of these prefixes never get a response
+    silent_prefixes: Vec<String>,
+    /// Commands starting with any of these prefixes are answered only after the delay
+    slow_prefixes: Vec<(String, Duration)>,
+    next_message_reference: u8,
+    /// Storage names selected with `AT+CPMS` for reading, writing and receiving
+    ///
+    /// Every storage shares the same messages, only the names are kept.
+    preferred_storage: [String; 3],
+    /// How many messages fit in storage before `+SMS FULL`
+    storage_capacity: u32,
+    /// Values set with `AT+CNMI`
+    new_message_indications: [u8; 5],
+    /// How many times `AT+CNMA` was sent
+    acknowledgements: u32,
+    /// First octet set with `AT+CSMP`, used for messages sent in text mode
+    submit_first_octet: u8,
+    /// The rest of `AT+CSMP` as it was set (validity period, protocol identifier and data coding scheme)
+    submit_parameters: String,
+    /// Set with `AT+CSDH`, adds the header details to text mode `AT+CMGR` responses
+    show_text_headers: bool,
+    /// Message reference, destination and whether a status report was requested, for every message sent
+    references: Vec<(u8, String, bool)>,
+    /// CMS errors to answer the next `AT+CMGS` sends with, in order
+    send_failures: Vec<u32>,
+    /// Mode, message identifiers and languages set with `AT+CSCB`, the lists decoded from UCS2
+    broadcast_channels: (u8, String, String),
+    /// Answers to USSD requests and replies: the text sent, then the status and text of the answer
+    ussd_answers: Vec<(String, u8, String)>
+}
+
+/// Handle to a running simulator, used to inspect it and inject unsolicited result codes
+#[derive(Clone)]
+pub struct SimulatorHandle {
+    state: Arc<Mutex<SimulatorState>>,
+    urc_sender: UnboundedSender<String>
+}
+
+impl SimulatedModem {
+    pub fn new() -> Self {
+        SimulatedModem {
+            state: SimulatorState {
+                echo: true,
+                imei: String::from("867584032145678"),
+                service_centre: String::from("+15555550100"),
+                signal_quality: (20, 99),
+                sms_format: 1,
+                auto_timezone_updates: false,
+                messages: Vec::new(),
+                sent: Vec::new(),
+                sent_submits: Vec::new(),
+                commands: Vec::new(),
+                silent_prefixes: Vec::new(),
+                slow_prefixes: Vec::new(),
+                next_message_reference: 1,
+                preferred_storage: [String::from("SM"), String::from("SM"), String::from("SM")],
+                storage_capacity: 30,
+                new_message_indications: [2, 1, 0, 0, 0],
+                acknowledgements: 0,
+                submit_first_octet: 17,
+                submit_parameters: String::from("167,0,0"),
+                show_text_headers: false,
+                references: Vec::new(),
+                send_failures: Vec::new(),
+                broadcast_channels: (0, String::new(), String::new()),
+                ussd_answers: Vec::new()
+            }
+        }
+    }
+
+    /// Sets the IMEI returned by `AT+SIMEI?`
+    pub fn with_imei(mut self, imei: &str) -> Self {
+        self.state.imei = String::from(imei);
+        self
+    }
+
+    /// Sets the CSQ and bit error rate returned by `AT+CSQ`
+    pub fn with_signal_quality(mut self, csq: u8, ber: u8) -> Self {
+        self.state.signal_quality = (csq, ber);
+        self
+    }
+
+    /// Stores a message in the next free memory index
+    pub fn with_sms(mut self, status: &str, address: &str, content: &str, timestamp: &str) -> Self {
+        self.state.store_sms(status, address, content, timestamp);
+        self
+    }
+
+    /// Stores a message from its hex PDU (with SMSC), ie. to store one part of a concatenated message
+    ///
+    /// Panics if the PDU can't be decoded
+    pub fn with_pdu_sms(mut self, status: &str, pdu: &str) -> Self {
+        self.state.store_pdu(status, pdu);
+        self
+    }
+
+    /// Sets how many messages fit in storage (30 by default, like a typical SIM)
+    pub fn with_storage_capacity(mut self, capacity: u32) -> Self {
+        self.state.storage_capacity = capacity;
+        self
+    }
+
+    /// Answers the USSD request (or menu reply) `request` with a `+CUSD` of the given status and text
+    ///
+    /// Requests with no answer set get an `OK` but no `+CUSD`, like a network that never answers.
+    pub fn with_ussd(mut self, request: &str, status: u8, response: &str) -> Self {
+        self.state.ussd_answers.push((String::from(request), status, String::from(response)));
+        self
+    }
+
+    /// Never answers commands starting with the given prefix (ie. `AT+COPS`), useful for exercising timeouts
+    pub fn with_silent_command(mut self, prefix: &str) -> Self {
+        self.state.silent_prefixes.push(prefix.to_uppercase());
+        self
+    }
+
+    /// Answers commands starting with the given prefix only after `delay`, handling nothing else meanwhile like a
+    /// busy modem. For `AT+CMGS` it's the `> ` prompt that's held back.
+    pub fn with_slow_command(mut self, prefix: &str, delay: Duration) -> Self {
+        self.state.slow_prefixes.push((prefix.to_uppercase(), delay));
+        self
+    }
+
+    /// Starts the simulator on a new in-memory pipe, returning the transport for `GsmModem` and a handle to the simulator
+    pub fn start(self) -> (TransportConfig, SimulatorHandle) {
+        let (transport, stream) = TransportConfig::in_memory(4096);
+        let (urc_sender, urc_receiver) = mpsc::unbounded_channel();
+        let state = Arc::new(Mutex::new(self.state));
+
+        tokio::spawn(run(stream, state.clone(), urc_receiver));
+
+        (transport, SimulatorHandle { state: state, urc_sender: urc_sender })
+    }
+}
+
+impl Default for SimulatedModem {
+    fn default() -> Self {
+        SimulatedModem::new()
+    }
+}
+
+impl SimulatorHandle {
+    /// Writes a raw unsolicited result code to the modem (ie. `"\r\n+CMTI: \"SM\",3\r\n"`)
+    pub fn inject_urc(&self, urc: &str) {
+        // The only failure is the simulator having stopped, in which case there's nobody left to notify
+        let _ = self.urc_sender.send(String::from(urc));
+    }
+
+    /// Stores a new incoming message and notifies the modem with `+CMTI`, followed by `+SMS FULL` if that filled storage
+    pub fn receive_sms(&self, address: &str, content: &str, timestamp: &str) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let index = state.store_sms("REC UNREAD", address, content, timestamp);
+        self.notify_received(&state, index);
+
+        index
+    }
+
+    /// Stores a new incoming message from its hex PDU (with SMSC) and notifies the modem with `+CMTI`,
+    /// followed by `+SMS FULL` if that filled storage
+    ///
+    /// Panics if the PDU can't be decoded
+    pub fn receive_pdu_sms(&self, pdu: &str) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let index = state.store_pdu("REC UNREAD", pdu);
+        self.notify_received(&state, index);
+
+        index
+    }
+
+    /// Routes a new incoming message straight to the modem with `+CMT` without storing it, in the current SMS format
+    ///
+    /// Sent whatever `AT+CNMI` is set to, so the test decides when messages are routed.
+    pub fn route_sms(&self, address: &str, content: &str, timestamp: &str) {
+        let urc = self.state.lock().unwrap().direct_sms(address, content, timestamp);
+        self.inject_urc(&urc);
+    }
+
+    /// Routes a new incoming message straight to the modem with `+CMT` from its hex PDU (with SMSC)
+    ///
+    /// Panics if the simulator is in text mode, since only PDU mode shows the PDU
+    pub fn route_pdu_sms(&self, pdu: &str) {
+        let state = self.state.lock().unwrap();
+        assert_eq!(state.sms_format, 0, "PDUs can only be routed in PDU mode");
+
+        let smsc_length = u8::from_str_radix(&pdu[..2], 16).expect("Simulated PDU should decode") as usize;
+        self.inject_urc(&format!("\r\n+CMT: ,{}\r\n{}\r\n", pdu.len() / 2 - 1 - smsc_length, pdu));
+    }
+
+    /// How many times the modem has acknowledged a routed message with `AT+CNMA`
+    pub fn acknowledgements(&self) -> u32 {
+        self.state.lock().unwrap().acknowledgements
+    }
+
+    fn notify_received(&self, state: &SimulatorState, index: u32) {
+        self.inject_urc(&format!("\r\n+CMTI: \"{}\",{}\r\n", state.preferred_storage[2], index));
+
+        if state.messages.len() as u32 >= state.storage_capacity {
+            self.inject_urc("\r\n+SMS FULL\r\n");
+        }
+    }
+
+    /// Sends a `+CDS` status report for the sent message with the given reference, in the current SMS format
+    ///
+    /// `status` is the TP-ST value (ie. 0 for delivered). The report is sent even if none was requested, like a misbehaving network would.
+    pub fn status_report(&self, reference: u8, status: u8) {
+        let urc = self.state.lock().unwrap().status_report(reference, status);
+        self.inject_urc(&urc);
+    }
+
+    /// Whether a status report was requested for the sent message with the given reference
+    pub fn status_report_requested(&self, reference: u8) -> bool {
+        self.state.lock().unwrap().references.iter().rev().find(|(r, _, _)| *r == reference).is_some_and(|(_, _, requested)| *requested)
+    }
+
+    /// Answers the next sends with `+CMS ERROR: <code>`, once for every code given
+    pub fn fail_next_sends(&self, codes: &[u32]) {
+        self.state.lock().unwrap().send_failures.extend_from_slice(codes);
+    }
+
+    /// Sends a `+CBM` for a cell broadcast page, in the current SMS format
+    ///
+    /// Sent whatever `AT+CSCB` and `AT+CNMI` are set to, so the test decides which broadcasts arrive.
+    /// Panics if the page can't be encoded.
+    pub fn broadcast(&self, page: &CellBroadcastPage) {
+        let urc = match self.state.lock().unwrap().sms_format {
+            0 => format!("\r\n+CBM: 88\r\n{}\r\n", page.encode().expect("Simulated broadcast should encode")),
+            _ => format!(
+                "\r\n+CBM: {},{},{},{},{}\r\n{}\r\n",
+                page.serial_number.0, page.message_identifier, page.data_coding_scheme, page.page, page.pages, utf16_to_hex(&page.content)
+            )
+        };
+        self.inject_urc(&urc);
+    }
+
+    /// Sends a `+CUSD` the network started, with the text as UCS2 hex
+    pub fn ussd(&self, status: u8, message: &str) {
+        self.inject_urc(&format!("\r\n+CUSD: {},\"{}\",15\r\n", status, utf16_to_hex(message)));
+    }
+
+    /// Sends a `MISSED_CALL` URC, time is in the modem's format (ie. `14:05PM`)
+    pub fn missed_call(&self, time: &str, number: &str) {
+        self.inject_urc(&format!("\r\nMISSED_CALL: {} {}\r\n", time, number));
+    }
+
+    /// Messages the modem has been asked to send
+    pub fn sent_messages(&self) -> Vec<SentSms> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// Every SMS-SUBMIT sent in PDU mode, ie. to check the data coding scheme or header of a sent message
+    pub fn sent_submits(&self) -> Vec<SmsSubmit> {
+        self.state.lock().unwrap().sent_submits.clone()
+    }
+
+    /// Messages currently held in storage
+    pub fn stored_messages(&self) -> Vec<SimulatedSms> {
+        self.state.lock().unwrap().messages.clone()
+    }
+
+    /// Every command received so far, without the trailing `\r`
+    pub fn received_commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Whether commands are echoed back, until `ATE0`
+    pub fn echo(&self) -> bool {
+        self.state.lock().unwrap().echo
+    }
+
+    /// The IMEI currently configured on the simulator
+    pub fn imei(&self) -> String {
+        self.state.lock().unwrap().imei.clone()
+    }
+
+    /// Whether automatic timezone updates (`AT+CTZU`) are enabled
+    pub fn auto_timezone_updates(&self) -> bool {
+        self.state.lock().unwrap().auto_timezone_updates
+    }
+}
+
+impl SimulatorState {
+    fn store_sms(&mut self, status: &str, address: &str, content: &str, timestamp: &str) -> u32 {
+        let index = (0..).find(|i| !self.messages.iter().any(|m| m.index == *i)).unwrap();
+        self.messages.push(SimulatedSms {
+            index: index,
+            status: String::from(status),
+            address: String::from(address),
+            content: String::from(content),
+            timestamp: String::from(timestamp),
+            pdu: None
+        });
+
+        index
+    }
+
+    fn store_pdu(&mut self, status: &str, pdu: &str) -> u32 {
+        let decoded = Pdu::decode(pdu).expect("Simulated PDU should decode");
+        let (address, content, timestamp) = match &decoded.tpdu {
+            Tpdu::Deliver(deliver) => (
+                deliver.originator.to_string(),
+                deliver.user_data.text().unwrap_or_default(),
+                datetime_to_timestamp(&deliver.service_centre_timestamp)
+            ),
+            Tpdu::Submit(submit) => (submit.destination.to_string(), submit.user_data.text().unwrap_or_default(), String::new()),
+            Tpdu::StatusReport(report) => (report.recipient.to_string(), "", datetime_to_timestamp(&report.discharge_time))
+        };
+
+        let index = self.store_sms(status, &address, content, &timestamp);
+        self.messages.last_mut().unwrap().pdu = Some(String::from(pdu));
+
+        index
+    }
+
+    /// Records a message body sent after `AT+CMGS`, `argument` is whatever followed the `=`
+    fn send(&mut self, argument: &str, body: &[u8]) -> String {
+        if !self.send_failures.is_empty() {
+            return cms_error(self.send_failures.remove(0))
+        }
+
+        let (sent, status_report_requested) = if self.sms_format == 0 {
+            // In PDU mode the argument is the TPDU length and the body is the hex PDU
+            let pdu = Pdu::decode(&String::from_utf8_lossy(body));
+            match pdu {
+                Ok(Pdu { tpdu: Tpdu::Submit(submit), .. }) if argument.parse::<usize>().is_ok() => {
+                    let sent = SentSms {
+                        destination: submit.destination.to_string(),
+                        content: match &submit.user_data.body {
+                            UserDataBody::Text(text) => text.clone(),
+                            UserDataBody::Binary(data) => bytes_to_hex(data)
+                        }
+                    };
+                    let status_report_requested = submit.status_report_request;
+                    self.sent_submits.push(submit);
+
+                    (sent, status_report_requested)
+                }
+                _ => return cms_error(304)
+            }
+        } else {
+            // Both are UCS2 hex since the modem is configured with AT+CSCS="UCS2"
+            // The type-of-address after the destination is optional, the `+` already says whether it's international
+            let destination = hex_to_utf16(argument.split(',').next().unwrap_or_default().trim_matches('"'));
+            let content = hex_to_utf16(&String::from_utf8_lossy(body));
+            match (destination, content) {
+                (Ok(destination), Ok(content)) => (SentSms { destination: destination, content: content }, self.submit_first_octet & 0x20 != 0),
+                _ => return cms_error(304)
+            }
+        };
+
+        let reference = self.next_message_reference;
+        self.next_message_reference = reference.wrapping_add(1);
+        self.references.push((reference, sent.destination.clone(), status_report_requested));
+        self.sent.push(sent);
+
+        info(&format!("+CMGS: {}", reference))
+    }
+
+    /// Builds a `+CMT` URC for a new message
+    fn direct_sms(&self, address: &str, content: &str, timestamp: &str) -> String {
+        if self.sms_format == 0 {
+            let message = SimulatedSms {
+                index: 0,
+                status: String::from("REC UNREAD"),
+                address: String::from(address),
+                content: String::from(content),
+                timestamp: String::from(timestamp),
+                pdu: None
+            };
+            let (pdu, length) = message_pdu(&message, &self.service_centre).expect("Routed message should encode");
+
+            format!("\r\n+CMT: ,{}\r\n{}\r\n", length, pdu)
+        } else {
+            format!("\r\n+CMT: \"{}\",\"\",\"{}\"\r\n{}\r\n", utf16_to_hex(address), timestamp, utf16_to_hex(content))
+        }
+    }
+
+    /// Builds a `+CDS` URC for the sent message with the given reference
+    fn status_report(&self, reference: u8, status: u8) -> String {
+        let recipient = self.references.iter().rev().find(|(r, _, _)| *r == reference).map(|(_, destination, _)| destination.clone()).unwrap_or_default();
+        let now = Utc::now().fixed_offset();
+
+        if self.sms_format == 0 {
+            let report = SmsStatusReport {
+                message_reference: reference,
+                recipient: Address::new(&recipient),
+                service_centre_timestamp: now,
+                discharge_time: now,
+                status: DeliveryStatus(status)
+            };
+            let (pdu, length) = Pdu { smsc: Some(Address::new(&self.service_centre)), tpdu: Tpdu::StatusReport(report) }.encode().expect("Status report should encode");
+
+            format!("\r\n+CDS: {}\r\n{}\r\n", length, pdu)
+        } else {
+            let type_of_address = if recipient.starts_with('+') { 145 } else { 129 };
+            let timestamp = datetime_to_timestamp(&now);
+
+            format!(
+                "\r\n+CDS: 6,{},\"{}\",{},\"{}\",\"{}\",{}\r\n",
+                reference, utf16_to_hex(&recipient), type_of_address, timestamp, timestamp, status
+            )
+        }
+    }
+
+    /// Builds the response to a single command line
+    fn respond(&mut self, command: &str) -> String {
+        let upper = command.to_uppercase();
+
+        match upper.as_str() {
+            "AT" | "ATZ" => return ok(),
+            "ATE0" => { self.echo = false; return ok() }
+            "ATE1" => { self.echo = true; return ok() }
+            "AT+CSQ" => return info(&format!("+CSQ: {},{}", self.signal_quality.0, self.signal_quality.1)),
+            "AT+SIMEI?" => return info(&format!("+SIMEI: {}", self.imei)),
+            "AT+CMGF?" => return info(&format!("+CMGF: {}", self.sms_format)),
+            "AT+CTZU?" => return info(&format!("+CTZU: {}", self.auto_timezone_updates as u8)),
+            "AT+CNMI?" => {
+                let values: Vec<String> = self.new_message_indications.iter().map(|value| value.to_string()).collect();
+                return info(&format!("+CNMI: {}", values.join(",")))
+            }
+            "AT+CNMA" => { self.acknowledgements += 1; return ok() }
+            _ => ()
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CMEE=") {
+            return if matches!(value, "0" | "1" | "2") { ok() } else { error() }
+        }
+
+        if upper.starts_with("AT+CSCS=") {
+            return ok()
+        }
+
+        if upper == "AT+CPMS?" {
+            let used = self.messages.len();
+            let usage: Vec<String> = self.preferred_storage.iter().map(|storage| format!("\"{}\",{},{}", storage, used, self.storage_capacity)).collect();
+            return info(&format!("+CPMS: {}", usage.join(",")))
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CPMS=") {
+            let storages: Vec<&str> = value.split(',').map(|storage| storage.trim_matches('"')).collect();
+            if storages.is_empty() || storages.len() > 3 || !storages.iter().all(|storage| matches!(*storage, "SM" | "ME" | "MT")) {
+                return cms_error(302)
+            }
+
+            for (i, storage) in storages.iter().enumerate() {
+                self.preferred_storage[i] = String::from(*storage);
+            }
+            let used = self.messages.len();
+            let usage = vec![format!("{},{}", used, self.storage_capacity); 3];
+            return info(&format!("+CPMS: {}", usage.join(",")))
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CMGD=") {
+            let mut arguments = value.split(',');
+            let index = arguments.next().and_then(|index| index.parse::<u32>().ok());
+            let flag = arguments.next().map(|flag| flag.parse::<u8>().ok());
+
+            let deleted: &[&str] = match flag {
+                None | Some(Some(0)) => {
+                    let Some(index) = index else { return cms_error(321) };
+                    let Some(position) = self.messages.iter().position(|m| m.index == index) else { return cms_error(321) };
+                    self.messages.remove(position);
+                    return ok()
+                }
+                Some(Some(1)) => &["REC READ"],
+                Some(Some(2)) => &["REC READ", "STO SENT"],
+                Some(Some(3)) => &["REC READ", "STO SENT", "STO UNSENT"],
+                Some(Some(4)) => &["REC READ", "STO SENT", "STO UNSENT", "REC UNREAD"],
+                _ => return cms_error(302)
+            };
+
+            self.messages.retain(|m| !deleted.contains(&m.status.as_str()));
+            return ok()
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CSMP=") {
+            let (first_octet, parameters) = value.split_once(',').unwrap_or((value, ""));
+            return match first_octet.parse::<u8>() {
+                Ok(first_octet) => {
+                    self.submit_first_octet = first_octet;
+                    self.submit_parameters = String::from(parameters);
+                    ok()
+                }
+                Err(_) => cms_error(304)
+            }
+        }
+
+        if upper == "AT+CSMP?" {
+            return info(&format!("+CSMP: {},{}", self.submit_first_octet, self.submit_parameters))
+        }
+
+        if upper == "AT+CSCA?" {
+            let type_of_address = if self.service_centre.starts_with('+') { 145 } else { 129 };
+            return info(&format!("+CSCA: \"{}\",{}", utf16_to_hex(&self.service_centre), type_of_address))
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CSCA=") {
+            let (number, type_of_address) = value.split_once(',').unwrap_or((value, "129"));
+            let Ok(number) = hex_to_utf16(number.trim_matches('"')) else { return cms_error(304) };
+            self.service_centre = match type_of_address {
+                "145" if !number.starts_with('+') => format!("+{}", number),
+                _ => number
+            };
+            return ok()
+        }
+
+        if upper == "AT+CSDH?" {
+            return info(&format!("+CSDH: {}", self.show_text_headers as u8))
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CSDH=") {
+            return match value {
+                "0" | "1" => { self.show_text_headers = value == "1"; ok() }
+                _ => error()
+            }
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CMGF=") {
+            return match value.parse::<u8>() {
+                Ok(format @ (0 | 1)) => { self.sms_format = format; ok() }
+                _ => error()
+            }
+        }
+
+        if upper == "AT+CSCB?" {
+            let (mode, message_identifiers, languages) = &self.broadcast_channels;
+            return info(&format!("+CSCB: {},\"{}\",\"{}\"", mode, utf16_to_hex(message_identifiers), utf16_to_hex(languages)))
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CSCB=") {
+            let arguments: Vec<&str> = value.split(',').map(|argument| argument.trim_matches('"')).collect();
+            let lists: Vec<Option<String>> = arguments.iter().skip(1).map(|list| hex_to_utf16(list).ok()).collect();
+            return match (arguments[0], lists.as_slice()) {
+                ("0" | "1", [Some(message_identifiers), Some(languages)]) => {
+                    self.broadcast_channels = (arguments[0].parse().unwrap(), message_identifiers.clone(), languages.clone());
+                    ok()
+                }
+                _ => cms_error(303)
+            }
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CUSD=") {
+            let arguments: Vec<&str> = value.split(',').map(|argument| argument.trim_matches('"')).collect();
+            return match arguments.as_slice() {
+                ["1", request, ..] => {
+                    let Ok(request) = hex_to_utf16(request) else { return cme_error(4) };
+                    match self.ussd_answers.iter().find(|(answered, _, _)| answered.to_uppercase() == request.to_uppercase()) {
+                        Some((_, status, response)) => format!("{}\r\n+CUSD: {},\"{}\",15\r\n", ok(), status, utf16_to_hex(response)),
+                        None => ok()
+                    }
+                }
+                ["0" | "1" | "2"] => ok(),
+                _ => cme_error(4)
+            }
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CNMI=") {
+            let values: Vec<Option<u8>> = value.split(',').map(|value| value.parse().ok()).collect();
+            let maximums = [2, 3, 3, 2, 1];
+            if values.len() > 5 || values.iter().zip(maximums).any(|(value, maximum)| !value.is_some_and(|value| value <= maximum)) {
+                return cms_error(303)
+            }
+
+            for (i, value) in values.into_iter().enumerate() {
+                self.new_message_indications[i] = value.unwrap();
+            }
+            return ok()
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CTZU=") {
+            return match value {
+                "0" => { self.auto_timezone_updates = false; ok() }
+                "1" => { self.auto_timezone_updates = true; ok() }
+                _ => error()
+            }
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+SIMEI=") {
+            if value.len() != 15 || !value.chars().all(|c| c.is_ascii_digit()) {
+                return cme_error(3)
+            }
+            self.imei = String::from(value);
+            return ok()
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CMGR=") {
+            let Ok(index) = value.parse::<u32>() else { return cms_error(321) };
+            let Some(message) = self.messages.iter_mut().find(|m| m.index == index) else { return cms_error(321) };
+
+            let response = if self.sms_format == 0 {
+                let Some(pdu) = message_pdu(message, &self.service_centre) else { return cms_error(500) };
+                format!("\r\n+CMGR: {},,{}\r\n{}\r\n\r\nOK\r\n", pdu_status(&message.status), pdu.1, pdu.0)
+            } else if message.status.starts_with("STO") {
+                // Outgoing messages have no timestamp
+                format!("\r\n+CMGR: \"{}\",\"{}\",\"\"\r\n{}\r\n\r\nOK\r\n", message.status, utf16_to_hex(&message.address), utf16_to_hex(&message.content))
+            } else {
+                // <tooa>,<fo>,<pid>,<dcs>,<sca>,<tosca>,<length>
+                let details = if self.show_text_headers {
+                    let type_of_address = |number: &str| if number.starts_with('+') { 145 } else { 129 };
+                    let dcs = if gsm7::encode(&message.content).is_some() { 0 } else { 8 };
+                    format!(
+                        ",{},4,0,{},\"{}\",{},{}",
+                        type_of_address(&message.address), dcs, utf16_to_hex(&self.service_centre), type_of_address(&self.service_centre), message.content.chars().count()
+                    )
+                } else {
+                    String::new()
+                };
+                format!(
+                    "\r\n+CMGR: \"{}\",\"{}\",\"\",\"{}\"{}\r\n{}\r\n\r\nOK\r\n",
+                    message.status, utf16_to_hex(&message.address), message.timestamp, details, utf16_to_hex(&message.content)
+                )
+            };
+
+            // Reading a message marks it as read on the real modem too
+            if message.status == "REC UNREAD" {
+                message.status = String::from("REC READ");
+            }
+
+            return response
+        }
+
+        if let Some(value) = upper.strip_prefix("AT+CMGL=") {
+            // PDU mode lists by number, with 4 for every message
+            let status = match (self.sms_format, value) {
+                (0, "0") => "REC UNREAD",
+                (0, "1") => "REC READ",
+                (0, "2") => "STO UNSENT",
+                (0, "3") => "STO SENT",
+                (0, "4") => "ALL",
+                (0, _) => return cms_error(302),
+                _ => value.trim_matches('"')
+            };
+            let mut response = String::new();
+
+            for message in self.messages.iter_mut().filter(|m| status == "ALL" || m.status == status) {
+                if self.sms_format == 0 {
+                    let Some(pdu) = message_pdu(message, &self.service_centre) else { return cms_error(500) };
+                    response.push_str(&format!("\r\n+CMGL: {},{},,{}\r\n{}", message.index, pdu_status(&message.status), pdu.1, pdu.0));
+                } else {
+                    response.push_str(&format!(
+                        "\r\n+CMGL: {},\"{}\",\"{}\",\"\",\"{}\"\r\n{}",
+                        message.index, message.status, utf16_to_hex(&message.address), message.timestamp, utf16_to_hex(&message.content)
+                    ));
+                }
+
+                if message.status == "REC UNREAD" {
+                    message.status = String::from("REC READ");
+                }
+            }
+
+            response.push_str("\r\n\r\nOK\r\n");
+            return response
+        }
+
+        error()
+    }
+}
+
+/// The PDU mode status number for a text mode status
+fn pdu_status(status: &str) -> u8 {
+    match status {
+        "REC UNREAD" => 0,
+        "REC READ" => 1,
+        "STO UNSENT" => 2,
+        _ => 3
+    }
+}
+
+/// Encodes a stored message as a PDU, returning the hex and the TPDU length
+///
+/// Received messages become an SMS-DELIVER and stored ones an SMS-SUBMIT, just like on the real modem.
+fn message_pdu(message: &SimulatedSms, service_centre: &str) -> Option<(String, usize)> {
+    if let Some(pdu) = &message.pdu {
+        let smsc_length = u8::from_str_radix(pdu.get(..2)?, 16).ok()? as usize;
+        return Some((pdu.clone(), pdu.len() / 2 - 1 - smsc_length))
+    }
+
+    let tpdu = if message.status.starts_with("REC") {
+        let (data_coding_scheme, user_data) = UserData::from_text(&message.content);
+        let timestamp = DateTime::parse_from_rfc3339(&timestamp_to_iso_8601(&message.timestamp).ok()?).ok()?;
+
+        Tpdu::Deliver(SmsDeliver {
+            more_messages_to_send: false,
+            reply_path: false,
+            status_report_indication: false,
+            originator: Address::new(&message.address),
+            protocol_identifier: 0,
+            data_coding_scheme: data_coding_scheme,
+            service_centre_timestamp: timestamp,
+            user_data: user_data
+        })
+    } else {
+        Tpdu::Submit(SmsSubmit::new(Address::new(&message.address), &message.content))
+    };
+
+    Pdu { smsc: Some(Address::new(service_centre)), tpdu: tpdu }.encode().ok()
+}
+
+fn ok() -> String {
+    String::from("\r\nOK\r\n")
+}
+
+fn error() -> String {
+    String::from("\r\nERROR\r\n")
+}
+
+fn info(line: &str) -> String {
+    format!("\r\n{}\r\n\r\nOK\r\n", line)
+}
+
+fn cme_error(code: u32) -> String {
+    format!("\r\n+CME ERROR: {}\r\n", code)
+}
+
+fn cms_error(code: u32) -> String {
+    format!("\r\n+CMS ERROR: {}\r\n", code)
+}
+
+/// Runs the simulator until the modem side of the pipe is dropped
+async fn run(mut stream: DuplexStream, state: Arc<Mutex<SimulatorState>>, mut urc_receiver: UnboundedReceiver<String>) {
+    let mut input: Vec<u8> = Vec::new();
+    let mut read_buf = vec![0; 1024];
+    // Argument of an `AT+CMGS` waiting on its message body
+    let mut pending_send: Option<String> = None;
+    // The response to a slow command and when it's due, nothing else is handled until then
+    let mut delayed: Option<(Instant, String)> = None;
+
+    loop {
+        let due = delayed.as_ref().map(|(due, _)| *due);
+
+        let mut output = tokio::select! {
+            read = stream.read(&mut read_buf) => {
+                match read {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => input.extend_from_slice(&read_buf[..n])
+                }
+                String::new()
+            }
+            Some(urc) = urc_receiver.recv() => urc,
+            _ = tokio::time::sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => delayed.take().unwrap().1
+        };
+
+        if delayed.is_none() {
+            output.push_str(&handle_input(&mut input, &mut pending_send, &mut delayed, &mut state.lock().unwrap()));
+        }
+
+        if !output.is_empty() && stream.write_all(output.as_bytes()).await.is_err() {
+            return
+        }
+    }
+}
+
+/// Handles every complete command (or message body) in the input, returning the output for them
+///
+/// Stops at a slow command, leaving the rest of the input for once its response has been sent.
+fn handle_input(input: &mut Vec<u8>, pending_send: &mut Option<String>, delayed: &mut Option<(Instant, String)>, state: &mut SimulatorState) -> String {
+    let mut output = String::new();
+
+    loop {
+        if let Some(argument) = pending_send.clone() {
+            // The body is terminated by Ctrl+Z, or abandoned with ESC
+            let Some(end) = input.iter().position(|b| *b == 0x1a || *b == 0x1b) else { break };
+            let body: Vec<u8> = input.drain(..=end).collect();
+            *pending_send = None;
+
+            if body[end] == 0x1b {
+                continue
+            }
+
+            output.push_str(&state.send(&argument, &body[..end]));
+        } else {
+            let Some(end) = input.iter().position(|b| *b == b'\r') else { break };
+            let line: Vec<u8> = input.drain(..=end).collect();
+            // ESC outside of a prompt is ignored, like the real modem does
+            let command = String::from_utf8_lossy(&line[..end]).trim().trim_start_matches('\x1b').to_string();
+
+            if command.is_empty() {
+                continue
+            }
+
+            if state.echo {
+                output.push_str(&format!("{}\r", command));
+            }
+            state.commands.push(command.clone());
+
+            let upper = command.to_uppercase();
+            if state.silent_prefixes.iter().any(|prefix| upper.starts_with(prefix)) {
+                continue
+            }
+
+            let response = if upper.starts_with("AT+CMGS=") {
+                *pending_send = Some(command[8..].to_string());
+                String::from("\r\n> ")
+            } else {
+                state.respond(&command)
+            };
+
+            if let Some((_, delay)) = state.slow_prefixes.iter().find(|(prefix, _)| upper.starts_with(prefix)) {
+                *delayed = Some((Instant::now() + *delay, response));
+                break
+            }
+            output.push_str(&response);
+        }
+    }
+
+    output
+}
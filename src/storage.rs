@@ -0,0 +1,151 @@
+use regex::Regex;
+
+use crate::{error::{Error, Result}, utils::hex_to_utf16_or_raw};
+
+/// Memory the modem keeps messages in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmsStorage {
+    /// The SIM card (`SM`)
+    Sim,
+    /// The modem's own memory (`ME`)
+    Phone,
+    /// The SIM and the modem's memory together (`MT`)
+    Any,
+    /// Cell broadcast messages (`BM`)
+    Broadcast,
+    /// Status reports (`SR`)
+    StatusReport
+}
+
+impl SmsStorage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SmsStorage::Sim => "SM",
+            SmsStorage::Phone => "ME",
+            SmsStorage::Any => "MT",
+            SmsStorage::Broadcast => "BM",
+            SmsStorage::StatusReport => "SR"
+        }
+    }
+
+    /// Parses a storage name as returned by the modem, either as-is or as UCS2 hex (ie. `0053004D`)
+    pub fn try_from_name(name: &str) -> Result<SmsStorage> {
+        match storage_name(name).as_str() {
+            "SM" => Ok(SmsStorage::Sim),
+            "ME" => Ok(SmsStorage::Phone),
+            "MT" => Ok(SmsStorage::Any),
+            "BM" => Ok(SmsStorage::Broadcast),
+            "SR" => Ok(SmsStorage::StatusReport),
+            _ => Err(Error::parse("Failed to parse SMS storage!", name))
+        }
+    }
+}
+
+/// How full a single storage is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub storage: SmsStorage,
+    pub used: u32,
+    pub total: u32
+}
+
+impl StorageUsage {
+    pub fn is_full(&self) -> bool {
+        self.used >= self.total
+    }
+}
+
+/// The storage used for each kind of operation, as set with `AT+CPMS`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreferredStorage {
+    /// Where messages are read and deleted from
+    pub read: StorageUsage,
+    /// Where messages are written and sent from
+    pub write: StorageUsage,
+    /// Where received messages are saved
+    pub receive: StorageUsage
+}
+
+impl PreferredStorage {
+    /// Takes the modem output of `AT+CPMS?`, which includes the name of each storage
+    pub fn from_cpms_query(raw_string: String) -> Result<PreferredStorage> {
+        let captures = Regex::new(r#"\+CPMS: "([0-9A-Z]+)",(\d+),(\d+),"([0-9A-Z]+)",(\d+),(\d+),"([0-9A-Z]+)",(\d+),(\d+)"#).unwrap()
+            .captures(&raw_string)
+            .ok_or_else(|| Error::parse("Failed to parse preferred storage!", &raw_string))?;
+
+        let mut usages = Vec::new();
+        for i in 0..3 {
+            let storage = SmsStorage::try_from_name(&captures[i * 3 + 1])?;
+            let used = captures[i * 3 + 2].parse::<u32>().map_err(|_| Error::parse("Failed to parse used storage!", &raw_string))?;
+            let total = captures[i * 3 + 3].parse::<u32>().map_err(|_| Error::parse("Failed to parse total storage!", &raw_string))?;
+
+            usages.push(StorageUsage { storage: storage, used: used, total: total });
+        }
+
+        Ok(PreferredStorage { read: usages[0], write: usages[1], receive: usages[2] })
+    }
+
+    /// Takes the modem output of `AT+CPMS=<read>,<write>,<receive>`, which only has the usage of each
+    pub fn from_cpms_set(raw_string: String, storages: [SmsStorage; 3]) -> Result<PreferredStorage> {
+        let captures = Regex::new(r"\+CPMS: (\d+),(\d+),(\d+),(\d+),(\d+),(\d+)").unwrap()
+            .captures(&raw_string)
+            .ok_or_else(|| Error::parse("Failed to parse preferred storage!", &raw_string))?;
+
+        let mut usages = Vec::new();
+        for (i, storage) in storages.into_iter().enumerate() {
+            let used = captures[i * 2 + 1].parse::<u32>().map_err(|_| Error::parse("Failed to parse used storage!", &raw_string))?;
+            let total = captures[i * 2 + 2].parse::<u32>().map_err(|_| Error::parse("Failed to parse total storage!", &raw_string))?;
+
+            usages.push(StorageUsage { storage: storage, used: used, total: total });
+        }
+
+        Ok(PreferredStorage { read: usages[0], write: usages[1], receive: usages[2] })
+    }
+}
+
+/// Which messages `AT+CMGD` deletes from the read storage, regardless of the index given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeleteFlag {
+    /// Every received message that has been read
+    Read,
+    /// Read messages, and outgoing messages that have been sent
+    ReadAndSent,
+    /// Read messages, and every outgoing message whether sent or not
+    ReadAndOutgoing,
+    /// Every message, including unread ones
+    All
+}
+
+impl From<DeleteFlag> for u8 {
+    fn from(flag: DeleteFlag) -> u8 {
+        match flag {
+            DeleteFlag::Read => 1,
+            DeleteFlag::ReadAndSent => 2,
+            DeleteFlag::ReadAndOutgoing => 3,
+            DeleteFlag::All => 4
+        }
+    }
+}
+
+/// What `GsmModem::spawn_storage_cleanup` deletes, and when
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CleanupPolicy {
+    /// Which messages to delete
+    ///
+    /// Anything less than `DeleteFlag::All` keeps unread messages, so storage can stay full if nothing reads them.
+    pub delete: DeleteFlag,
+    /// Also clean up once a new message leaves the receive storage with at least this many messages,
+    /// instead of waiting for it to fill up and drop messages
+    pub threshold: Option<u32>
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> Self {
+        CleanupPolicy { delete: DeleteFlag::ReadAndSent, threshold: None }
+    }
+}
+
+/// Normalizes a storage name that may be UCS2 hex (with `AT+CSCS="UCS2"` some modems encode it too)
+pub(crate) fn storage_name(name: &str) -> String {
+    hex_to_utf16_or_raw(name, |c| c.is_ascii_uppercase())
+}
@@ -0,0 +1,99 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncWrite, DuplexStream}, net::TcpStream};
+use tokio_serial::SerialPortBuilderExt;
+
+use crate::error::{Error, Result};
+
+/// Anything the modem can be talked to over
+///
+/// Implemented for every `AsyncRead + AsyncWrite` stream, so serial ports, ptys, sockets and in-memory pipes all work
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// A type erased transport, this is what `GsmModem` holds once connected
+pub type BoxedTransport = Box<dyn Transport>;
+
+/// Describes how to reach the modem, used by `GsmModem::open()` to create the connection
+pub enum TransportConfig {
+    /// A physical serial port (ie. `/dev/ttyS0`)
+    Serial {
+        path: String,
+        baud_rate: u32,
+        timeout: Duration
+    },
+
+    /// A Unix pseudo terminal, such as one end of a `socat` pty pair
+    Pty {
+        path: String
+    },
+
+    /// A TCP "serial-over-network" bridge like ser2net
+    Tcp {
+        address: String
+    },
+
+    /// One end of an in-memory pipe, the other end is handed to whatever is pretending to be the modem
+    InMemory(InMemoryTransport)
+}
+
+/// Holds the modem side of an in-memory pipe until the modem is opened
+///
+/// A pipe can't be recreated once dropped, so this transport can only be opened once.
+pub struct InMemoryTransport {
+    stream: Mutex<Option<DuplexStream>>
+}
+
+impl TransportConfig {
+    /// A physical serial port
+    pub fn serial(path: &str, baud_rate: u32, timeout: Duration) -> TransportConfig {
+        TransportConfig::Serial { path: String::from(path), baud_rate: baud_rate, timeout: timeout }
+    }
+
+    /// A Unix pseudo terminal
+    pub fn pty(path: &str) -> TransportConfig {
+        TransportConfig::Pty { path: String::from(path) }
+    }
+
+    /// A TCP bridge, the address is anything `TcpStream::connect` accepts (ie. `192.168.1.20:3000`)
+    pub fn tcp(address: &str) -> TransportConfig {
+        TransportConfig::Tcp { address: String::from(address) }
+    }
+
+    /// Creates an in-memory pipe, returning the config for the modem and the stream for the other end
+    pub fn in_memory(max_buf_size: usize) -> (TransportConfig, DuplexStream) {
+        let (modem_end, remote_end) = tokio::io::duplex(max_buf_size);
+        (TransportConfig::InMemory(InMemoryTransport { stream: Mutex::new(Some(modem_end)) }), remote_end)
+    }
+
+    /// Creates the connection described by the config
+    pub async fn connect(&self) -> Result<BoxedTransport> {
+        match self {
+            TransportConfig::Serial { path, baud_rate, timeout } => {
+                let mut port = tokio_serial::new(path, *baud_rate).timeout(*timeout).open_native_async()?;
+                port.set_exclusive(false)?;
+
+                Ok(Box::new(port))
+            }
+            TransportConfig::Pty { path } => {
+                // Ptys ignore the baud rate, but the serial builder still wants one
+                let mut port = tokio_serial::new(path, 115_200).open_native_async()?;
+                port.set_exclusive(false)?;
+
+                Ok(Box::new(port))
+            }
+            TransportConfig::Tcp { address } => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+
+                Ok(Box::new(stream))
+            }
+            TransportConfig::InMemory(transport) => {
+                let stream = transport.stream.lock().unwrap().take().ok_or_else(|| Error::invalid_argument("In-memory transport has already been opened!"))?;
+
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
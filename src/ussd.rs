@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::{error::{Error, Result}, events::ModemEvent, gsm7, gsm_modem::GsmModem, utils::{hex_to_bytes, hex_to_utf16, utf16_to_hex}};
+
+/// How long to wait for the network to answer a USSD request
+///
+/// Networks usually answer within a few seconds, but drop menu sessions left waiting much longer than this.
+pub const DEFAULT_USSD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Data coding scheme for GSM 7-bit USSD strings with no language given
+const USSD_DCS_GSM7: u8 = 15;
+
+/// What the network expects after a `+CUSD`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UssdStatus {
+    /// The session is over, nothing more to do
+    Done,
+    /// The network is waiting on a reply (ie. a menu choice)
+    ActionRequired,
+    /// The network ended the session
+    Terminated,
+    /// Another client on the modem answered
+    OtherClient,
+    /// The network doesn't support the request
+    NotSupported,
+    /// The network gave up waiting on a reply
+    Timeout
+}
+
+impl UssdStatus {
+    pub fn from_code(code: u8) -> Option<UssdStatus> {
+        match code {
+            0 => Some(UssdStatus::Done),
+            1 => Some(UssdStatus::ActionRequired),
+            2 => Some(UssdStatus::Terminated),
+            3 => Some(UssdStatus::OtherClient),
+            4 => Some(UssdStatus::NotSupported),
+            5 => Some(UssdStatus::Timeout),
+            _ => None
+        }
+    }
+}
+
+impl From<UssdStatus> for u8 {
+    fn from(status: UssdStatus) -> u8 {
+        match status {
+            UssdStatus::Done => 0,
+            UssdStatus::ActionRequired => 1,
+            UssdStatus::Terminated => 2,
+            UssdStatus::OtherClient => 3,
+            UssdStatus::NotSupported => 4,
+            UssdStatus::Timeout => 5
+        }
+    }
+}
+
+/// A USSD message from the network, either answering a request or started by the network
+#[derive(Clone, Debug, PartialEq)]
+pub struct UssdResponse {
+    pub status: UssdStatus,
+    /// The text, `None` if the network only sent a status
+    pub message: Option<String>,
+    pub data_coding_scheme: Option<u8>
+}
+
+impl UssdResponse {
+    /// Builds a response from the parts of a `+CUSD`, decoding the text as the modem sent it
+    ///
+    /// With `AT+CSCS="UCS2"` the text is UCS2 hex, but some modems pass GSM 7-bit text through as packed
+    /// septets in hex instead, so those are unpacked when the coding scheme is GSM 7-bit. Text that's
+    /// neither is kept as the modem sent it.
+    pub fn from_cusd(status: u8, message: Option<&str>, data_coding_scheme: Option<u8>) -> Option<UssdResponse> {
+        let message = message.map(|message| decode_ussd(message, data_coding_scheme));
+
+        Some(UssdResponse { status: UssdStatus::from_code(status)?, message: message, data_coding_scheme: data_coding_scheme })
+    }
+
+    /// Whether the network is waiting on a reply
+    pub fn expects_reply(&self) -> bool {
+        self.status == UssdStatus::ActionRequired
+    }
+}
+
+fn decode_ussd(message: &str, data_coding_scheme: Option<u8>) -> String {
+    let ucs2 = hex_to_utf16(message).ok();
+    let gsm7 = data_coding_scheme.is_some_and(is_gsm7_coding);
+
+    match ucs2 {
+        // GSM 7-bit text converted to UCS2 by the modem only has characters from the default alphabet
+        Some(text) if !gsm7 || gsm7::encode(&text).is_some() => text,
+        _ if gsm7 => match hex_to_bytes(message) {
+            Ok(packed) => {
+                let septets = gsm7::unpack_septets(&packed, packed.len() * 8 / 7, 0);
+                // Padding a whole octet leaves a carriage return as the last septet
+                String::from(gsm7::decode(&septets).trim_end_matches('\r'))
+            }
+            Err(_) => String::from(message)
+        },
+        _ => String::from(message)
+    }
+}
+
+/// Whether a USSD data coding scheme is GSM 7-bit, see 3GPP TS 23.038 section 5
+fn is_gsm7_coding(dcs: u8) -> bool {
+    match dcs >> 4 {
+        0x00 | 0x02 | 0x03 => true,
+        // Language indicated by the first characters of the message
+        0x01 => dcs & 0x0F == 0x00,
+        0x04..=0x07 => dcs & 0x0C == 0x00,
+        0x0F => dcs & 0x04 == 0x00,
+        _ => false
+    }
+}
+
+/// An interactive USSD session, started with `GsmModem::start_ussd` (or `GsmModem::ussd_session` for one the network started)
+///
+/// Only one session can be open on a modem at a time, the network ends the older one.
+pub struct UssdSession {
+    modem: GsmModem,
+    events: broadcast::Receiver<ModemEvent>,
+    timeout: Duration,
+    response: UssdResponse
+}
+
+impl UssdSession {
+    pub(crate) async fn start(modem: &GsmModem, request: &str, timeout: Duration) -> Result<UssdSession> {
+        // Subscribe before sending, the answer can come before the OK
+        let mut events = modem.subscribe();
+        let response = exchange(modem, &mut events, request, timeout).await?;
+
+        Ok(UssdSession { modem: modem.clone(), events: events, timeout: timeout, response: response })
+    }
+
+    pub(crate) fn resume(modem: &GsmModem, response: UssdResponse, timeout: Duration) -> UssdSession {
+        UssdSession { modem: modem.clone(), events: modem.subscribe(), timeout: timeout, response: response }
+    }
+
+    /// The latest message from the network
+    pub fn response(&self) -> &UssdResponse {
+        &self.response
+    }
+
+    /// Whether the network is waiting on a reply
+    pub fn is_open(&self) -> bool {
+        self.response.expects_reply()
+    }
+
+    /// Replies to the latest message (ie. with a menu choice), returning the network's answer
+    pub async fn reply(&mut self, reply: &str) -> Result<&UssdResponse> {
+        if !self.is_open() {
+            return Err(Error::invalid_argument("USSD session is no longer open!"))
+        }
+
+        self.response = exchange(&self.modem, &mut self.events, reply, self.timeout).await?;
+
+        Ok(&self.response)
+    }
+
+    /// Ends the session, if the network hasn't already
+    pub async fn cancel(self) -> Result<()> {
+        if self.is_open() {
+            self.modem.cancel_ussd().await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends a request or reply and waits for the network's answer, cancelling the session if it never comes
+///
+/// Falling behind on events fails with `Error::EventsMissed` without cancelling, since the answer may have been lost.
+async fn exchange(modem: &GsmModem, events: &mut broadcast::Receiver<ModemEvent>, text: &str, timeout: Duration) -> Result<UssdResponse> {
+    // Skips anything already queued (ie. a +CUSD the network sent on its own), only what comes after this is the answer
+    *events = events.resubscribe();
+
+    let command = format!("AT+CUSD=1,\"{}\",{}\r", utf16_to_hex(text), USSD_DCS_GSM7);
+    modem.write_data(command.clone()).await?;
+
+    let answer = tokio::time::timeout(timeout, async {
+        loop {
+            match events.recv().await {
+                Ok(ModemEvent::Ussd(response)) => return Ok(response),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => return Err(Error::EventsMissed(missed)),
+                Err(broadcast::error::RecvError::Closed) => return Err(Error::ConnectionClosed)
+            }
+        }
+    }).await;
+
+    match answer {
+        Ok(answer) => answer,
+        Err(_) => {
+            // Best effort, the network may already have dropped the session
+            let _ = modem.cancel_ussd().await;
+            Err(Error::Timeout { command: String::from(command.trim_end()), timeout: timeout })
+        }
+    }
+}
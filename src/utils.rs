@@ -1,8 +1,13 @@
-use std::error::Error;
-
+use chrono::{DateTime, FixedOffset};
 use regex::Regex;
 
+use crate::error::{Error, Result};
+
 pub fn is_valid_imei(intended_imei: &String) -> bool {
+    if intended_imei.len() != 15 || !intended_imei.chars().all(|c| c.is_ascii_digit()) {
+        return false
+    }
+
     let numbers = intended_imei.split("").collect::<Vec<&str>>();
     let check_digit = numbers[numbers.len()-2].parse::<i32>().expect("Failed to convert string to int!");
 
@@ -24,12 +29,12 @@ pub fn is_valid_imei(intended_imei: &String) -> bool {
     check_digit == (10 - (sum % 10)) % 10
 }
 
-// Convert a hex string to u16 bytes
-fn hex_to_bytes(s: &str) -> Option<Vec<u16>> {
-    if s.len() % 2 == 0 {
+// Convert a hex string to UTF-16 code units, each unit is 4 hex characters
+fn hex_to_units(s: &str) -> Option<Vec<u16>> {
+    if s.len().is_multiple_of(4) {
         (0..s.len())
-            .step_by(2)
-            .map(|i| s.get(i..i + 2)
+            .step_by(4)
+            .map(|i| s.get(i..i + 4)
                       .and_then(|sub| u16::from_str_radix(sub, 16).ok()))
             .collect()
     } else {
@@ -37,34 +42,36 @@ fn hex_to_bytes(s: &str) -> Option<Vec<u16>> {
     }
 }
 
-pub fn hex_to_utf16(hex: &str) -> Result<String, Box<dyn Error>> {
-    if let Some(hex_vec) = hex_to_bytes(hex) {
-        return Ok(String::from_utf16(hex_vec.as_slice())?)
-    } else {
-        return Err("Failed to parse hex to UTF16".into())
-    };
+pub fn hex_to_utf16(hex: &str) -> Result<String> {
+    let units = hex_to_units(hex).ok_or_else(|| Error::parse("Failed to parse hex to UTF16", hex))?;
+
+    String::from_utf16(units.as_slice()).map_err(|_| Error::parse("Failed to parse hex to UTF16", hex))
 }
 
 /// Converts the GSM given timestamp format to ISO 8601
-pub fn timestamp_to_iso_8601(timestamp: &str) -> Result<String, Box<dyn Error>> {
+pub fn timestamp_to_iso_8601(timestamp: &str) -> Result<String> {
     // Extracts each component via regex and indivdually pull them out, probably a more efficent way to do this
-    let timestamp_re = Regex::new(r"(\d{2})/(\d{2})/(\d{2}),(\d{2}):(\d{2}):(\d{2})((?:-|\+)\d{0,3})?")?;
+    let timestamp_re = Regex::new(r"(\d{2})/(\d{2})/(\d{2}),(\d{2}):(\d{2}):(\d{2})((?:-|\+)\d{0,3})?").unwrap();
 
-    let captures = timestamp_re.captures(&timestamp).ok_or_else(|| Err::<String, Box<dyn Error>>("Failed to parse timezone!".into()).unwrap())?;
+    let captures = timestamp_re.captures(timestamp).ok_or_else(|| Error::parse("Failed to parse timestamp!", timestamp))?;
 
-    let year = captures.get(1).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse year value!").unwrap())?.as_str();
+    let year = captures.get(1).ok_or_else(|| Error::parse("Failed to parse year value!", timestamp))?.as_str();
 
-    let month = captures.get(2).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse month value!").unwrap())?.as_str();
+    let month = captures.get(2).ok_or_else(|| Error::parse("Failed to parse month value!", timestamp))?.as_str();
 
-    let day = captures.get(3).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse day value!").unwrap())?.as_str();
+    let day = captures.get(3).ok_or_else(|| Error::parse("Failed to parse day value!", timestamp))?.as_str();
 
-    let hour = captures.get(4).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse hour value!").unwrap())?.as_str();
+    let hour = captures.get(4).ok_or_else(|| Error::parse("Failed to parse hour value!", timestamp))?.as_str();
 
-    let min = captures.get(5).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse minute value!").unwrap())?.as_str();
+    let min = captures.get(5).ok_or_else(|| Error::parse("Failed to parse minute value!", timestamp))?.as_str();
 
-    let sec = captures.get(6).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse second value!").unwrap())?.as_str();
+    let sec = captures.get(6).ok_or_else(|| Error::parse("Failed to parse second value!", timestamp))?.as_str();
 
-    let tz = captures.get(7).ok_or_else(|| Err::<Box<dyn std::error::Error>, &str>("Failed to parse timezone value!").unwrap())?.as_str().parse::<f32>()?/4_f32;
+    let tz = captures.get(7)
+        .ok_or_else(|| Error::parse("Failed to parse timezone value!", timestamp))?
+        .as_str()
+        .parse::<f32>()
+        .map_err(|_| Error::parse("Failed to parse timezone value!", timestamp))?/4_f32;
 
     let sign: char;
 
@@ -74,11 +81,87 @@ pub fn timestamp_to_iso_8601(timestamp: &str) -> Result<String, Box<dyn Error>>
         sign = '+';
     }
 
-    let converted_tz = format!("{}{:02}:{:02}", sign, tz.abs().trunc(), tz.fract() * 60_f32);
+    let converted_tz = format!("{}{:02}:{:02}", sign, tz.abs().trunc(), tz.abs().fract() * 60_f32);
 
     // I hate the year format that comes from the modem (`25`) but whatever
     let converted_stamp = format!("20{}-{}-{}T{}:{}:{}{}", year, month, day, hour, min, sec, converted_tz);
 
     Ok(converted_stamp)
 
-}
\ No newline at end of file
+}
+
+/// Converts a timestamp to the GSM format (ie. `25/06/01,12:00:00-16`), the reverse of `timestamp_to_iso_8601`
+pub fn datetime_to_timestamp(timestamp: &DateTime<FixedOffset>) -> String {
+    format!("{}{:+03}", timestamp.format("%y/%m/%d,%H:%M:%S"), timestamp.offset().local_minus_utc() / (15 * 60))
+}
+
+/// Convert a string into the UTF-16 hex representation the modem uses in UCS2 mode
+pub fn utf16_to_hex(content: &str) -> String {
+    content.encode_utf16().map(|unit| format!("{:04X}", unit)).collect()
+}
+
+/// Decodes a field that's UCS2 hex with `AT+CSCS="UCS2"`, but that some modems send as it is
+///
+/// Plain text made of digits is valid hex too, so the decoded text is only used if every character is `allowed`.
+pub(crate) fn hex_to_utf16_or_raw(raw: &str, allowed: impl Fn(char) -> bool) -> String {
+    match hex_to_utf16(raw) {
+        Ok(decoded) if !decoded.is_empty() && decoded.chars().all(allowed) => decoded,
+        _ => String::from(raw)
+    }
+}
+
+/// Decodes a number field and its type-of-address, adding the `+` of international numbers (type `145`)
+pub(crate) fn decode_number(raw: &str, type_of_address: Option<&str>) -> String {
+    let number = hex_to_utf16_or_raw(raw, |c| c.is_ascii_digit() || c == '+');
+
+    match type_of_address {
+        Some("145") if !number.starts_with('+') => format!("+{}", number),
+        _ => number
+    }
+}
+
+/// Convert a hex string (ie. a PDU) into bytes
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(Error::parse("Hex string has an odd length", hex))
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2)
+                    .and_then(|sub| u8::from_str_radix(sub, 16).ok())
+                    .ok_or_else(|| Error::parse("Failed to parse hex to bytes", hex)))
+        .collect()
+}
+
+/// Convert bytes into the uppercase hex the modem expects
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Escapes a field of a tab-separated record, so it can't break the record across lines
+pub(crate) fn escape_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+pub(crate) fn unescape_field(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\')
+        }
+    }
+
+    unescaped
+}
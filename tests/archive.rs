@@ -0,0 +1,185 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use chrono::{DateTime, Duration, FixedOffset, TimeZone};
+use async_modem::{archive::{Direction, MessageArchive, MessageQuery}, constants::SmsMessage, delivery::SentMessage, utils::utf16_to_hex};
+
+/// A fresh archive path for each test, so tests can run in parallel
+fn archive_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("async-modem-{}-{}.archive", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    path
+}
+
+fn at(hour: u32) -> DateTime<FixedOffset> {
+    FixedOffset::west_opt(4 * 3600).unwrap().with_ymd_and_hms(2025, 6, 1, hour, 30, 45).unwrap()
+}
+
+fn received(status: &str, address: &str, content: &str, hour: u32) -> SmsMessage {
+    let raw = format!(
+        "+CMGL: 1,\"{}\",\"{}\",\"\",\"25/06/01,{:02}:30:45-16\"\r\n{}\r\n",
+        status, utf16_to_hex(address), hour, utf16_to_hex(content)
+    );
+
+    SmsMessage::from_cmgl(raw).unwrap().remove(0)
+}
+
+/// An outgoing message as read from storage, which has no timestamp
+fn stored_sent(address: &str, content: &str) -> SmsMessage {
+    let raw = format!("+CMGL: 1,\"STO SENT\",\"{}\",\"\",\r\n{}\r\n", utf16_to_hex(address), utf16_to_hex(content));
+
+    SmsMessage::from_cmgl(raw).unwrap().remove(0)
+}
+
+#[test]
+fn deduplicates_and_persists() {
+    let path = archive_path("persists");
+    let archive = MessageArchive::open(&path).unwrap();
+
+    let message = received("REC UNREAD", "+13155550123", "Hello\twith a tab", 12);
+    assert_eq!(archive.record(&message).unwrap(), Some(1));
+    assert_eq!(archive.record(&message).unwrap(), None);
+    let id = archive.record(&received("REC READ", "ACME", "Line one\nline two", 13)).unwrap().unwrap();
+    assert!(archive.mark_read(1).unwrap());
+    assert!(!archive.mark_read(1).unwrap());
+    drop(archive);
+
+    let archive = MessageArchive::open(&path).unwrap();
+    assert_eq!(archive.len(), 2);
+    assert_eq!(archive.record(&message).unwrap(), None);
+    let messages = archive.query(&MessageQuery::default());
+    assert_eq!(messages[0].content, "Hello\twith a tab");
+    assert_eq!(messages[0].timestamp, at(12));
+    assert!(messages[0].read);
+    assert_eq!(messages[1].content, "Line one\nline two");
+    assert_eq!(archive.record(&received("REC UNREAD", "ACME", "New", 14)).unwrap(), Some(id + 1));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn drops_a_partly_written_record() {
+    let path = archive_path("partial");
+    let archive = MessageArchive::open(&path).unwrap();
+    archive.record(&received("REC UNREAD", "+13155550123", "Kept", 12)).unwrap();
+    drop(archive);
+
+    fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"M\t2\tR\t0\t2025-06").unwrap();
+
+    let archive = MessageArchive::open(&path).unwrap();
+    assert_eq!(archive.len(), 1);
+    archive.record(&received("REC UNREAD", "+13155550123", "Appended", 13)).unwrap();
+    drop(archive);
+
+    assert_eq!(MessageArchive::open(&path).unwrap().len(), 2);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn refuses_other_files() {
+    let path = archive_path("other");
+    fs::write(&path, "not an archive\n").unwrap();
+
+    assert!(MessageArchive::open(&path).is_err());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn groups_conversations_by_number() {
+    let path = archive_path("conversations");
+    let archive = MessageArchive::open(&path).unwrap();
+
+    archive.record(&received("REC UNREAD", "+13155550123", "First", 10)).unwrap();
+    let mut sent = SentMessage::new("13155550123", "Reply", vec![1]);
+    sent.sent_at = at(11).to_utc();
+    archive.record_sent(&sent).unwrap();
+    archive.record(&received("REC UNREAD", "+13155550123", "Second", 12)).unwrap();
+    archive.record(&received("REC UNREAD", "+447700900123", "Other", 11)).unwrap();
+
+    let conversations = archive.conversations();
+    assert_eq!(conversations.len(), 2);
+    assert_eq!(conversations[0].contact, "+13155550123");
+    assert_eq!(conversations[0].message_count, 3);
+    assert_eq!(conversations[0].unread_count, 2);
+    assert_eq!(conversations[0].last_message.content, "Second");
+    assert_eq!(conversations[1].contact, "+447700900123");
+
+    let conversation = archive.conversation("+1 (315) 555-0123");
+    let contents: Vec<&str> = conversation.iter().map(|message| message.content.as_str()).collect();
+    assert_eq!(contents, vec!["First", "Reply", "Second"]);
+    assert_eq!(conversation[1].direction, Direction::Sent);
+
+    assert_eq!(archive.mark_conversation_read("+13155550123").unwrap(), 2);
+    assert_eq!(archive.unread().len(), 1);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn groups_national_and_international_numbers() {
+    let path = archive_path("national");
+    let archive = MessageArchive::open(&path).unwrap();
+
+    archive.record(&received("REC UNREAD", "07700900123", "National", 10)).unwrap();
+    archive.record(&received("REC UNREAD", "+447700900123", "International", 11)).unwrap();
+    archive.record(&received("REC UNREAD", "0900123", "Too short to tell", 12)).unwrap();
+
+    let conversations = archive.conversations();
+    assert_eq!(conversations.len(), 2);
+    assert_eq!(conversations[1].contact, "+447700900123");
+    assert_eq!(conversations[1].message_count, 2);
+    assert_eq!(archive.conversation("07700900123").len(), 2);
+    drop(archive);
+
+    let archive = MessageArchive::open(&path).unwrap();
+    assert_eq!(archive.conversation("+44 7700 900123").len(), 2);
+    assert_eq!(archive.record(&received("REC UNREAD", "07700900123", "National", 10)).unwrap(), None);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn deduplicates_stored_messages_without_a_timestamp() {
+    let path = archive_path("untimed");
+    let archive = MessageArchive::open(&path).unwrap();
+
+    let sent = SentMessage::new("+13155550123", "Sent earlier", vec![1]);
+    archive.record_sent(&sent).unwrap();
+    assert_eq!(archive.record(&stored_sent("+13155550123", "Sent earlier")).unwrap(), None);
+
+    let id = archive.record(&stored_sent("+13155550123", "Only stored")).unwrap();
+    assert!(id.is_some());
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    assert_eq!(archive.record(&stored_sent("+13155550123", "Only stored")).unwrap(), None);
+    drop(archive);
+
+    let archive = MessageArchive::open(&path).unwrap();
+    assert_eq!(archive.record(&stored_sent("13155550123", "Only stored")).unwrap(), None);
+    assert_eq!(archive.len(), 2);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn queries_by_date_and_status() {
+    let path = archive_path("query");
+    let archive = MessageArchive::open(&path).unwrap();
+
+    for hour in 9..15 {
+        let status = if hour % 2 == 0 { "REC READ" } else { "REC UNREAD" };
+        archive.record(&received(status, "+13155550123", &format!("At {}", hour), hour)).unwrap();
+    }
+
+    let query = MessageQuery { since: Some(at(10)), until: Some(at(13)), ..Default::default() };
+    let contents: Vec<String> = archive.query(&query).into_iter().map(|message| message.content).collect();
+    assert_eq!(contents, vec!["At 10", "At 11", "At 12"]);
+
+    let query = MessageQuery { since: Some(at(10) - Duration::minutes(1)), unread_only: true, ..Default::default() };
+    let contents: Vec<String> = archive.query(&query).into_iter().map(|message| message.content).collect();
+    assert_eq!(contents, vec!["At 11", "At 13"]);
+
+    let query = MessageQuery { direction: Some(Direction::Sent), ..Default::default() };
+    assert!(archive.query(&query).is_empty());
+
+    fs::remove_file(path).unwrap();
+}
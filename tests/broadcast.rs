@@ -0,0 +1,132 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::sync::broadcast::Receiver;
+use async_modem::{broadcast::{BroadcastAssembler, BroadcastChannels, CellBroadcast, CellBroadcastPage, GeographicalScope, PublicWarning, SerialNumber}, constants::SmsFormat, events::ModemEvent, simulator::SimulatedModem, utils::bytes_to_hex};
+use common::start;
+
+fn page(message_identifier: u16, page: u8, pages: u8, content: &str) -> CellBroadcastPage {
+    CellBroadcastPage {
+        serial_number: SerialNumber(0x3002),
+        message_identifier: message_identifier,
+        data_coding_scheme: 0x01,
+        page: page,
+        pages: pages,
+        language: Some(String::from("en")),
+        content: String::from(content)
+    }
+}
+
+/// Skips events until the next complete broadcast
+async fn next_broadcast(events: &mut Receiver<ModemEvent>) -> CellBroadcast {
+    tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let ModemEvent::CellBroadcast(broadcast) = events.recv().await.unwrap() {
+                return broadcast
+            }
+        }
+    }).await.expect("No broadcast arrived")
+}
+
+#[test]
+fn round_trips_gsm7_pages() {
+    let original = page(4370, 2, 3, "Presidential Alert: this is a test");
+    let pdu = original.encode().unwrap();
+    assert_eq!(pdu.len(), 176);
+    assert!(pdu.starts_with("3002111201"));
+    assert_eq!(&pdu[10..12], "23");
+
+    let decoded = CellBroadcastPage::decode(&pdu).unwrap();
+    assert_eq!(decoded, original);
+    assert_eq!(decoded.serial_number.geographical_scope(), GeographicalScope::CellImmediate);
+    assert_eq!(decoded.serial_number.message_code(), 0x300);
+    assert_eq!(decoded.serial_number.update_number(), 2);
+
+    assert!(page(4370, 1, 1, &"a".repeat(94)).encode().is_err());
+}
+
+#[test]
+fn decodes_ucs2_with_language_in_content() {
+    // "en" packed into two octets, then UCS2 padded with carriage returns
+    let mut octets = vec![0x40, 0x10, 0x11, 0x12, 0x11, 0x11, 0x65, 0x37];
+    octets.extend("Évacuez ⚠".encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+    while octets.len() < 88 {
+        octets.extend([0x00, 0x0D]);
+    }
+
+    let page = CellBroadcastPage::decode(&bytes_to_hex(&octets)).unwrap();
+    assert_eq!(page.language.as_deref(), Some("en"));
+    assert_eq!(page.content, "Évacuez ⚠");
+    assert_eq!((page.page, page.pages), (1, 1));
+    assert_eq!(page.serial_number.geographical_scope(), GeographicalScope::Plmn);
+}
+
+#[test]
+fn classifies_public_warnings() {
+    let warning = |identifier| CellBroadcast::from_page(page(identifier, 1, 1, "")).public_warning();
+
+    assert_eq!(warning(4352), Some(PublicWarning::Etws));
+    assert_eq!(warning(4370), Some(PublicWarning::Presidential));
+    assert_eq!(warning(4383), Some(PublicWarning::Presidential));
+    assert_eq!(warning(4386), Some(PublicWarning::Severe));
+    assert_eq!(warning(4392), Some(PublicWarning::Amber));
+    assert_eq!(warning(4380), Some(PublicWarning::Test));
+    assert_eq!(warning(4396), Some(PublicWarning::Other));
+    assert_eq!(warning(50), None);
+}
+
+#[test]
+fn assembles_pages_in_any_order() {
+    let mut assembler = BroadcastAssembler::new();
+
+    assert_eq!(assembler.push(page(4371, 2, 2, " second")), None);
+    assert_eq!(assembler.push(page(4371, 2, 2, " second")), None);
+    let broadcast = assembler.push(page(4371, 1, 2, "First")).unwrap();
+    assert_eq!(broadcast.content, "First second");
+    assert_eq!(broadcast.pages, 2);
+    assert_eq!(broadcast.public_warning(), Some(PublicWarning::Extreme));
+}
+
+#[tokio::test]
+async fn receives_multipage_broadcasts() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    sim.broadcast(&page(4370, 1, 2, "Take shelter "));
+    sim.broadcast(&page(4370, 2, 2, "now."));
+
+    let ModemEvent::CellBroadcastPage(first) = events.recv().await.unwrap() else { panic!("Expected a broadcast page") };
+    assert_eq!(first.page, 1);
+    let broadcast = next_broadcast(&mut events).await;
+    assert_eq!(broadcast.content, "Take shelter now.");
+    assert_eq!(broadcast.language.as_deref(), Some("en"));
+    assert_eq!(broadcast.public_warning(), Some(PublicWarning::Presidential));
+
+    modem.set_sms_format(SmsFormat::Text).await.unwrap();
+    sim.broadcast(&page(50, 1, 1, "Cell 1234"));
+    let broadcast = next_broadcast(&mut events).await;
+    assert_eq!((broadcast.message_identifier, broadcast.content.as_str()), (50, "Cell 1234"));
+    assert_eq!(broadcast.language.as_deref(), Some("en"));
+
+    sim.inject_urc("\r\n+CBMI: \"BM\",4\r\n");
+    let event = events.recv().await.unwrap();
+    assert_eq!(event, ModemEvent::CellBroadcastStored { storage: String::from("BM"), index: 4 });
+}
+
+#[tokio::test]
+async fn configures_broadcast_channels() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    let channels = BroadcastChannels { languages: vec![0..=5, 15..=15], ..BroadcastChannels::public_warnings() };
+    assert_eq!(channels.to_lists(), (String::from("4352-6399"), String::from("0-5,15")));
+    modem.set_broadcast_channels(&channels).await.unwrap();
+    assert!(sim.received_commands().iter().any(|command| command.starts_with("AT+CSCB=0,\"")));
+    assert_eq!(modem.get_broadcast_channels().await.unwrap(), channels);
+
+    let plain = BroadcastChannels::from_cscb(String::from("+CSCB: 1,\"50,4370-4383\",\"\"")).unwrap();
+    assert!(plain.reject);
+    assert_eq!(plain.message_identifiers, vec![50..=50, 4370..=4383]);
+    assert!(plain.languages.is_empty());
+}
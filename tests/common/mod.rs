@@ -0,0 +1,47 @@
+//! Fixtures shared by the integration tests, each test file uses only some of them
+#![allow(dead_code)]
+
+use chrono::{FixedOffset, TimeZone};
+use async_modem::{gsm_modem::GsmModem, pdu::{Address, ApplicationPort, ConcatReference, Pdu, SmsDeliver, SmsSubmit, Tpdu}, simulator::{SimulatedModem, SimulatorHandle}};
+
+/// Starts the simulator and opens a modem on it
+pub async fn start(simulator: SimulatedModem) -> (GsmModem, SimulatorHandle) {
+    let (transport, handle) = simulator.start();
+    let modem = GsmModem::new(transport);
+    modem.open().await.unwrap();
+
+    (modem, handle)
+}
+
+/// Turns an outgoing message part into the part the recipient would receive
+pub fn deliver(submit: SmsSubmit) -> SmsDeliver {
+    SmsDeliver {
+        more_messages_to_send: false,
+        reply_path: false,
+        status_report_indication: false,
+        originator: submit.destination,
+        protocol_identifier: 0,
+        data_coding_scheme: submit.data_coding_scheme,
+        service_centre_timestamp: FixedOffset::east_opt(0).unwrap().with_ymd_and_hms(2025, 6, 1, 12, 30, 45).unwrap(),
+        user_data: submit.user_data
+    }
+}
+
+/// Every part of a message from +13155550123, as the recipient would receive them
+pub fn deliver_segments(content: &str, reference: ConcatReference) -> Vec<SmsDeliver> {
+    SmsSubmit::segments(Address::new("+13155550123"), content, reference).unwrap().into_iter().map(deliver).collect()
+}
+
+/// Hex PDUs of every part of a message from +13155550123, as the recipient would receive them
+pub fn deliver_parts(content: &str, reference: ConcatReference) -> Vec<String> {
+    deliver_segments(content, reference).into_iter().map(encode).collect()
+}
+
+/// Hex PDUs of every part of a data message, as the recipient would receive them
+pub fn deliver_data_parts(originator: &str, port: ApplicationPort, data: &[u8], reference: ConcatReference) -> Vec<String> {
+    SmsSubmit::data_segments(Address::new(originator), port, data, reference).unwrap().into_iter().map(|submit| encode(deliver(submit))).collect()
+}
+
+fn encode(deliver: SmsDeliver) -> String {
+    Pdu { smsc: None, tpdu: Tpdu::Deliver(deliver) }.encode().unwrap().0
+}
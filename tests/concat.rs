@@ -0,0 +1,130 @@
+mod common;
+
+use std::time::Duration;
+
+use async_modem::{concat::Reassembler, gsm7::{Encoding, ShiftTables}, pdu::{Address, ConcatReference, Concatenation, MessageLength, SmsSubmit}};
+use common::deliver_segments;
+
+fn part_lengths(content: &str, reference: ConcatReference) -> Vec<usize> {
+    SmsSubmit::segments(Address::new("5551234"), content, reference).unwrap()
+        .iter()
+        .map(|submit| submit.user_data.text().unwrap().chars().count())
+        .collect()
+}
+
+#[test]
+fn short_messages_are_not_split() {
+    let segments = SmsSubmit::segments(Address::new("5551234"), &"a".repeat(160), ConcatReference::EightBit(1)).unwrap();
+
+    assert_eq!(segments.len(), 1);
+    assert!(segments[0].user_data.header.is_none());
+}
+
+#[test]
+fn splits_with_concatenation_headers() {
+    assert_eq!(part_lengths(&"a".repeat(200), ConcatReference::EightBit(1)), vec![153, 47]);
+    assert_eq!(part_lengths(&"a".repeat(200), ConcatReference::SixteenBit(1)), vec![152, 48]);
+
+    let segments = SmsSubmit::segments(Address::new("5551234"), &"a".repeat(400), ConcatReference::SixteenBit(0x1234)).unwrap();
+    let concatenations: Vec<Concatenation> = segments.iter().map(|s| s.user_data.header.as_ref().unwrap().concatenation().unwrap()).collect();
+
+    assert_eq!(concatenations, (1..=3).map(|sequence| Concatenation { reference: ConcatReference::SixteenBit(0x1234), total: 3, sequence: sequence }).collect::<Vec<_>>());
+}
+
+#[test]
+fn never_splits_a_character() {
+    // Escaped characters take two septets
+    assert_eq!(part_lengths(&"{".repeat(100), ConcatReference::EightBit(1)), vec![76, 24]);
+    // Surrogate pairs take two UTF-16 units
+    assert_eq!(part_lengths(&"👋".repeat(40), ConcatReference::EightBit(1)), vec![33, 7]);
+}
+
+#[test]
+fn reassembles_parts_in_any_order() {
+    let content = format!("{} the end", "Long message ".repeat(30));
+    let parts = deliver_segments(&content, ConcatReference::EightBit(7));
+    assert_eq!(parts.len(), 3);
+
+    let mut reassembler = Reassembler::default();
+    assert!(reassembler.push(&parts[2], 12).is_none());
+    assert!(reassembler.push(&parts[0], 10).is_none());
+
+    let pending = reassembler.pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].missing_parts(), vec![2]);
+    assert_eq!(pending[0].received_parts(), vec![1, 3]);
+
+    let message = reassembler.push(&parts[1], 11).unwrap();
+    assert_eq!(message.content(), content);
+    assert_eq!(message.memory_index(), 10);
+    assert_eq!(message.memory_indices(), vec![10, 11, 12]);
+    assert!(reassembler.pending().is_empty());
+}
+
+#[test]
+fn keeps_messages_with_different_references_apart() {
+    let first = deliver_segments(&"a".repeat(200), ConcatReference::EightBit(1));
+    let second = deliver_segments(&"b".repeat(200), ConcatReference::EightBit(2));
+
+    let mut reassembler = Reassembler::default();
+    assert!(reassembler.push(&first[0], 0).is_none());
+    assert!(reassembler.push(&second[1], 1).is_none());
+    assert_eq!(reassembler.push(&second[0], 2).unwrap().content(), "b".repeat(200));
+    assert_eq!(reassembler.push(&first[1], 3).unwrap().content(), "a".repeat(200));
+}
+
+#[test]
+fn expires_incomplete_messages() {
+    let parts = deliver_segments(&"a".repeat(200), ConcatReference::EightBit(1));
+
+    let mut reassembler = Reassembler::new(Duration::from_millis(20));
+    reassembler.push(&parts[0], 4);
+    assert!(reassembler.expire().is_empty());
+
+    std::thread::sleep(Duration::from_millis(30));
+    let expired = reassembler.expire();
+
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].missing_parts(), vec![2]);
+    assert_eq!(expired[0].memory_indices(), vec![4]);
+    assert_eq!(expired[0].partial_content(), "a".repeat(153));
+    assert!(reassembler.pending().is_empty());
+}
+
+#[test]
+fn measures_gsm7_text() {
+    let length = MessageLength::measure("");
+    assert_eq!((length.segments, length.remaining), (1, 160));
+
+    let length = MessageLength::measure(&"a".repeat(160));
+    assert_eq!(length.encoding, Encoding::Gsm7(ShiftTables::default()));
+    assert_eq!((length.length, length.segments, length.remaining), (160, 1, 0));
+
+    let length = MessageLength::measure(&"a".repeat(161));
+    assert_eq!((length.segments, length.remaining), (2, 153 - 8));
+
+    // The extension table character takes two septets
+    let length = MessageLength::measure("Costs 5€");
+    assert_eq!((length.length, length.remaining), (9, 151));
+    assert!(length.ucs2_characters.is_empty());
+}
+
+#[test]
+fn measures_ucs2_text() {
+    let length = MessageLength::measure("Hello 👋 你好 👋");
+    assert_eq!(length.encoding, Encoding::Ucs2);
+    assert_eq!(length.length, 28);
+    assert_eq!((length.segments, length.remaining), (1, 56));
+    assert_eq!(length.ucs2_characters, vec!['👋', '你', '好']);
+
+    let length = MessageLength::measure(&"你".repeat(71));
+    assert_eq!((length.segments, length.remaining), (2, 67 - 4));
+}
+
+#[test]
+fn measured_segments_match_sent_segments() {
+    for content in ["a".repeat(459), "a".repeat(460), "{".repeat(80), "{".repeat(81), "ş".repeat(200), "👋".repeat(34), "x👋".repeat(23)] {
+        let sent = SmsSubmit::segments(Address::new("5551234"), &content, ConcatReference::EightBit(1)).unwrap();
+        assert_eq!(MessageLength::measure(&content).segments, sent.len(), "{}", content);
+    }
+}
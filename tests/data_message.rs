@@ -0,0 +1,143 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio::sync::{broadcast::Receiver, mpsc};
+use async_modem::{constants::{MessagePipelineOptions, SmsFormat}, data_message::{DataMessage, PortRouter}, events::{BackgroundTask, ModemEvent}, pdu::{Address, ApplicationPort, ConcatReference, InformationElement, Pdu, SmsSubmit, Tpdu, UserDataBody, IE_APPLICATION_PORT_8BIT}, simulator::SimulatedModem, utils::bytes_to_hex};
+use common::{deliver_data_parts, start};
+
+const PORT: ApplicationPort = ApplicationPort { destination: 9200, source: 16000 };
+
+/// Skips events until the next data message from the pipeline, failing on any text message
+async fn next_data(events: &mut Receiver<ModemEvent>) -> (DataMessage, Option<String>) {
+    tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            match events.recv().await.unwrap() {
+                ModemEvent::DataSmsReceived { message, storage } => return (message, storage),
+                ModemEvent::SmsReceived { message, .. } => panic!("Data message received as text: {:?}", message),
+                _ => ()
+            }
+        }
+    }).await.expect("No data message arrived")
+}
+
+#[test]
+fn segments_carry_the_port() {
+    let single = SmsSubmit::data_segments(Address::new("+13155550123"), PORT, &[0xCA, 0xFE], ConcatReference::EightBit(1)).unwrap();
+    assert_eq!(single.len(), 1);
+    let header = single[0].user_data.header.as_ref().unwrap();
+    assert_eq!(header.application_port(), Some(PORT));
+    assert_eq!(header.concatenation(), None);
+
+    let (hex, _) = Pdu { smsc: None, tpdu: Tpdu::Submit(single[0].clone()) }.encode().unwrap();
+    let Tpdu::Submit(decoded) = Pdu::decode(&hex).unwrap().tpdu else { panic!("Not an SMS-SUBMIT") };
+    assert_eq!(decoded, single[0]);
+    assert_eq!(decoded.user_data.body, UserDataBody::Binary(vec![0xCA, 0xFE]));
+
+    // 133 octets fit next to the port alone, parts with a concatenation header too hold 128
+    assert_eq!(SmsSubmit::data_segments(Address::new("+13155550123"), PORT, &[0; 133], ConcatReference::EightBit(1)).unwrap().len(), 1);
+    let parts = SmsSubmit::data_segments(Address::new("+13155550123"), PORT, &[0; 300], ConcatReference::EightBit(1)).unwrap();
+    let sizes: Vec<usize> = parts.iter().map(|part| match &part.user_data.body { UserDataBody::Binary(data) => data.len(), _ => 0 }).collect();
+    assert_eq!(sizes, vec![128, 128, 44]);
+    assert!(parts.iter().all(|part| part.user_data.header.as_ref().unwrap().application_port() == Some(PORT)));
+    assert_eq!(parts[2].user_data.header.as_ref().unwrap().concatenation().unwrap().sequence, 3);
+
+    let mut header = header.clone();
+    header.elements = vec![InformationElement { id: IE_APPLICATION_PORT_8BIT, data: vec![245, 240] }];
+    assert_eq!(header.application_port(), Some(ApplicationPort { destination: 245, source: 240 }));
+}
+
+#[tokio::test]
+async fn send_data_sms() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let data: Vec<u8> = (0..=255).collect();
+
+    let references = modem.send_data_sms(&String::from("+1 315 555 0123"), PORT, &data).await.unwrap();
+
+    assert_eq!(references.len(), 2);
+    let sent = sim.sent_messages();
+    assert!(sent.iter().all(|message| message.destination == "+13155550123"));
+    assert_eq!(sent.iter().map(|message| message.content.as_str()).collect::<String>(), bytes_to_hex(&data));
+    // Only messages people read are reported as sent
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn pipeline_emits_data_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+
+    let index = sim.receive_pdu_sms(&deliver_data_parts("+13155550123", PORT, b"reading=21.5", ConcatReference::EightBit(1))[0]);
+    let (message, storage) = next_data(&mut events).await;
+    assert_eq!(storage.as_deref(), Some("SM"));
+    assert_eq!(message.address, "+13155550123");
+    assert_eq!(message.port, PORT);
+    assert_eq!(message.data, b"reading=21.5");
+    assert_eq!(message.memory_indices, vec![index]);
+
+    let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let parts = deliver_data_parts("+13155550123", PORT, &data, ConcatReference::SixteenBit(700));
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    sim.route_pdu_sms(&parts[1]);
+    sim.route_pdu_sms(&parts[0]);
+    let (message, storage) = next_data(&mut events).await;
+    assert_eq!(storage, None);
+    assert_eq!(message.data, data);
+    pipeline.abort();
+}
+
+#[tokio::test]
+async fn router_hands_messages_to_their_port() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let (sender, mut routed) = mpsc::unbounded_channel();
+    let fallback_sender = sender.clone();
+
+    let router = PortRouter::new()
+        .with_handler(PORT.destination, move |message| sender.send(("meter", message)).unwrap())
+        .with_fallback(move |message| fallback_sender.send(("fallback", message)).unwrap());
+    assert_eq!(router.ports(), vec![9200]);
+
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+    let port_router = modem.spawn_port_router(router);
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+
+    sim.route_pdu_sms(&deliver_data_parts("+13155550123", PORT, &[1, 2, 3], ConcatReference::EightBit(1))[0]);
+    let (handler, message) = tokio::time::timeout(Duration::from_secs(1), routed.recv()).await.unwrap().unwrap();
+    assert_eq!(handler, "meter");
+    assert_eq!(message.data, vec![1, 2, 3]);
+
+    let other = ApplicationPort { destination: 5000, source: 0 };
+    sim.route_pdu_sms(&deliver_data_parts("+13155550199", other, &[9], ConcatReference::EightBit(1))[0]);
+    let (handler, message) = tokio::time::timeout(Duration::from_secs(1), routed.recv()).await.unwrap().unwrap();
+    assert_eq!(handler, "fallback");
+    assert_eq!(message.port.destination, 5000);
+    assert_eq!(message.address, "+13155550199");
+
+    pipeline.abort();
+    port_router.abort();
+}
+
+#[tokio::test]
+async fn router_reports_messages_nobody_took() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+    let port_router = modem.spawn_port_router(PortRouter::new().with_handler(PORT.destination, |_| ()));
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+
+    let other = ApplicationPort { destination: 5000, source: 0 };
+    sim.route_pdu_sms(&deliver_data_parts("+13155550199", other, &[9], ConcatReference::EightBit(1))[0]);
+    let error = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let ModemEvent::BackgroundError { task: BackgroundTask::PortRouter, error } = events.recv().await.unwrap() {
+                return error
+            }
+        }
+    }).await.expect("Nothing reported the unhandled message");
+    assert!(error.contains("5000"));
+
+    pipeline.abort();
+    port_router.abort();
+}
@@ -0,0 +1,20 @@
+use async_modem::error_codes::{CmeErrorCode, ErrorCategory};
+
+#[test]
+fn sim7600_codes_take_the_place_of_27007_ones() {
+    let codes: Vec<(i32, &str)> = (256..=263).map(|code| (code, CmeErrorCode::from_code(code).description())).collect();
+
+    assert_eq!(codes, vec![
+        (256, "Operation temporarily not allowed"),
+        (257, "Call barred"),
+        (258, "Phone is busy"),
+        (259, "User abort"),
+        (260, "Invalid dial string"),
+        (261, "SS not executed"),
+        (262, "SIM Blocked"),
+        (263, "Invalid block")
+    ]);
+    assert_eq!(CmeErrorCode::from_code(256).category(), ErrorCategory::Transient);
+    assert_eq!(CmeErrorCode::from_code(262).category(), ErrorCategory::SimAuth);
+    assert_eq!(CmeErrorCode::from_code(264), CmeErrorCode::UnknownNetworkMessage);
+}
@@ -0,0 +1,76 @@
+use async_modem::{constants::UnsolicitedResultCode, error_codes::{CmeErrorCode, CmsErrorCode}, framer::{FinalResult, FrameKind, Framer}};
+
+#[test]
+fn splits_lines_across_reads() {
+    let mut framer = Framer::new();
+
+    assert!(framer.push(b"\r\n+CSQ: 2").is_empty());
+    let frames = framer.push(b"0,99\r\n\r\nOK\r\n");
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].kind, FrameKind::Intermediate);
+    assert_eq!(frames[0].line, "+CSQ: 20,99");
+    assert_eq!(frames[1].kind, FrameKind::Final(FinalResult::Ok));
+    assert_eq!(frames.iter().map(|f| f.raw.as_str()).collect::<String>(), "\r\n+CSQ: 20,99\r\n\r\nOK\r\n");
+}
+
+#[test]
+fn separates_urcs_from_responses() {
+    let mut framer = Framer::new();
+
+    let frames = framer.push(b"\r\n+CMGL: 0,\"REC READ\",\"0031\",\"\",\"25/06/01,12:30:45-16\"\r\n\r\n+CMTI: \"SM\",3\r\n0048\r\n\r\nOK\r\n");
+    let kinds: Vec<FrameKind> = frames.iter().map(|f| f.kind.clone()).collect();
+
+    assert_eq!(kinds, vec![
+        FrameKind::Intermediate,
+        FrameKind::Urc(UnsolicitedResultCode::CMTI),
+        FrameKind::Intermediate,
+        FrameKind::Final(FinalResult::Ok)
+    ]);
+}
+
+#[test]
+fn message_content_is_never_a_result_code() {
+    let mut framer = Framer::new();
+
+    let frames = framer.push(b"\r\n+CMGR: \"REC READ\",\"+13155550123\",\"\",\"25/06/01,12:30:45-16\"\r\nOK\r\n\r\nOK\r\n");
+
+    assert_eq!(frames[1].kind, FrameKind::Intermediate);
+    assert_eq!(frames[2].kind, FrameKind::Final(FinalResult::Ok));
+}
+
+#[test]
+fn recognizes_prompt_and_errors() {
+    let mut framer = Framer::new();
+
+    assert_eq!(framer.push(b"\r\n> ")[0].kind, FrameKind::Prompt);
+    assert_eq!(framer.push(b"\r\n+CMS ERROR: 332\r\n")[0].kind, FrameKind::Final(FinalResult::CmsError(332)));
+    assert_eq!(framer.push(b"RING\r\r\n")[0].kind, FrameKind::Urc(UnsolicitedResultCode::Ring));
+}
+
+#[test]
+fn attaches_body_to_two_line_urcs() {
+    let mut framer = Framer::new();
+
+    let frames = framer.push(b"\r\n+CDS: 25\r\n0006D60B911326880736F4111011719551401110117195714000\r\n\r\nOK\r\n");
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].kind, FrameKind::Urc(UnsolicitedResultCode::StatusReport));
+    assert_eq!(frames[0].body.as_deref(), Some("0006D60B911326880736F4111011719551401110117195714000"));
+    assert_eq!(frames[1].kind, FrameKind::Final(FinalResult::Ok));
+
+    // Text mode reports fit on one line
+    let frames = framer.push(b"\r\n+CDS: 6,1,\"+13155550123\",145,\"25/06/01,12:30:45+00\",\"25/06/01,12:30:47+00\",0\r\n");
+    assert_eq!(frames[0].body, None);
+}
+
+#[test]
+fn recognizes_verbose_errors() {
+    let mut framer = Framer::new();
+
+    assert_eq!(framer.push(b"\r\n+CME ERROR: SIM not inserted\r\n")[0].kind, FrameKind::Final(FinalResult::CmeError(CmeErrorCode::SimNotInserted.code())));
+    assert_eq!(framer.push(b"\r\n+CMS ERROR: Network timeout\r\n")[0].kind, FrameKind::Final(FinalResult::CmsError(CmsErrorCode::NetworkTimeout.code())));
+    // Vendor text nobody documented still ends the command
+    assert_eq!(framer.push(b"\r\n+CME ERROR: modem is busy, try later\r\n")[0].kind, FrameKind::Final(FinalResult::CmeError(100)));
+    assert_eq!(framer.push(b"\r\n+CMS ERROR: \r\n")[0].kind, FrameKind::Final(FinalResult::CmsError(500)));
+}
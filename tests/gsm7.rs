@@ -0,0 +1,56 @@
+use async_modem::{gsm7::{self, Encoding, NationalLanguage, ShiftTables}, pdu::{Address, Pdu, SmsSubmit, Tpdu, IE_NATIONAL_LOCKING_SHIFT, IE_NATIONAL_SINGLE_SHIFT}};
+
+#[test]
+fn escapes_extension_characters() {
+    let septets = gsm7::encode("[5€]").unwrap();
+
+    assert_eq!(septets, vec![0x1b, 0x3c, 0x35, 0x1b, 0x65, 0x1b, 0x3e]);
+    assert_eq!(gsm7::decode(&septets), "[5€]");
+    assert!(gsm7::encode("ğ").is_none());
+}
+
+#[test]
+fn packs_and_unpacks_septets() {
+    let septets = gsm7::encode("hellohello").unwrap();
+    let packed = gsm7::pack_septets(&septets, 0);
+
+    assert_eq!(packed, vec![0xe8, 0x32, 0x9b, 0xfd, 0x46, 0x97, 0xd9, 0xec, 0x37]);
+    assert_eq!(gsm7::unpack_septets(&packed, septets.len(), 0), septets);
+}
+
+#[test]
+fn national_tables_round_trip() {
+    let tables = ShiftTables { locking: Some(NationalLanguage::Turkish), single: Some(NationalLanguage::Turkish) };
+    let text = "Günaydın, İstanbul! Şimdi ğ ve ç";
+
+    let septets = tables.encode(text).unwrap();
+
+    assert_eq!(tables.decode(&septets), text);
+    assert!(gsm7::encode(text).is_none());
+}
+
+#[test]
+fn picks_the_cheapest_encoding() {
+    assert_eq!(gsm7::cheapest_encoding("Plain ASCII {with} extension"), Encoding::Gsm7(ShiftTables::default()));
+    assert_eq!(gsm7::cheapest_encoding("Hello there 👋"), Encoding::Ucs2);
+    assert_eq!(gsm7::cheapest_encoding("Açaí não é caro, é ótimo! Ação à vista"), Encoding::Gsm7(ShiftTables { locking: Some(NationalLanguage::Portuguese), single: None }));
+    // Ω is missing from the Portuguese locking table but is in its single shift table
+    assert_eq!(gsm7::cheapest_encoding("Açaí não é caro, é ótimo! Ação à vista, 5Ω"), Encoding::Gsm7(ShiftTables { locking: Some(NationalLanguage::Portuguese), single: Some(NationalLanguage::Portuguese) }));
+
+    // A single accented character isn't worth a header, but it still beats UCS2
+    let Encoding::Gsm7(tables) = gsm7::cheapest_encoding("Nos vemos mañana en la estación de trenes a las ocho y media") else { panic!("Expected GSM 7-bit") };
+    assert_eq!(tables.locking, None);
+}
+
+#[test]
+fn national_tables_are_announced_in_pdus() {
+    let submit = SmsSubmit::new(Address::new("+351912345678"), "Açaí não é caro, é ótimo! Ação à vista, 5Ω");
+    let header = submit.user_data.header.clone().unwrap();
+    let ids: Vec<u8> = header.elements.iter().map(|element| element.id).collect();
+
+    assert_eq!(ids, vec![IE_NATIONAL_LOCKING_SHIFT, IE_NATIONAL_SINGLE_SHIFT]);
+    assert_eq!(header.shift_tables(), ShiftTables { locking: Some(NationalLanguage::Portuguese), single: Some(NationalLanguage::Portuguese) });
+
+    let pdu = Pdu { smsc: None, tpdu: Tpdu::Submit(submit) };
+    assert_eq!(Pdu::decode(&pdu.encode().unwrap().0).unwrap(), pdu);
+}
@@ -0,0 +1,829 @@
+mod common;
+
+use std::time::Duration;
+
+use chrono::{FixedOffset, TimeZone};
+use tokio::sync::broadcast::Receiver;
+use async_modem::{archive::{Direction, MessageArchive}, constants::{MessageIndication, MessagePipelineOptions, ModemErrorType, NewMessageIndications, SendOptions, SmsFormat, SmsMessage, SmsParameters, SmsStatus, StatusReportIndication}, error::Error, error_codes::{CmsErrorCode, ErrorCategory}, events::{BackgroundTask, DirectSms, ModemEvent}, pdu::{Address, Alphabet, ConcatReference, DataCodingScheme, DeliveryState, DeliveryStatus, InformationElement, Pdu, SmsStatusReport, Tpdu, UserDataHeader, ValidityPeriod, WaitingMessageKind, IE_SPECIAL_SMS_INDICATION}, phone_number::PhoneNumber, simulator::{SentSms, SimulatedModem, SimulatorHandle}, storage::{CleanupPolicy, DeleteFlag, SmsStorage}, utils::utf16_to_hex};
+use common::{deliver_parts, start};
+
+const TIMESTAMP: &str = "25/06/01,12:30:45-16";
+
+/// Waits for a background task to get storage down to the given number of messages
+async fn wait_for_stored_count(sim: &SimulatorHandle, count: usize) {
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while sim.stored_messages().len() > count {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }).await.expect("Storage was never cleaned up");
+}
+
+/// Skips events until the next message from the pipeline
+async fn next_received(events: &mut Receiver<ModemEvent>) -> (SmsMessage, Option<String>) {
+    loop {
+        if let ModemEvent::SmsReceived { message, storage } = events.recv().await.unwrap() {
+            return (message, storage)
+        }
+    }
+}
+
+#[tokio::test]
+async fn open_configures_the_modem() {
+    // The simulator starts out echoing commands, like a modem fresh from power up
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    assert_eq!(sim.received_commands(), vec!["ATZ", "ATE0", "AT+CMEE=1", "AT+CMGF=1", "AT+CSCS=\"UCS2\""]);
+    assert!(!sim.echo());
+    assert_eq!(modem.get_service_centre().await.unwrap().to_string(), "+15555550100");
+
+    modem.close().await.unwrap();
+    assert!(matches!(modem.get_signal_quality().await, Err(Error::NotOpen)));
+}
+
+#[tokio::test]
+async fn get_signal_quality() {
+    let (modem, _) = start(SimulatedModem::new().with_signal_quality(23, 99)).await;
+
+    assert_eq!(modem.get_signal_quality().await.unwrap(), (23, 99));
+}
+
+#[tokio::test]
+async fn get_and_set_imei() {
+    let (modem, sim) = start(SimulatedModem::new().with_imei("867584032145678")).await;
+
+    assert_eq!(modem.get_imei().await.unwrap(), "867584032145678");
+
+    modem.set_imei(String::from("490154203237518")).await.unwrap();
+    assert_eq!(sim.imei(), "490154203237518");
+
+    assert!(modem.set_imei(String::from("490154203237519")).await.is_err());
+}
+
+#[tokio::test]
+async fn get_and_set_sms_format() {
+    let (modem, _) = start(SimulatedModem::new()).await;
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    assert!(matches!(modem.get_sms_format().await.unwrap(), SmsFormat::ProtocolDataUnit));
+
+    modem.set_sms_format(SmsFormat::Text).await.unwrap();
+    assert!(matches!(modem.get_sms_format().await.unwrap(), SmsFormat::Text));
+}
+
+#[tokio::test]
+async fn get_and_set_auto_timezone_updates() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    modem.set_auto_timezone_updates_config(true).await.unwrap();
+    assert!(sim.auto_timezone_updates());
+    assert!(modem.get_auto_timezone_updates_config().await.unwrap());
+
+    modem.set_auto_timezone_updates_config(false).await.unwrap();
+    assert!(!modem.get_auto_timezone_updates_config().await.unwrap());
+}
+
+#[tokio::test]
+async fn get_sms_message() {
+    let simulator = SimulatedModem::new().with_sms("REC UNREAD", "+13155550123", "Hello there 👋", TIMESTAMP);
+    let (modem, _) = start(simulator).await;
+
+    let message = modem.get_sms_message(0).await.unwrap();
+
+    assert_eq!(message.memory_index(), 0);
+    assert_eq!(message.address(), "+13155550123");
+    assert_eq!(message.content(), "Hello there 👋");
+    assert_eq!(message.timestamp().unwrap().to_rfc3339(), "2025-06-01T12:30:45-04:00");
+}
+
+#[tokio::test]
+async fn get_missing_sms_message_fails() {
+    let (modem, _) = start(SimulatedModem::new()).await;
+
+    let error = modem.get_sms_message(7).await.unwrap_err();
+    let modem_error = error.modem_error().unwrap();
+    assert_eq!(modem_error.error_type(), ModemErrorType::CmsError);
+    assert_eq!(modem_error.code(), 321);
+    assert_eq!(modem_error.cms_code(), Some(CmsErrorCode::InvalidMemoryIndex));
+    assert_eq!(error.category(), ErrorCategory::User);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn get_sms_messages() {
+    let simulator = SimulatedModem::new()
+    .with_sms("REC READ", "+13155550123", "First", TIMESTAMP)
+    .with_sms("REC UNREAD", "+13155550199", "Second", TIMESTAMP);
+    let (modem, _) = start(simulator).await;
+
+    let messages = modem.get_sms_messages(SmsStatus::All).await.unwrap();
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].content(), "First");
+    assert_eq!(messages[1].memory_index(), 1);
+    assert_eq!(messages[1].address(), "+13155550199");
+}
+
+#[tokio::test]
+async fn get_outgoing_sms_messages() {
+    let simulator = SimulatedModem::new()
+        .with_sms("REC READ", "+4915112345678", "Hallo", "25/06/01,12:30:45+08")
+        .with_sms("STO UNSENT", "+13155550199", "Draft", "");
+    let (modem, _) = start(simulator).await;
+
+    let messages = modem.get_sms_messages(SmsStatus::All).await.unwrap();
+    assert_eq!(messages[0].timestamp().unwrap().offset(), &FixedOffset::east_opt(2 * 3600).unwrap());
+    assert!(messages[1].is_outgoing());
+
+    let draft = modem.get_sms_message(1).await.unwrap();
+    assert_eq!(draft.status(), SmsStatus::StoredUnsent);
+    assert_eq!(draft.content(), "Draft");
+    assert_eq!(draft.timestamp(), None);
+
+    let (messages, _) = modem.get_concatenated_sms_messages(SmsStatus::All).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].status(), SmsStatus::ReceivedRead);
+    assert_eq!(messages[0].service_centre().as_deref(), Some("+15555550100"));
+    assert_eq!(messages[0].encoding(), Some(Alphabet::Gsm7));
+}
+
+#[tokio::test]
+async fn send_text_sms() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    modem.send_text_sms(&String::from("13155550123"), &String::from("Hi!")).await.unwrap();
+
+    assert_eq!(sim.sent_messages(), vec![SentSms { destination: String::from("13155550123"), content: String::from("Hi!") }]);
+
+    // Only text that fits the GSM 7-bit alphabet is sent as 7-bit
+    modem.send_text_sms(&String::from("13155550123"), &String::from("Hello there 👋")).await.unwrap();
+    let csmp: Vec<String> = sim.received_commands().into_iter().filter(|command| command.starts_with("AT+CSMP=")).collect();
+    assert_eq!(csmp, vec!["AT+CSMP=17,167,0,0", "AT+CSMP=17,167,0,8"]);
+    assert_eq!(sim.sent_messages()[1].content, "Hello there 👋");
+}
+
+#[tokio::test]
+async fn send_pdu_sms() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    let references = modem.send_pdu_sms(&String::from("+13155550123"), &String::from("Hello there 👋")).await.unwrap();
+
+    assert_eq!(references, vec![1]);
+    assert_eq!(sim.sent_messages(), vec![SentSms { destination: String::from("+13155550123"), content: String::from("Hello there 👋") }]);
+}
+
+#[tokio::test]
+async fn long_messages_are_sent_in_parts() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let content = "Long message ".repeat(20);
+
+    modem.send_text_sms(&String::from("+13155550123"), &content).await.unwrap();
+
+    let sent = sim.sent_messages();
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(|message| message.destination == "+13155550123"));
+    assert_eq!(sent.iter().map(|message| message.content.as_str()).collect::<String>(), content);
+}
+
+#[tokio::test]
+async fn destinations_are_validated_and_normalized() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    let result = modem.send_text_sms(&String::from("+1 315 555 O123"), &String::from("Hi!")).await;
+    assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    let result = modem.send_pdu_sms(&String::from("ACME"), &String::from("Hi!")).await;
+    assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    assert!(sim.sent_messages().is_empty());
+
+    modem.send_text_sms(&String::from("+1 (315) 555-0123"), &String::from("Hi!")).await.unwrap();
+    assert!(sim.received_commands().iter().any(|command| command.starts_with("AT+CMGS=") && command.ends_with(",145")));
+    assert_eq!(sim.sent_messages()[0].destination, "+13155550123");
+}
+
+#[tokio::test]
+async fn delivery_reports_are_matched_to_sent_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let options = SendOptions { status_report: true, ..Default::default() };
+
+    let references = modem.send_text_sms_with_options(&String::from("+13155550123"), &"Long message ".repeat(20), &options).await.unwrap();
+    assert_eq!(references, vec![1, 2]);
+    assert!(sim.status_report_requested(1) && sim.status_report_requested(2));
+    let ModemEvent::SmsSent(sent) = events.recv().await.unwrap() else { panic!("Expected a sent message") };
+    assert_eq!(sent.message_references, vec![1, 2]);
+
+    sim.status_report(2, 0);
+    let ModemEvent::DeliveryReport(report) = events.recv().await.unwrap() else { panic!("Expected a delivery report") };
+    assert_eq!(report.message_reference, 2);
+    assert_eq!(report.recipient, "+13155550123");
+    assert_eq!(report.message.unwrap().statuses, vec![None, Some(DeliveryStatus(0))]);
+    assert_eq!(report.message_state, None);
+
+    sim.status_report(1, 0);
+    let ModemEvent::DeliveryReport(report) = events.recv().await.unwrap() else { panic!("Expected a delivery report") };
+    assert_eq!(report.message.unwrap().content, "Long message ".repeat(20));
+    assert_eq!(report.message_state, Some(DeliveryState::Delivered));
+    assert!(modem.pending_deliveries().is_empty());
+}
+
+#[tokio::test]
+async fn text_mode_delivery_reports() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let options = SendOptions { status_report: true, ..Default::default() };
+
+    let references = modem.send_text_sms_with_options(&String::from("13155550123"), &String::from("Hi!"), &options).await.unwrap();
+    assert!(sim.received_commands().contains(&String::from("AT+CSMP=49,167,0,0")));
+    assert_eq!(modem.pending_deliveries().len(), 1);
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::SmsSent(_)));
+
+    // A report for something that wasn't tracked still comes through
+    sim.status_report(99, 0);
+    let ModemEvent::DeliveryReport(report) = events.recv().await.unwrap() else { panic!("Expected a delivery report") };
+    assert_eq!(report.message, None);
+
+    sim.status_report(references[0], 0x46);
+    let ModemEvent::DeliveryReport(report) = events.recv().await.unwrap() else { panic!("Expected a delivery report") };
+    assert_eq!(report.recipient, "13155550123");
+    assert_eq!(report.status.state(), DeliveryState::Failed);
+    assert_eq!(report.message_state, Some(DeliveryState::Failed));
+    assert!(modem.pending_deliveries().is_empty());
+
+    // Without the option no report is requested
+    let references = modem.send_text_sms(&String::from("13155550123"), &String::from("Hi!")).await.unwrap();
+    assert!(!sim.status_report_requested(references[0]));
+}
+
+#[tokio::test]
+async fn read_stored_delivery_report() {
+    let timestamp = FixedOffset::east_opt(0).unwrap().with_ymd_and_hms(2025, 6, 1, 12, 30, 45).unwrap();
+    let report = SmsStatusReport {
+        message_reference: 7,
+        recipient: Address::new("+13155550123"),
+        service_centre_timestamp: timestamp,
+        discharge_time: timestamp,
+        status: DeliveryStatus(0)
+    };
+    let (pdu, _) = Pdu { smsc: Some(Address::new("+15555550100")), tpdu: Tpdu::StatusReport(report) }.encode().unwrap();
+    let (modem, sim) = start(SimulatedModem::new().with_pdu_sms("REC UNREAD", &pdu)).await;
+    let mut events = modem.subscribe();
+
+    sim.inject_urc("\r\n+CDSI: \"SM\",0\r\n");
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::StatusReportStored { storage: String::from("SM"), index: 0 });
+
+    let report = modem.read_delivery_report(0).await.unwrap();
+    assert_eq!(report.message_reference, 7);
+    assert_eq!(report.discharge_time, timestamp);
+    assert_eq!(report.message, None);
+}
+
+#[tokio::test]
+async fn get_concatenated_sms_messages() {
+    let content = "Long message ".repeat(20);
+    let parts = deliver_parts(&content, ConcatReference::EightBit(9));
+
+    let simulator = SimulatedModem::new()
+    .with_pdu_sms("REC READ", &parts[1])
+    .with_sms("REC READ", "+13155550199", "Short", TIMESTAMP)
+    .with_pdu_sms("REC READ", &parts[0]);
+    let (modem, _) = start(simulator).await;
+
+    let (messages, incomplete) = modem.get_concatenated_sms_messages(SmsStatus::All).await.unwrap();
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].content(), "Short");
+    assert_eq!(messages[1].content(), content);
+    assert_eq!(messages[1].memory_indices(), vec![2, 0]);
+    assert!(incomplete.is_empty());
+}
+
+#[tokio::test]
+async fn get_pdu_sms_messages() {
+    let simulator = SimulatedModem::new()
+    .with_sms("REC UNREAD", "+13155550123", "First", TIMESTAMP)
+    .with_sms("STO UNSENT", "+13155550199", "Draft", TIMESTAMP);
+    let (modem, _) = start(simulator).await;
+
+    let stored = modem.get_pdu_sms_message(0).await.unwrap();
+    assert_eq!(stored.status, SmsStatus::ReceivedUnread);
+    assert_eq!(stored.pdu.smsc.unwrap().to_string(), "+15555550100");
+    let Tpdu::Deliver(deliver) = stored.pdu.tpdu else { panic!("Expected an SMS-DELIVER") };
+    let message = SmsMessage::from_deliver(&deliver, stored.index);
+    assert_eq!(message.address(), "+13155550123");
+    assert_eq!(message.content(), "First");
+    assert_eq!(message.timestamp().unwrap().offset(), &FixedOffset::west_opt(4 * 3600).unwrap());
+
+    let messages = modem.get_pdu_sms_messages(SmsStatus::StoredUnsent).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    let Tpdu::Submit(submit) = &messages[0].pdu.tpdu else { panic!("Expected an SMS-SUBMIT") };
+    assert_eq!(submit.destination.to_string(), "+13155550199");
+    assert_eq!(submit.user_data.text(), Some("Draft"));
+
+    // Switching back to text mode is handled by the text mode methods
+    assert_eq!(modem.get_sms_messages(SmsStatus::All).await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn get_and_set_preferred_storage() {
+    let (modem, sim) = start(SimulatedModem::new().with_sms("REC READ", "+13155550123", "First", TIMESTAMP)).await;
+
+    let storage = modem.get_preferred_storage().await.unwrap();
+    assert_eq!(storage.receive.storage, SmsStorage::Sim);
+    assert_eq!((storage.receive.used, storage.receive.total), (1, 30));
+
+    let storage = modem.set_preferred_storage(SmsStorage::Phone, SmsStorage::Phone, SmsStorage::Any).await.unwrap();
+    assert_eq!(storage.receive.storage, SmsStorage::Any);
+    assert_eq!(storage.read.used, 1);
+
+    sim.receive_sms("+13155550123", "Incoming", TIMESTAMP);
+    assert_eq!(modem.get_preferred_storage().await.unwrap().receive.storage, SmsStorage::Any);
+}
+
+#[tokio::test]
+async fn delete_sms_messages() {
+    let simulator = SimulatedModem::new()
+        .with_sms("REC READ", "+13155550123", "First", TIMESTAMP)
+        .with_sms("REC UNREAD", "+13155550199", "Second", TIMESTAMP)
+        .with_sms("STO SENT", "+13155550199", "Third", "")
+        .with_sms("REC READ", "+13155550123", "Fourth", TIMESTAMP);
+    let (modem, sim) = start(simulator).await;
+
+    modem.delete_sms_message(3).await.unwrap();
+    assert!(modem.delete_sms_message(3).await.is_err());
+
+    modem.delete_sms_messages(DeleteFlag::ReadAndSent).await.unwrap();
+    let remaining: Vec<String> = sim.stored_messages().into_iter().map(|m| m.content).collect();
+    assert_eq!(remaining, vec!["Second"]);
+
+    modem.delete_sms_messages(DeleteFlag::All).await.unwrap();
+    assert!(sim.stored_messages().is_empty());
+}
+
+#[tokio::test]
+async fn storage_is_cleaned_up_when_full() {
+    let simulator = SimulatedModem::new()
+        .with_storage_capacity(3)
+        .with_sms("REC READ", "+13155550123", "First", TIMESTAMP)
+        .with_sms("REC READ", "+13155550123", "Second", TIMESTAMP);
+    let (modem, sim) = start(simulator).await;
+    let mut events = modem.subscribe();
+    let cleanup = modem.spawn_storage_cleanup(CleanupPolicy { delete: DeleteFlag::Read, threshold: None });
+
+    sim.receive_sms("+13155550199", "Third", TIMESTAMP);
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::NewSms { .. }));
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::SmsFull);
+
+    wait_for_stored_count(&sim, 1).await;
+    let remaining: Vec<String> = sim.stored_messages().into_iter().map(|m| m.content).collect();
+    assert_eq!(remaining, vec!["Third"]);
+    cleanup.abort();
+}
+
+#[tokio::test]
+async fn storage_is_cleaned_up_past_threshold() {
+    let (modem, sim) = start(SimulatedModem::new().with_sms("REC READ", "+13155550123", "First", TIMESTAMP)).await;
+    let mut events = modem.subscribe();
+    let cleanup = modem.spawn_storage_cleanup(CleanupPolicy { delete: DeleteFlag::Read, threshold: Some(2) });
+
+    sim.receive_sms("+13155550199", "Second", TIMESTAMP);
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::NewSms { .. }));
+
+    wait_for_stored_count(&sim, 1).await;
+    assert_eq!(sim.stored_messages()[0].content, "Second");
+    cleanup.abort();
+}
+
+#[tokio::test]
+async fn storage_is_cleaned_up_in_the_receive_storage() {
+    let (modem, sim) = start(SimulatedModem::new().with_sms("REC READ", "+13155550123", "First", TIMESTAMP)).await;
+    let mut events = modem.subscribe();
+    modem.set_preferred_storage(SmsStorage::Sim, SmsStorage::Sim, SmsStorage::Phone).await.unwrap();
+    let cleanup = modem.spawn_storage_cleanup(CleanupPolicy { delete: DeleteFlag::Read, threshold: Some(2) });
+
+    sim.receive_sms("+13155550199", "Second", TIMESTAMP);
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::NewSms { storage: String::from("ME"), index: 1 });
+    wait_for_stored_count(&sim, 1).await;
+
+    let commands = sim.received_commands();
+    let delete = commands.iter().position(|command| command == "AT+CMGD=0,1").unwrap();
+    assert_eq!(commands[delete - 1], "AT+CPMS=\"ME\"");
+    assert_eq!(commands[delete + 1], "AT+CPMS=\"SM\"");
+    assert_eq!(modem.get_preferred_storage().await.unwrap().read.storage, SmsStorage::Sim);
+    cleanup.abort();
+}
+
+#[tokio::test]
+async fn get_and_set_new_message_indications() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    assert_eq!(modem.get_new_message_indications().await.unwrap(), NewMessageIndications::default());
+
+    let indications = NewMessageIndications {
+        messages: MessageIndication::Direct,
+        status_reports: StatusReportIndication::StoredIndex,
+        ..Default::default()
+    };
+    modem.set_new_message_indications(&indications).await.unwrap();
+
+    assert!(sim.received_commands().contains(&String::from("AT+CNMI=2,2,0,2,0")));
+    assert_eq!(modem.get_new_message_indications().await.unwrap(), indications);
+}
+
+#[tokio::test]
+async fn get_and_set_service_centre() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    assert_eq!(modem.get_service_centre().await.unwrap().to_string(), "+15555550100");
+
+    let number = PhoneNumber::parse("+44 7958 879879").unwrap();
+    modem.set_service_centre(&number).await.unwrap();
+
+    assert!(sim.received_commands().contains(&format!("AT+CSCA=\"{}\",145", utf16_to_hex("+447958879879"))));
+    assert_eq!(modem.get_service_centre().await.unwrap(), number);
+}
+
+#[tokio::test]
+async fn get_and_set_sms_parameters() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    assert_eq!(modem.get_sms_parameters().await.unwrap(), SmsParameters::default());
+
+    let week = SmsParameters { validity_period: Some(ValidityPeriod::Relative(Duration::from_secs(7 * 24 * 3600))), ..Default::default() };
+    modem.set_sms_parameters(&week).await.unwrap();
+    assert_eq!(modem.get_sms_parameters().await.unwrap(), week);
+
+    let expiry = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2025, 7, 1, 18, 0, 0).unwrap();
+    let absolute = SmsParameters { first_octet: 49, validity_period: Some(ValidityPeriod::Absolute(expiry)), ..Default::default() };
+    modem.set_sms_parameters(&absolute).await.unwrap();
+    // The validity period format bits follow the validity period
+    assert_eq!(modem.get_sms_parameters().await.unwrap(), SmsParameters { first_octet: 57, ..absolute });
+
+    let unlimited = SmsParameters { validity_period: None, ..Default::default() };
+    modem.set_sms_parameters(&unlimited).await.unwrap();
+    assert_eq!(modem.get_sms_parameters().await.unwrap().validity_period, None);
+
+    let csmp: Vec<String> = sim.received_commands().into_iter().filter(|command| command.starts_with("AT+CSMP=")).collect();
+    assert_eq!(csmp, vec!["AT+CSMP=17,173,0,0", "AT+CSMP=57,\"25/07/01,18:00:00+04\",0,0", "AT+CSMP=1,,0,0"]);
+}
+
+#[tokio::test]
+async fn text_messages_are_sent_with_the_validity_period() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    let options = SendOptions { validity_period: Some(ValidityPeriod::Relative(Duration::from_secs(3600))), ..Default::default() };
+    modem.send_text_sms_with_options(&String::from("+13155550123"), &String::from("Hi!"), &options).await.unwrap();
+
+    assert!(sim.received_commands().contains(&String::from("AT+CSMP=17,11,0,0")));
+}
+
+#[tokio::test]
+async fn get_and_set_show_text_mode_headers() {
+    let simulator = SimulatedModem::new().with_sms("REC UNREAD", "+13155550123", "Hello there 👋", TIMESTAMP);
+    let (modem, _) = start(simulator).await;
+
+    assert!(!modem.get_show_text_mode_headers().await.unwrap());
+    assert_eq!(modem.get_sms_message(0).await.unwrap().service_centre(), None);
+
+    modem.set_show_text_mode_headers(true).await.unwrap();
+
+    assert!(modem.get_show_text_mode_headers().await.unwrap());
+    let message = modem.get_sms_message(0).await.unwrap();
+    assert_eq!(message.service_centre().as_deref(), Some("+15555550100"));
+    assert_eq!(message.content(), "Hello there 👋");
+}
+
+#[tokio::test]
+async fn direct_messages_are_broadcast() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+
+    sim.route_sms("+13155550123", "OK", TIMESTAMP);
+    let ModemEvent::DirectSms(DirectSms::Text(message)) = events.recv().await.unwrap() else { panic!("Expected a text mode message") };
+    assert_eq!(message.address(), "+13155550123");
+    // Content that looks like a result code is still content
+    assert_eq!(message.content(), "OK");
+    assert!(!message.is_stored());
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    sim.route_sms("+13155550123", "Hello there 👋", TIMESTAMP);
+    let ModemEvent::DirectSms(DirectSms::Pdu(deliver)) = events.recv().await.unwrap() else { panic!("Expected a PDU mode message") };
+    assert_eq!(deliver.user_data.text(), Some("Hello there 👋"));
+}
+
+#[tokio::test]
+async fn pipeline_fetches_indexed_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+
+    let index = sim.receive_sms("+13155550123", "Incoming", TIMESTAMP);
+    let (message, storage) = next_received(&mut events).await;
+    assert_eq!(storage.as_deref(), Some("SM"));
+    assert_eq!(message.memory_index(), index);
+    assert_eq!(message.content(), "Incoming");
+
+    let content = "Long message ".repeat(20);
+    let parts = deliver_parts(&content, ConcatReference::EightBit(3));
+    sim.receive_pdu_sms(&parts[1]);
+    sim.receive_pdu_sms(&parts[0]);
+    let (message, _) = next_received(&mut events).await;
+    assert_eq!(message.content(), content);
+    assert_eq!(message.memory_indices(), vec![2, 1]);
+    pipeline.abort();
+}
+
+#[tokio::test]
+async fn pipeline_reads_from_the_storage_the_message_was_saved_to() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    // Nothing checks the interval for being zero before it's used
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions { acknowledge: true, reassembly_timeout: Duration::ZERO });
+
+    modem.set_preferred_storage(SmsStorage::Sim, SmsStorage::Sim, SmsStorage::Phone).await.unwrap();
+    let index = sim.receive_sms("+13155550123", "Incoming", TIMESTAMP);
+    let (message, storage) = tokio::time::timeout(Duration::from_secs(1), next_received(&mut events)).await.expect("Pipeline stopped");
+    assert_eq!(storage.as_deref(), Some("ME"));
+    assert_eq!(message.memory_index(), index);
+
+    let commands = sim.received_commands();
+    let read = commands.iter().position(|command| command == &format!("AT+CMGR={}", index)).unwrap();
+    assert_eq!(commands[read - 2], "AT+CPMS=\"ME\"");
+    assert_eq!(commands[read + 1], "AT+CPMS=\"SM\"");
+    assert_eq!(modem.get_preferred_storage().await.unwrap().read.storage, SmsStorage::Sim);
+    pipeline.abort();
+}
+
+#[tokio::test]
+async fn sms_format_holds_for_a_whole_send() {
+    let (modem, sim) = start(SimulatedModem::new().with_sms("REC UNREAD", "+13155550123", "Stored", TIMESTAMP)).await;
+
+    // Reads in PDU mode racing text mode sends, each needs the format it set to last until it's done
+    let reads = async {
+        for _ in 0..10 {
+            modem.get_pdu_sms_message(0).await.unwrap();
+        }
+    };
+    let sends = async {
+        for _ in 0..10 {
+            modem.send_text_sms("+13155550123", "Hi!").await.unwrap();
+        }
+    };
+    tokio::join!(reads, sends);
+
+    assert_eq!(sim.sent_messages().len(), 10);
+    assert!(sim.sent_messages().iter().all(|message| message.content == "Hi!"));
+    let mut format = None;
+    for command in sim.received_commands() {
+        match command.strip_prefix("AT+CMGF=") {
+            Some(value) => format = Some(String::from(value)),
+            None if command.starts_with("AT+CMGS=\"") => assert_eq!(format.as_deref(), Some("1")),
+            None if command.starts_with("AT+CMGR=") => assert_eq!(format.as_deref(), Some("0")),
+            None => ()
+        }
+    }
+}
+
+#[tokio::test]
+async fn archiver_records_sent_and_received_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let path = std::env::temp_dir().join(format!("async-modem-archiver-{}.archive", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let archive = MessageArchive::open(&path).unwrap();
+
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+    let archiver = modem.spawn_archiver(archive.clone());
+
+    sim.receive_sms("+13155550123", "Incoming", TIMESTAMP);
+    next_received(&mut events).await;
+    modem.send_text_sms(&String::from("+13155550123"), &String::from("Outgoing")).await.unwrap();
+
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while archive.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }).await.expect("Messages were never archived");
+
+    let conversation = archive.conversation("13155550123");
+    assert_eq!(conversation.iter().map(|message| message.direction).collect::<Vec<_>>(), vec![Direction::Received, Direction::Sent]);
+    assert_eq!(archive.unread().len(), 1);
+
+    pipeline.abort();
+    archiver.abort();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn pipeline_acknowledges_direct_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+
+    sim.route_sms("+13155550123", "Incoming", TIMESTAMP);
+    let (message, storage) = next_received(&mut events).await;
+    assert_eq!(storage, None);
+    assert_eq!(message.content(), "Incoming");
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    let content = "Long message ".repeat(20);
+    for part in deliver_parts(&content, ConcatReference::EightBit(4)) {
+        sim.route_pdu_sms(&part);
+    }
+    let (message, _) = next_received(&mut events).await;
+    assert_eq!(message.content(), content);
+    assert!(message.memory_indices().is_empty());
+
+    // Each routed message is acknowledged before anything is emitted for it
+    assert_eq!(sim.acknowledgements(), 3);
+    pipeline.abort();
+}
+
+#[tokio::test]
+async fn pipeline_gives_up_on_incomplete_messages() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions { acknowledge: false, reassembly_timeout: Duration::from_millis(50) });
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    sim.route_pdu_sms(&deliver_parts(&"a".repeat(200), ConcatReference::EightBit(5))[0]);
+
+    let incomplete = loop {
+        if let ModemEvent::IncompleteSms(incomplete) = events.recv().await.unwrap() {
+            break incomplete
+        }
+    };
+    assert_eq!(incomplete.missing_parts(), vec![2]);
+    assert_eq!(sim.acknowledgements(), 0);
+    pipeline.abort();
+}
+
+#[tokio::test]
+async fn concurrent_commands_get_their_own_responses() {
+    let (modem, _) = start(SimulatedModem::new().with_imei("867584032145678").with_signal_quality(17, 99)).await;
+
+    let imei_modem = modem.clone();
+    let imei = tokio::spawn(async move { imei_modem.get_imei().await });
+    let signal_modem = modem.clone();
+    let signal = tokio::spawn(async move { signal_modem.get_signal_quality().await });
+
+    assert_eq!(imei.await.unwrap().unwrap(), "867584032145678");
+    assert_eq!(signal.await.unwrap().unwrap(), (17, 99));
+}
+
+#[tokio::test]
+async fn commands_fail_once_closed() {
+    let (modem, _) = start(SimulatedModem::new()).await;
+
+    modem.close().await.unwrap();
+
+    assert!(!modem.is_open());
+    assert!(matches!(modem.get_signal_quality().await, Err(Error::NotOpen)));
+}
+
+#[tokio::test]
+async fn commands_time_out() {
+    let (modem, _) = start(SimulatedModem::new().with_silent_command("AT+COPS")).await;
+
+    let error = modem.write_data_with_timeout(String::from("AT+COPS?\r"), Duration::from_millis(50)).await.unwrap_err();
+    assert!(matches!(error, Error::Timeout { command, .. } if command == "AT+COPS?"));
+
+    // The modem is still usable afterwards
+    assert_eq!(modem.get_signal_quality().await.unwrap(), (20, 99));
+}
+
+#[tokio::test]
+async fn dropped_command_is_not_sent() {
+    let (modem, sim) = start(SimulatedModem::new().with_silent_command("AT+COPS")).await;
+
+    let slow = modem.write_data_with_timeout(String::from("AT+COPS?\r"), Duration::from_millis(200));
+    // Gives up on the queued command while the slow one is still holding up the queue
+    let dropped = tokio::time::timeout(Duration::from_millis(20), modem.write_data(String::from("AT+CSQ\r")));
+    let (_, dropped) = tokio::join!(slow, dropped);
+    assert!(dropped.is_err());
+
+    modem.get_imei().await.unwrap();
+    // After the five configuration commands from open(), with the modem probed once the silent command never answered
+    assert_eq!(sim.received_commands().split_off(5), vec!["AT+COPS?", "AT", "AT+SIMEI?"]);
+}
+
+#[tokio::test]
+async fn late_response_is_not_taken_for_the_next_command() {
+    let (modem, sim) = start(SimulatedModem::new().with_slow_command("AT+CSQ", Duration::from_millis(150))).await;
+
+    let error = modem.write_data_with_timeout(String::from("AT+CSQ\r"), Duration::from_millis(100)).await.unwrap_err();
+    assert!(matches!(error, Error::Timeout { .. }));
+
+    // Sent only once the late +CSQ and OK are out of the way
+    assert_eq!(modem.get_imei().await.unwrap(), "867584032145678");
+    assert_eq!(sim.received_commands().split_off(5), vec!["AT+CSQ", "AT+SIMEI?"]);
+}
+
+#[tokio::test]
+async fn late_prompt_is_escaped() {
+    let (modem, sim) = start(SimulatedModem::new().with_slow_command("AT+CMGS", Duration::from_millis(150))).await;
+
+    let command = format!("AT+CMGS=\"{}\",145\r", utf16_to_hex("+13155550123"));
+    let error = modem.write_data_with_timeout(command, Duration::from_millis(100)).await.unwrap_err();
+    assert!(matches!(error, Error::Timeout { .. }));
+
+    // The prompt shows up after the timeout and is backed out of, rather than taking the next command as a message body
+    assert_eq!(modem.get_signal_quality().await.unwrap(), (20, 99));
+    assert!(sim.sent_messages().is_empty());
+    assert_eq!(sim.received_commands().last().map(String::as_str), Some("AT+CSQ"));
+}
+
+#[tokio::test]
+async fn urcs_are_broadcast_as_events() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+
+    let index = sim.receive_sms("+13155550123", "Incoming", TIMESTAMP);
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::NewSms { storage: String::from("SM"), index: index });
+
+    sim.missed_call("14:05PM", "+13155550199");
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::MissedCall { time: String::from("14:05PM"), number: String::from("+13155550199") });
+
+    sim.inject_urc("\r\n+CTZV: -16\r\n");
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::TimeZoneChange { offset: FixedOffset::west_opt(4 * 3600).unwrap() });
+
+    sim.inject_urc("\r\nVOICE CALL: END: 000125\r\n");
+    assert_eq!(events.recv().await.unwrap(), ModemEvent::VoiceCallEnd { duration: Duration::from_secs(85) });
+
+    // A quarter of an hour offset no timezone has
+    sim.inject_urc("\r\n+CTZV: 9999\r\n");
+    let ModemEvent::BackgroundError { task, error } = events.recv().await.unwrap() else { panic!("Expected a background error") };
+    assert_eq!(task, BackgroundTask::Connection);
+    assert!(error.contains("+CTZV: 9999"));
+}
+
+#[tokio::test]
+async fn urcs_do_not_break_commands() {
+    let simulator = SimulatedModem::new()
+        .with_sms("REC READ", "+13155550123", "First", TIMESTAMP)
+        .with_sms("REC READ", "+13155550199", "Second", TIMESTAMP);
+    let (modem, sim) = start(simulator).await;
+    let mut events = modem.subscribe();
+
+    sim.receive_sms("+13155550100", "Incoming", TIMESTAMP);
+    sim.missed_call("14:05PM", "+13155550199");
+    let messages = modem.get_sms_messages(SmsStatus::All).await.unwrap();
+
+    assert_eq!(messages.len(), 3);
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::NewSms { index: 2, .. }));
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::MissedCall { .. }));
+}
+
+#[tokio::test]
+async fn sends_with_message_class() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let flash = SendOptions { class: Some(0), ..Default::default() };
+
+    modem.send_text_sms_with_options(&String::from("13155550123"), &String::from("Look now!"), &flash).await.unwrap();
+    assert!(sim.received_commands().contains(&String::from("AT+CSMP=17,167,0,16")));
+
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+    let options = SendOptions { class: Some(1), ..Default::default() };
+    modem.send_pdu_sms_with_options(&String::from("+13155550123"), &String::from("Hello there 👋"), &options).await.unwrap();
+    let submits = sim.sent_submits();
+    assert_eq!(submits.len(), 1);
+    assert_eq!(submits[0].data_coding_scheme, DataCodingScheme(0x19));
+    assert_eq!(submits[0].data_coding_scheme.alphabet(), Alphabet::Ucs2);
+
+    let invalid = SendOptions { class: Some(4), ..Default::default() };
+    let result = modem.send_pdu_sms_with_options(&String::from("+13155550123"), &String::from("Hi!"), &invalid).await;
+    assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    assert_eq!(sim.sent_submits().len(), 1);
+}
+
+#[tokio::test]
+async fn pipeline_reports_flash_messages_and_voicemail() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let pipeline = modem.spawn_message_pipeline(MessagePipelineOptions::default());
+    modem.set_sms_format(SmsFormat::ProtocolDataUnit).await.unwrap();
+
+    let Tpdu::Deliver(mut deliver) = Pdu::decode(&deliver_parts("Meet me outside", ConcatReference::EightBit(6))[0]).unwrap().tpdu else { panic!("Not an SMS-DELIVER") };
+    deliver.data_coding_scheme = DataCodingScheme::new(Alphabet::Gsm7, Some(0));
+    sim.route_pdu_sms(&Pdu { smsc: None, tpdu: Tpdu::Deliver(deliver.clone()) }.encode().unwrap().0);
+    let (message, _) = next_received(&mut events).await;
+    assert!(message.is_flash());
+    assert_eq!(message.content(), "Meet me outside");
+    assert!(message.message_waiting().is_empty());
+
+    // The header gives a count, the coding scheme only that voicemail is waiting
+    deliver.data_coding_scheme = DataCodingScheme(0xC8);
+    deliver.user_data.header = Some(UserDataHeader { elements: vec![InformationElement { id: IE_SPECIAL_SMS_INDICATION, data: vec![0x00, 3] }] });
+    sim.route_pdu_sms(&Pdu { smsc: None, tpdu: Tpdu::Deliver(deliver.clone()) }.encode().unwrap().0);
+    let (message, _) = next_received(&mut events).await;
+    assert!(!message.is_flash());
+    let waiting = message.message_waiting();
+    assert_eq!(waiting.len(), 1);
+    assert_eq!((waiting[0].kind, waiting[0].count, waiting[0].store), (WaitingMessageKind::Voicemail, Some(3), false));
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::VoicemailWaiting { count: Some(3) }));
+
+    deliver.data_coding_scheme = DataCodingScheme(0xD0);
+    deliver.user_data.header = None;
+    sim.route_pdu_sms(&Pdu { smsc: None, tpdu: Tpdu::Deliver(deliver) }.encode().unwrap().0);
+    next_received(&mut events).await;
+    assert!(matches!(events.recv().await.unwrap(), ModemEvent::VoicemailWaiting { count: Some(0) }));
+    pipeline.abort();
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use tokio::{sync::broadcast::Receiver, time::Instant};
+use async_modem::{events::ModemEvent, gsm_modem::GsmModem, outbox::{Outbox, OutboxMessage, OutboxOptions, OutboxState}, simulator::SimulatedModem};
+use common::start;
+
+fn outbox_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("async-modem-{}-{}.outbox", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    path
+}
+
+fn fast_options() -> OutboxOptions {
+    OutboxOptions {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(20),
+        max_backoff: Duration::from_millis(50),
+        send_interval: Duration::ZERO
+    }
+}
+
+/// Skips events until the given message reaches a final state, collecting every state it went through
+async fn wait_for_final(events: &mut Receiver<ModemEvent>, id: u64) -> (OutboxMessage, Vec<OutboxState>) {
+    let mut states = Vec::new();
+
+    tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            if let ModemEvent::OutboxUpdate(message) = events.recv().await.unwrap() && message.id == id {
+                states.push(message.state);
+                if message.state.is_final() {
+                    return message
+                }
+            }
+        }
+    }).await.map(|message| (message, states.clone())).expect("Message never reached a final state")
+}
+
+#[test]
+fn backoff_doubles_up_to_the_limit() {
+    let options = OutboxOptions::default();
+
+    assert_eq!(options.backoff(1), Duration::from_secs(5));
+    assert_eq!(options.backoff(3), Duration::from_secs(20));
+    assert_eq!(options.backoff(40), Duration::from_secs(300));
+}
+
+#[test]
+fn keeps_queued_messages_across_restarts() {
+    let path = outbox_path("restart");
+    let outbox = Outbox::open(&path).unwrap();
+
+    let first = outbox.enqueue("+13155550123", "First\nmessage", false).unwrap();
+    let second = outbox.enqueue("+13155550123", "Second", true).unwrap();
+    drop(outbox);
+
+    let outbox = Outbox::open(&path).unwrap();
+    let pending = outbox.pending();
+    assert_eq!(pending.iter().map(|message| message.id).collect::<Vec<_>>(), vec![first, second]);
+    assert_eq!(pending[0].content, "First\nmessage");
+    assert!(pending[1].status_report);
+    assert_eq!(pending[1].state, OutboxState::Queued);
+
+    // Ids keep increasing after sent messages are dropped
+    fs::write(&path, format!("{}S\t{}\t1\nS\t{}\t2\n", fs::read_to_string(&path).unwrap(), first, second)).unwrap();
+    let outbox = Outbox::open(&path).unwrap();
+    assert!(outbox.pending().is_empty());
+    assert_eq!(outbox.enqueue("+13155550123", "Third", false).unwrap(), second + 1);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn compacts_through_a_temporary_file() {
+    let path = outbox_path("compact");
+    let outbox = Outbox::open(&path).unwrap();
+    let id = outbox.enqueue("+13155550123", "Kept", false).unwrap();
+    drop(outbox);
+
+    // A record cut off by a crash is dropped, and a temporary file left by one is replaced
+    let temp = path.with_file_name(format!("{}.tmp", path.file_name().unwrap().to_str().unwrap()));
+    fs::write(&temp, "leftover").unwrap();
+    fs::write(&path, format!("{}Q\t{}\t0", fs::read_to_string(&path).unwrap(), id + 1)).unwrap();
+
+    let outbox = Outbox::open(&path).unwrap();
+    assert_eq!(outbox.pending().iter().map(|message| message.id).collect::<Vec<_>>(), vec![id]);
+    assert!(!temp.exists());
+    assert!(fs::read_to_string(&path).unwrap().ends_with("\tKept\n"));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn refuses_other_files() {
+    let path = outbox_path("other");
+    fs::write(&path, "not an outbox\n").unwrap();
+
+    assert!(Outbox::open(&path).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "not an outbox\n");
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn sends_in_order() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let outbox = Outbox::new();
+
+    let ids: Vec<u64> = (0..3).map(|i| outbox.enqueue("+13155550123", &format!("Message {}", i), false).unwrap()).collect();
+    let task = modem.spawn_outbox(outbox.clone(), fast_options());
+
+    let (message, states) = wait_for_final(&mut events, ids[2]).await;
+    assert_eq!(states, vec![OutboxState::Sending, OutboxState::Sent]);
+    assert_eq!(message.message_references, vec![3]);
+
+    let sent: Vec<String> = sim.sent_messages().into_iter().map(|message| message.content).collect();
+    assert_eq!(sent, vec!["Message 0", "Message 1", "Message 2"]);
+    assert!(outbox.pending().is_empty());
+    task.abort();
+}
+
+#[tokio::test]
+async fn retries_transient_errors() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let outbox = Outbox::new();
+    let task = modem.spawn_outbox(outbox.clone(), fast_options());
+
+    // Network timeout, then SIM busy, then through
+    sim.fail_next_sends(&[332, 314]);
+    let id = outbox.enqueue("+13155550123", "Eventually", false).unwrap();
+    let later = outbox.enqueue("+13155550123", "After", false).unwrap();
+
+    let (message, states) = wait_for_final(&mut events, id).await;
+    assert_eq!(message.state, OutboxState::Sent);
+    assert_eq!(message.attempts, 3);
+    assert!(message.last_error.unwrap().contains("SIM busy"));
+    assert_eq!(states, vec![
+        OutboxState::Sending, OutboxState::Queued,
+        OutboxState::Sending, OutboxState::Queued,
+        OutboxState::Sending, OutboxState::Sent
+    ]);
+
+    wait_for_final(&mut events, later).await;
+    let sent: Vec<String> = sim.sent_messages().into_iter().map(|message| message.content).collect();
+    assert_eq!(sent, vec!["Eventually", "After"]);
+    task.abort();
+}
+
+#[tokio::test]
+async fn fails_on_permanent_errors_and_exhausted_retries() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let outbox = Outbox::new();
+    let task = modem.spawn_outbox(outbox.clone(), fast_options());
+
+    sim.fail_next_sends(&[330]);
+    let id = outbox.enqueue("+13155550123", "No SMSC", false).unwrap();
+    let (message, _) = wait_for_final(&mut events, id).await;
+    assert_eq!(message.state, OutboxState::Failed);
+    assert_eq!(message.attempts, 1);
+
+    sim.fail_next_sends(&[332, 332, 332]);
+    let id = outbox.enqueue("+13155550123", "No network", false).unwrap();
+    let (message, _) = wait_for_final(&mut events, id).await;
+    assert_eq!(message.state, OutboxState::Failed);
+    assert_eq!(message.attempts, 3);
+
+    assert!(sim.sent_messages().is_empty());
+    task.abort();
+}
+
+#[tokio::test]
+async fn waits_for_the_modem_to_open() {
+    let (transport, sim) = SimulatedModem::new().start();
+    let modem = GsmModem::new(transport);
+    let mut events = modem.subscribe();
+    let outbox = Outbox::new();
+    let task = modem.spawn_outbox(outbox.clone(), fast_options());
+
+    let id = outbox.enqueue("+13155550123", "Once open", false).unwrap();
+    // Long enough to run out of attempts if not being open used them up
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!outbox.get(id).unwrap().state.is_final());
+
+    modem.open().await.unwrap();
+    let (message, _) = wait_for_final(&mut events, id).await;
+    assert_eq!(message.state, OutboxState::Sent);
+    assert_eq!(message.attempts, 1);
+    assert_eq!(sim.sent_messages()[0].content, "Once open");
+    task.abort();
+}
+
+#[tokio::test]
+async fn respects_the_send_interval() {
+    let (modem, _) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let outbox = Outbox::new();
+    let options = OutboxOptions { send_interval: Duration::from_millis(100), ..fast_options() };
+
+    let started = Instant::now();
+    let ids: Vec<u64> = (0..3).map(|i| outbox.enqueue("+13155550123", &format!("Message {}", i), false).unwrap()).collect();
+    let task = modem.spawn_outbox(outbox, options);
+
+    wait_for_final(&mut events, ids[2]).await;
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    task.abort();
+}
+
+#[tokio::test]
+async fn sends_messages_left_from_a_previous_run() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+    let mut events = modem.subscribe();
+    let path = outbox_path("resume");
+
+    let id = Outbox::open(&path).unwrap().enqueue("+13155550123", "Left over", false).unwrap();
+    let outbox = Outbox::open(&path).unwrap();
+    let task = modem.spawn_outbox(outbox, fast_options());
+
+    wait_for_final(&mut events, id).await;
+    assert_eq!(sim.sent_messages()[0].content, "Left over");
+    task.abort();
+
+    assert!(Outbox::open(&path).unwrap().pending().is_empty());
+    fs::remove_file(path).unwrap();
+}
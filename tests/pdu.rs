@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+use async_modem::pdu::{Address, Alphabet, DataCodingScheme, DeliveryState, DeliveryStatus, InformationElement, MessageWaiting, NumberingPlan, Pdu, SmsDeliver, SmsStatusReport, SmsSubmit, Tpdu, TypeOfNumber, UserData, UserDataBody, UserDataHeader, ValidityPeriod, WaitingMessageKind, IE_SPECIAL_SMS_INDICATION};
+
+fn timestamp() -> DateTime<FixedOffset> {
+    FixedOffset::east_opt(-4 * 3600).unwrap().with_ymd_and_hms(2025, 6, 1, 12, 30, 45).unwrap()
+}
+
+#[test]
+fn decodes_deliver() {
+    let pdu = Pdu::decode("07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07").unwrap();
+
+    assert_eq!(pdu.smsc.unwrap().to_string(), "+31624000000");
+
+    let Tpdu::Deliver(deliver) = pdu.tpdu else { panic!("Expected an SMS-DELIVER") };
+    assert_eq!(deliver.originator.to_string(), "+31641600986");
+    assert_eq!(deliver.originator.type_of_number, TypeOfNumber::International);
+    assert_eq!(deliver.originator.numbering_plan, NumberingPlan::Isdn);
+    assert_eq!(deliver.data_coding_scheme.alphabet(), Alphabet::Gsm7);
+    // The timezone octet is 0x08, which is the sign bit with zero quarter hours
+    assert_eq!(deliver.service_centre_timestamp, FixedOffset::east_opt(0).unwrap().with_ymd_and_hms(2002, 8, 26, 19, 37, 41).unwrap());
+    assert_eq!(deliver.user_data.text(), Some("How are you?"));
+}
+
+#[test]
+fn encodes_submit() {
+    let mut submit = SmsSubmit::new(Address::new("+46708251358"), "hellohello");
+    submit.validity_period = Some(ValidityPeriod::Relative(Duration::from_secs(4 * 24 * 3600)));
+
+    let (hex, length) = Pdu { smsc: None, tpdu: Tpdu::Submit(submit) }.encode().unwrap();
+
+    assert_eq!(hex, "0011000B916407281553F80000AA0AE8329BFD4697D9EC37");
+    assert_eq!(length, 23);
+}
+
+#[test]
+fn round_trips_ucs2_with_header() {
+    let deliver = SmsDeliver {
+        more_messages_to_send: false,
+        reply_path: false,
+        status_report_indication: true,
+        originator: Address::new("ACME"),
+        protocol_identifier: 0,
+        data_coding_scheme: DataCodingScheme::new(Alphabet::Ucs2, Some(1)),
+        service_centre_timestamp: timestamp(),
+        user_data: UserData {
+            header: Some(UserDataHeader { elements: vec![InformationElement { id: 0x00, data: vec![0x2a, 0x02, 0x01] }] }),
+            body: UserDataBody::Text(String::from("Hello there 👋"))
+        }
+    };
+    let pdu = Pdu { smsc: Some(Address::new("+15555550100")), tpdu: Tpdu::Deliver(deliver) };
+
+    let decoded = Pdu::decode(&pdu.encode().unwrap().0).unwrap();
+
+    assert_eq!(decoded, pdu);
+    let Tpdu::Deliver(deliver) = decoded.tpdu else { panic!("Expected an SMS-DELIVER") };
+    assert_eq!(deliver.originator.type_of_number, TypeOfNumber::Alphanumeric);
+    assert_eq!(deliver.data_coding_scheme.class(), Some(1));
+}
+
+#[test]
+fn aligns_gsm7_text_after_header() {
+    let mut submit = SmsSubmit::new(Address::new("5551234"), "Part one of many");
+    submit.user_data.header = Some(UserDataHeader { elements: vec![InformationElement { id: 0x00, data: vec![0x01, 0x03, 0x01] }] });
+    submit.validity_period = Some(ValidityPeriod::Absolute(timestamp()));
+    let pdu = Pdu { smsc: None, tpdu: Tpdu::Submit(submit) };
+
+    assert_eq!(Pdu::decode(&pdu.encode().unwrap().0).unwrap(), pdu);
+}
+
+#[test]
+fn rejects_truncated_pdu() {
+    assert!(Pdu::decode("07911326040000F0040B911346610089F600002080629173140810C8").is_err());
+    assert!(Pdu::decode("0011000B91640728").is_err());
+}
+
+#[test]
+fn decodes_status_report() {
+    let pdu = Pdu::decode("0006D60B911326880736F4111011719551401110117195714000").unwrap();
+
+    assert!(pdu.smsc.is_none());
+    let Tpdu::StatusReport(report) = pdu.tpdu else { panic!("Expected an SMS-STATUS-REPORT") };
+    assert_eq!(report.message_reference, 0xd6);
+    assert_eq!(report.recipient.to_string(), "+31628870634");
+    assert_eq!(report.service_centre_timestamp, FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2011, 1, 11, 17, 59, 15).unwrap());
+    assert_eq!(report.discharge_time, FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2011, 1, 11, 17, 59, 17).unwrap());
+    assert_eq!(report.status.state(), DeliveryState::Delivered);
+}
+
+#[test]
+fn round_trips_status_report() {
+    let report = SmsStatusReport {
+        message_reference: 42,
+        recipient: Address::new("+13155550123"),
+        service_centre_timestamp: timestamp(),
+        discharge_time: timestamp(),
+        status: DeliveryStatus(0x46)
+    };
+    let pdu = Pdu { smsc: Some(Address::new("+15555550100")), tpdu: Tpdu::StatusReport(report) };
+
+    assert_eq!(Pdu::decode(&pdu.encode().unwrap().0).unwrap(), pdu);
+    assert_eq!(DeliveryStatus(0x46).state(), DeliveryState::Failed);
+    assert_eq!(DeliveryStatus(0x21).state(), DeliveryState::Pending);
+    assert!(!DeliveryStatus(0x21).is_final());
+    assert_eq!(DeliveryStatus(0x61).state(), DeliveryState::Failed);
+}
+
+#[test]
+fn parses_message_waiting_indications() {
+    // Discard group, voicemail waiting
+    let discard = DataCodingScheme(0xC8).message_waiting().unwrap();
+    assert_eq!(discard, MessageWaiting { kind: WaitingMessageKind::Voicemail, active: true, count: None, store: false });
+    assert_eq!(DataCodingScheme(0xC8).alphabet(), Alphabet::Gsm7);
+    // Store group, UCS2, no more email waiting
+    assert_eq!(DataCodingScheme(0xE2).message_waiting(), Some(MessageWaiting { kind: WaitingMessageKind::Email, active: false, count: None, store: true }));
+    assert_eq!(DataCodingScheme(0x10).message_waiting(), None);
+    assert_eq!(DataCodingScheme::new(Alphabet::Ucs2, Some(0)).class(), Some(0));
+
+    let header = UserDataHeader { elements: vec![
+        InformationElement { id: IE_SPECIAL_SMS_INDICATION, data: vec![0x80, 3] },
+        InformationElement { id: IE_SPECIAL_SMS_INDICATION, data: vec![0x01, 0] }
+    ] };
+    assert_eq!(header.message_waiting(), vec![
+        MessageWaiting { kind: WaitingMessageKind::Voicemail, active: true, count: Some(3), store: true },
+        MessageWaiting { kind: WaitingMessageKind::Fax, active: false, count: Some(0), store: false }
+    ]);
+}
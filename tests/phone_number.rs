@@ -0,0 +1,67 @@
+use async_modem::{error::Error, pdu::{Address, TypeOfNumber}, phone_number::PhoneNumber};
+
+#[test]
+fn parses_written_formats() {
+    let number = PhoneNumber::parse(" +1 (315) 555-0123 ").unwrap();
+    assert_eq!(number.digits(), "13155550123");
+    assert!(number.is_international());
+    assert_eq!(number.type_of_address(), 145);
+    assert_eq!(number.to_string(), "+13155550123");
+
+    let number: PhoneNumber = "0171/123.4567".parse().unwrap();
+    assert_eq!(number.to_string(), "01711234567");
+    assert_eq!(number.type_of_address(), 129);
+
+    let address = Address::from(&PhoneNumber::parse("+447700900123").unwrap());
+    assert_eq!(address.type_of_number, TypeOfNumber::International);
+    assert_eq!(address.type_of_address(), 145);
+}
+
+#[test]
+fn rejects_invalid_numbers() {
+    for number in ["", "+", "ACME", "+1 315 555 O123", "12+34", "*100#", "+1234567890123456", "123456789012345678901"] {
+        assert!(matches!(PhoneNumber::parse(number), Err(Error::InvalidArgument(_))), "{} should be rejected", number);
+    }
+}
+
+#[test]
+fn adds_country_codes() {
+    let uk = PhoneNumber::parse("07700 900123").unwrap().with_country_code("44").unwrap();
+    assert_eq!(uk.to_string(), "+447700900123");
+
+    // A national number that happens to start with the country code's digits still gets the code
+    let italy = PhoneNumber::parse("3931234567").unwrap().with_country_code("39").unwrap();
+    assert_eq!(italy.to_string(), "+393931234567");
+
+    let us = PhoneNumber::parse_without_plus("13155550123").unwrap();
+    assert_eq!(us.to_string(), "+13155550123");
+    assert!(us.is_international());
+
+    let international = PhoneNumber::parse("+13155550123").unwrap();
+    assert_eq!(international.with_country_code("44").unwrap(), international);
+}
+
+#[test]
+fn matches_the_same_contact() {
+    let international = PhoneNumber::parse("+13155550123").unwrap();
+
+    for number in ["+1 315 555 0123", "13155550123", "3155550123"] {
+        assert!(international.matches(&PhoneNumber::parse(number).unwrap()), "{} should match", number);
+    }
+    assert!(PhoneNumber::parse("+447700900123").unwrap().matches(&PhoneNumber::parse("07700900123").unwrap()));
+
+    assert!(!international.matches(&PhoneNumber::parse("+13155550124").unwrap()));
+    assert!(!international.matches(&PhoneNumber::parse("0").unwrap()));
+    assert!(!PhoneNumber::parse("3155550123").unwrap().matches(&PhoneNumber::parse("13155550123").unwrap()));
+}
+
+#[test]
+fn does_not_match_the_end_of_a_number() {
+    let international = PhoneNumber::parse("+13155550123").unwrap();
+
+    // Trailing digits, a local number without its area code and a short code
+    for number in ["23", "0123", "50123", "5550123", "05550123"] {
+        assert!(!international.matches(&PhoneNumber::parse(number).unwrap()), "{} shouldn't match", number);
+        assert!(!PhoneNumber::parse(number).unwrap().matches(&international), "{} shouldn't match", number);
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::{FixedOffset, TimeZone};
+use async_modem::{constants::{SmsMessage, SmsStatus}, pdu::Alphabet, utils::utf16_to_hex};
+
+#[test]
+fn parses_positive_timezone_and_alpha() {
+    let raw = format!(
+        "\r\n+CMGR: \"REC READ\",\"{}\",\"{}\",\"25/06/01,12:30:45+08\"\r\n{}\r\n\r\nOK\r\n",
+        utf16_to_hex("+4915112345678"), utf16_to_hex("Alice"), utf16_to_hex("Hallo")
+    );
+    let message = SmsMessage::from_cmgr(raw, 4).unwrap();
+
+    assert_eq!(message.status(), SmsStatus::ReceivedRead);
+    assert_eq!(message.alpha().as_deref(), Some("Alice"));
+    assert_eq!(message.timestamp(), Some(FixedOffset::east_opt(2 * 3600).unwrap().with_ymd_and_hms(2025, 6, 1, 12, 30, 45).unwrap()));
+    assert_eq!(message.encoding(), None);
+    assert!(!message.is_outgoing());
+}
+
+#[test]
+fn parses_detailed_header() {
+    // AT+CSDH=1 adds <tooa>,<fo>,<pid>,<dcs>,<sca>,<tosca>,<length>
+    let raw = format!(
+        "\r\n+CMGR: \"REC UNREAD\",\"{}\",,\"25/06/01,12:30:45-16\",145,4,0,24,\"{}\",145,2\r\n{}\r\n\r\nOK\r\n",
+        utf16_to_hex("+13155550123"), utf16_to_hex("+15555550100"), utf16_to_hex("Hi")
+    );
+    let message = SmsMessage::from_cmgr(raw, 0).unwrap();
+
+    assert_eq!(message.alpha(), None);
+    assert_eq!(message.encoding(), Some(Alphabet::Ucs2));
+    assert_eq!(message.class(), Some(0));
+    assert_eq!(message.service_centre().as_deref(), Some("+15555550100"));
+}
+
+#[test]
+fn parses_outgoing_messages() {
+    let raw = format!(
+        "\r\n+CMGR: \"STO UNSENT\",\"{}\",\"\",129,17,0,0,167,\"{}\",145,5\r\n{}\r\n\r\nOK\r\n",
+        utf16_to_hex("5551234"), utf16_to_hex("+15555550100"), utf16_to_hex("Draft")
+    );
+    let message = SmsMessage::from_cmgr(raw, 2).unwrap();
+
+    assert!(message.is_outgoing());
+    assert_eq!(message.timestamp(), None);
+    assert_eq!(message.address(), "5551234");
+    assert_eq!(message.encoding(), Some(Alphabet::Gsm7));
+    assert_eq!(message.service_centre().as_deref(), Some("+15555550100"));
+}
+
+#[test]
+fn lists_every_kind_of_message() {
+    let raw = format!(
+        "\r\n+CMGL: 0,\"REC READ\",\"{}\",\"\",\"25/06/01,12:30:45+00\"\r\n{}\r\n+CMGL: 1,\"STO SENT\",\"{}\",\"\",\r\n{}\r\n+CMGL: 3,\"REC UNREAD\",\"{}\",\"\",\"25/06/01,12:31:00-16\",145,2\r\n{}\r\n\r\nOK\r\n",
+        utf16_to_hex("+13155550123"), utf16_to_hex("First"),
+        utf16_to_hex("+13155550199"), utf16_to_hex("Second"),
+        utf16_to_hex("+13155550123"), utf16_to_hex("OK")
+    );
+    let messages = SmsMessage::from_cmgl(raw).unwrap();
+
+    let summary: Vec<(u32, SmsStatus, String)> = messages.iter().map(|m| (m.memory_index(), m.status(), m.content())).collect();
+    assert_eq!(summary, vec![
+        (0, SmsStatus::ReceivedRead, String::from("First")),
+        (1, SmsStatus::StoredSent, String::from("Second")),
+        (3, SmsStatus::ReceivedUnread, String::from("OK"))
+    ]);
+    assert_eq!(messages[1].timestamp(), None);
+}
@@ -0,0 +1,28 @@
+use async_modem::storage::{PreferredStorage, SmsStorage, StorageUsage};
+
+#[test]
+fn parses_cpms_query() {
+    let storage = PreferredStorage::from_cpms_query(String::from("\r\n+CPMS: \"SM\",5,30,\"ME\",0,100,\"SM\",5,30\r\n\r\nOK\r\n")).unwrap();
+
+    assert_eq!(storage.read, StorageUsage { storage: SmsStorage::Sim, used: 5, total: 30 });
+    assert_eq!(storage.write, StorageUsage { storage: SmsStorage::Phone, used: 0, total: 100 });
+    assert!(!storage.receive.is_full());
+}
+
+#[test]
+fn parses_ucs2_storage_names() {
+    let storage = PreferredStorage::from_cpms_query(String::from("\r\n+CPMS: \"0053004D\",30,30,\"0053004D\",30,30,\"004D0054\",30,30\r\n\r\nOK\r\n")).unwrap();
+
+    assert_eq!(storage.read.storage, SmsStorage::Sim);
+    assert_eq!(storage.receive.storage, SmsStorage::Any);
+    assert!(storage.receive.is_full());
+    assert!(SmsStorage::try_from_name("XX").is_err());
+}
+
+#[test]
+fn parses_cpms_set() {
+    let storage = PreferredStorage::from_cpms_set(String::from("\r\n+CPMS: 1,30,2,100,1,30\r\n\r\nOK\r\n"), [SmsStorage::Sim, SmsStorage::Phone, SmsStorage::Sim]).unwrap();
+
+    assert_eq!(storage.write, StorageUsage { storage: SmsStorage::Phone, used: 2, total: 100 });
+    assert!(PreferredStorage::from_cpms_set(String::from("\r\nOK\r\n"), [SmsStorage::Sim; 3]).is_err());
+}
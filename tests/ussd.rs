@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use async_modem::{error::Error, events::ModemEvent, gsm7, simulator::SimulatedModem, ussd::{UssdResponse, UssdStatus}, utils::{bytes_to_hex, utf16_to_hex}};
+use common::start;
+
+#[test]
+fn decodes_cusd_text() {
+    let ucs2 = UssdResponse::from_cusd(0, Some(&utf16_to_hex("Balance: €5.00")), Some(72)).unwrap();
+    assert_eq!(ucs2.status, UssdStatus::Done);
+    assert_eq!(ucs2.message.as_deref(), Some("Balance: €5.00"));
+
+    // Some modems pass GSM 7-bit text through packed, even with AT+CSCS="UCS2"
+    let packed = bytes_to_hex(&gsm7::pack_septets(&gsm7::encode("Balance: 5.00 EUR").unwrap(), 0));
+    let gsm = UssdResponse::from_cusd(1, Some(&packed), Some(15)).unwrap();
+    assert_eq!(gsm.message.as_deref(), Some("Balance: 5.00 EUR"));
+    assert!(gsm.expects_reply());
+
+    let status_only = UssdResponse::from_cusd(2, None, None).unwrap();
+    assert_eq!(status_only.status, UssdStatus::Terminated);
+    assert_eq!(status_only.message, None);
+
+    assert_eq!(UssdResponse::from_cusd(9, None, None), None);
+}
+
+#[tokio::test]
+async fn balance_check() {
+    let (modem, sim) = start(SimulatedModem::new().with_ussd("*100#", 0, "Your balance is 12.50")).await;
+
+    let session = modem.start_ussd("*100#").await.unwrap();
+    assert_eq!(session.response().message.as_deref(), Some("Your balance is 12.50"));
+    assert!(!session.is_open());
+    session.cancel().await.unwrap();
+
+    let commands = sim.received_commands();
+    assert!(commands.contains(&format!("AT+CUSD=1,\"{}\",15", utf16_to_hex("*100#"))));
+    assert!(!commands.contains(&String::from("AT+CUSD=2")));
+}
+
+#[tokio::test]
+async fn menu_session() {
+    let simulator = SimulatedModem::new()
+        .with_ussd("*123#", 1, "1. Balance\n2. Bundles")
+        .with_ussd("2", 1, "1. 1GB\n2. 5GB")
+        .with_ussd("1", 0, "1GB bundle added");
+    let (modem, sim) = start(simulator).await;
+
+    let mut session = modem.start_ussd("*123#").await.unwrap();
+    assert!(session.is_open());
+    assert_eq!(session.response().message.as_deref(), Some("1. Balance\n2. Bundles"));
+
+    assert_eq!(session.reply("2").await.unwrap().message.as_deref(), Some("1. 1GB\n2. 5GB"));
+    let last = session.reply("1").await.unwrap();
+    assert_eq!(last.status, UssdStatus::Done);
+    assert_eq!(last.message.as_deref(), Some("1GB bundle added"));
+
+    assert!(matches!(session.reply("1").await, Err(Error::InvalidArgument(_))));
+
+    let cancelled = modem.start_ussd("*123#").await.unwrap();
+    cancelled.cancel().await.unwrap();
+    assert_eq!(sim.received_commands().last().map(String::as_str), Some("AT+CUSD=2"));
+}
+
+#[tokio::test]
+async fn reply_skips_ussd_the_network_sent_before_it() {
+    let simulator = SimulatedModem::new()
+        .with_ussd("*123#", 1, "1. Balance\n2. Bundles")
+        .with_ussd("1", 0, "Balance: 12.50");
+    let (modem, sim) = start(simulator).await;
+    let mut events = modem.subscribe();
+
+    let mut session = modem.start_ussd("*123#").await.unwrap();
+    sim.ussd(0, "Unrelated notice");
+    // Waits for the notice to be queued for the session too
+    loop {
+        if let ModemEvent::Ussd(response) = tokio::time::timeout(Duration::from_secs(1), events.recv()).await.unwrap().unwrap()
+            && response.message.as_deref() == Some("Unrelated notice") {
+            break
+        }
+    }
+
+    assert_eq!(session.reply("1").await.unwrap().message.as_deref(), Some("Balance: 12.50"));
+}
+
+#[tokio::test]
+async fn unanswered_request_times_out_and_cancels() {
+    let (modem, sim) = start(SimulatedModem::new()).await;
+
+    let error = modem.start_ussd_with_timeout("*999#", Duration::from_millis(100)).await.err().unwrap();
+    assert!(matches!(error, Error::Timeout { ref command, .. } if command.starts_with("AT+CUSD=1,")));
+    assert_eq!(sim.received_commands().last().map(String::as_str), Some("AT+CUSD=2"));
+
+    assert!(matches!(modem.start_ussd("").await, Err(Error::InvalidArgument(_))));
+}
+
+#[tokio::test]
+async fn network_initiated_ussd() {
+    let (modem, sim) = start(SimulatedModem::new().with_ussd("1", 0, "Subscribed")).await;
+    let mut events = modem.subscribe();
+
+    sim.ussd(1, "Reply 1 to subscribe");
+    let response = match tokio::time::timeout(Duration::from_secs(1), events.recv()).await.unwrap().unwrap() {
+        ModemEvent::Ussd(response) => response,
+        event => panic!("Unexpected event {:?}", event)
+    };
+    assert_eq!(response.status, UssdStatus::ActionRequired);
+    assert_eq!(response.message.as_deref(), Some("Reply 1 to subscribe"));
+
+    let mut session = modem.ussd_session(response);
+    assert_eq!(session.reply("1").await.unwrap().message.as_deref(), Some("Subscribed"));
+}